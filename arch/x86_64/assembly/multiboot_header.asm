@@ -17,6 +17,17 @@ header_start:
 
     ; insert optional multiboot tags here
 
+    ; framebuffer tag, the console falls back to the VGA text mode without it
+    dw 5    ; type
+    dw 1    ; flags (optional)
+    dd 20   ; size
+    dd 1024 ; width
+    dd 768  ; height
+    dd 32   ; depth
+
+    ; tags are 8 bytes aligned
+    align 8
+
     ; required end tag
     dw 0    ; type
     dw 0    ; flags
@@ -0,0 +1,112 @@
+; Application Processor (AP) startup trampoline
+;
+; This is assembled as a flat binary and copied by the BSP to 0x8000, which is where the APs start
+; executing, in real mode, after receiving the Startup IPI. Since all the APs are started at once,
+; only the one holding the trampoline lock can use the variables below. It tells the BSP that it
+; arrived, waits until the BSP fills the variables for it, switches directly to long mode using the
+; kernel page table and calls `kstart_ap`. The BSP releases the lock for the next AP.
+
+ORG 0x8000
+SECTION .text
+USE16
+
+trampoline:
+    jmp short startup_ap
+    times 8 - ($ - trampoline) nop
+    .ready: dq 0
+    .lock: dq 0
+    .go: dq 0
+    .cpu_id: dq 0
+    .page_table: dq 0
+    .stack_end: dq 0
+    .ist_stack_end: dq 0
+    .code: dq 0
+
+startup_ap:
+    cli
+
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; take the trampoline lock
+.acquire:
+    lock bts word [trampoline.lock], 0
+    jnc .acquired
+.spin:
+    pause
+    test word [trampoline.lock], 1
+    jnz .spin
+    jmp .acquire
+.acquired:
+
+    ; tell the BSP we are here and wait for our variables
+    mov byte [trampoline.ready], 1
+.wait_go:
+    pause
+    cmp byte [trampoline.go], 0
+    je .wait_go
+
+    ; load the kernel page table
+    mov edi, [trampoline.page_table]
+    mov cr3, edi
+
+    ; enable OSXMMEXCPT, OSFXSR, global pages and PAE
+    mov eax, cr4
+    or eax, 1 << 10 | 1 << 9 | 1 << 7 | 1 << 5
+    mov cr4, eax
+
+    ; load the long mode GDT
+    lgdt [gdtr]
+
+    ; enable long mode and the no-execute bit
+    mov ecx, 0xC0000080
+    rdmsr
+    or eax, 1 << 11 | 1 << 8
+    wrmsr
+
+    ; enable paging, write protection, coprocessor monitoring and protected mode at once
+    mov eax, cr0
+    and eax, ~(1 << 2)
+    or eax, 1 << 31 | 1 << 16 | 1 << 1 | 1
+    mov cr0, eax
+
+    ; far jump to load the 64-bit code segment
+    jmp gdt.code:long_mode_ap
+
+USE64
+long_mode_ap:
+    mov ax, gdt.data
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+
+    mov rdi, [trampoline.cpu_id]
+    mov rsi, [trampoline.ist_stack_end]
+    mov rsp, [trampoline.stack_end]
+    mov rax, [trampoline.code]
+
+    ; from here the variables aren't needed anymore
+    mov qword [trampoline.ready], 2
+
+    call rax
+.halt:
+    cli
+    hlt
+    jmp .halt
+
+; long mode GDT, only used until `kstart_ap` loads the per-CPU one
+gdt:
+    dq 0
+.code: equ $ - gdt
+    dq (1 << 43) | (1 << 44) | (1 << 47) | (1 << 53)
+.data: equ $ - gdt
+    dq (1 << 41) | (1 << 44) | (1 << 47)
+.end:
+
+gdtr:
+    dw gdt.end - gdt - 1
+    dq gdt
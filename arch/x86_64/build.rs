@@ -0,0 +1,23 @@
+//! Assemble the AP startup trampoline as a flat binary, that is then included by the `smp` module.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("trampoline");
+
+    let status = Command::new("nasm")
+        .arg("-f").arg("bin")
+        .arg("-o").arg(&dest_path)
+        .arg("assembly/trampoline.asm")
+        .status()
+        .expect("failed to run nasm");
+
+    if !status.success() {
+        panic!("failed to assemble the AP trampoline");
+    }
+
+    println!("cargo:rerun-if-changed=assembly/trampoline.asm");
+}
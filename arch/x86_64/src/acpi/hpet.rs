@@ -0,0 +1,52 @@
+//! High Precision Event Timer (HPET) Description Table
+//!
+//! Describes the HPET event timer block: the physical address of its registers, the number of
+//! comparators of the block and the minimum clock tick in periodic mode.
+//!
+//! References:
+//! - [IA-PC HPET Specification](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/software-developers-hpet-spec-1-0a.pdf), 3.2.4
+
+use core::{mem, ptr};
+
+use super::sdt::Sdt;
+
+/// ACPI Generic Address Structure
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct GenericAddress {
+    /// Address space: 0 for system memory, 1 for system I/O
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    reserved: u8,
+    pub address: u64
+}
+
+/// Generic address space: system memory
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct Hpet {
+    pub header: Sdt,
+    pub hardware_revision: u8,
+    /// Bits 0-4: number of comparators - 1, bit 5: 64-bit counter, bit 7: legacy replacement
+    pub comparator_info: u8,
+    pub pci_vendor_id: u16,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    /// Minimum clock tick on periodic mode, in counter ticks
+    pub minimum_tick: u16,
+    pub page_protection: u8
+}
+
+impl Hpet {
+    /// Cast the SDT to a HPET instance
+    pub fn new(sdt: &'static Sdt) -> Option<Self> {
+        if &sdt.signature == b"HPET" && sdt.length as usize >= mem::size_of::<Self>() {
+            Some(unsafe { ptr::read((sdt as *const Sdt) as *const Self) })
+        } else {
+            None
+        }
+    }
+}
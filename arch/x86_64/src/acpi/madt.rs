@@ -0,0 +1,341 @@
+//! Multiple APIC Description Table (MADT)
+//!
+//! The MADT describes all the interrupt controllers of the system: the Local APIC of each
+//! processor, the I/O APICs, how the legacy ISA IRQs are mapped to global system interrupts (GSI)
+//! and which interrupt pins are connected to NMIs.
+//!
+//! The table is a header followed by a list of variable length entries, each one starting with its
+//! type and length.
+
+use collections::Vec;
+use core::{mem, slice};
+
+use super::sdt::{Sdt, read_u16, read_u32, read_u64};
+
+/// Entry type: Processor Local APIC
+const ENTRY_LOCAL_APIC: u8 = 0;
+/// Entry type: I/O APIC
+const ENTRY_IO_APIC: u8 = 1;
+/// Entry type: Interrupt Source Override
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+/// Entry type: Non-Maskable Interrupt (NMI) Source
+const ENTRY_NMI_SOURCE: u8 = 3;
+/// Entry type: Local APIC NMI
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+/// Entry type: Local APIC Address Override
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// The system also has a PC-AT-compatible dual-8259 setup, that must be disabled
+pub const FLAG_PCAT_COMPAT: u32 = 1;
+
+/// The processor is ready for use
+const LOCAL_APIC_ENABLED: u32 = 1;
+
+/// Processor Local APIC
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MadtLocalApic {
+    /// ACPI processor id
+    pub processor_id: u8,
+    /// Local APIC id
+    pub apic_id: u8,
+    /// Local APIC flags
+    pub flags: u32
+}
+
+impl MadtLocalApic {
+    /// Check if the processor can be used.
+    pub fn is_enabled(&self) -> bool {
+        self.flags & LOCAL_APIC_ENABLED == LOCAL_APIC_ENABLED
+    }
+}
+
+/// I/O APIC
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MadtIoApic {
+    /// I/O APIC id
+    pub id: u8,
+    /// Physical address of the I/O APIC registers
+    pub address: u32,
+    /// First global system interrupt handled by this I/O APIC
+    pub gsi_base: u32
+}
+
+/// Interrupt Source Override
+///
+/// Describes how an ISA IRQ is connected to a global system interrupt, when it isn't identity
+/// mapped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MadtInterruptSourceOverride {
+    /// Bus, always 0 (ISA)
+    pub bus: u8,
+    /// ISA IRQ
+    pub source: u8,
+    /// Global system interrupt that the IRQ signals
+    pub gsi: u32,
+    /// MPS INTI flags, polarity (bits 0-1) and trigger mode (bits 2-3)
+    pub flags: u16
+}
+
+/// Non-Maskable Interrupt Source
+///
+/// A global system interrupt that must be configured as NMI.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MadtNmiSource {
+    /// MPS INTI flags
+    pub flags: u16,
+    /// Global system interrupt
+    pub gsi: u32
+}
+
+/// Local APIC NMI
+///
+/// A Local APIC interrupt input (LINT) that is connected to NMI.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MadtLocalApicNmi {
+    /// ACPI processor id, 0xFF means all the processors
+    pub processor_id: u8,
+    /// MPS INTI flags
+    pub flags: u16,
+    /// Local APIC LINT pin (0 or 1)
+    pub lint: u8
+}
+
+/// MADT
+#[derive(Clone, Debug)]
+pub struct Madt {
+    /// Physical address of the Local APICs
+    pub local_apic_address: u64,
+    /// Multiple APIC flags
+    pub flags: u32,
+    /// Local APIC of each processor
+    pub local_apics: Vec<MadtLocalApic>,
+    /// I/O APICs
+    pub io_apics: Vec<MadtIoApic>,
+    /// Interrupt source overrides
+    pub interrupt_source_overrides: Vec<MadtInterruptSourceOverride>,
+    /// NMI sources
+    pub nmi_sources: Vec<MadtNmiSource>,
+    /// Local APIC NMIs
+    pub local_apic_nmis: Vec<MadtLocalApicNmi>
+}
+
+impl Madt {
+    /// Parse the SDT as a MADT
+    pub fn new(sdt: &'static Sdt) -> Option<Madt> {
+        if &sdt.signature == b"APIC" {
+            let data = unsafe { slice::from_raw_parts(sdt as *const Sdt as *const u8, sdt.length as usize) };
+            Madt::parse(data)
+        } else {
+            None
+        }
+    }
+
+    /// Parse a MADT from the bytes of the whole table, header included.
+    ///
+    /// ## Returns
+    /// `None` if it isn't a MADT, the checksum doesn't match or an entry is out of bounds. Entries
+    /// of unknown types are skipped.
+    pub fn parse(data: &[u8]) -> Option<Madt> {
+        let header_size = mem::size_of::<Sdt>();
+
+        // the header is followed by the Local APIC address and the flags
+        if data.len() < header_size + 8 || &data[0..4] != b"APIC" {
+            return None;
+        }
+
+        let length = read_u32(data, 4) as usize;
+        if length < header_size + 8 || length > data.len() {
+            return None;
+        }
+        let data = &data[..length];
+
+        // all the bytes of the table must sum to zero
+        if checksum(data) != 0 {
+            return None;
+        }
+
+        let mut madt = Madt {
+            local_apic_address: read_u32(data, header_size) as u64,
+            flags: read_u32(data, header_size + 4),
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            interrupt_source_overrides: Vec::new(),
+            nmi_sources: Vec::new(),
+            local_apic_nmis: Vec::new()
+        };
+
+        let mut offset = header_size + 8;
+        while offset < data.len() {
+            // each entry starts with its type and length
+            if offset + 2 > data.len() {
+                return None;
+            }
+            let entry_type = data[offset];
+            let entry_len = data[offset + 1] as usize;
+            if entry_len < 2 || offset + entry_len > data.len() {
+                return None;
+            }
+            let entry = &data[offset..offset + entry_len];
+
+            match entry_type {
+                ENTRY_LOCAL_APIC if entry_len >= 8 => {
+                    madt.local_apics.push(MadtLocalApic {
+                        processor_id: entry[2],
+                        apic_id: entry[3],
+                        flags: read_u32(entry, 4)
+                    });
+                },
+                ENTRY_IO_APIC if entry_len >= 12 => {
+                    madt.io_apics.push(MadtIoApic {
+                        id: entry[2],
+                        address: read_u32(entry, 4),
+                        gsi_base: read_u32(entry, 8)
+                    });
+                },
+                ENTRY_INTERRUPT_SOURCE_OVERRIDE if entry_len >= 10 => {
+                    madt.interrupt_source_overrides.push(MadtInterruptSourceOverride {
+                        bus: entry[2],
+                        source: entry[3],
+                        gsi: read_u32(entry, 4),
+                        flags: read_u16(entry, 8)
+                    });
+                },
+                ENTRY_NMI_SOURCE if entry_len >= 8 => {
+                    madt.nmi_sources.push(MadtNmiSource {
+                        flags: read_u16(entry, 2),
+                        gsi: read_u32(entry, 4)
+                    });
+                },
+                ENTRY_LOCAL_APIC_NMI if entry_len >= 6 => {
+                    madt.local_apic_nmis.push(MadtLocalApicNmi {
+                        processor_id: entry[2],
+                        flags: read_u16(entry, 3),
+                        lint: entry[5]
+                    });
+                },
+                ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE if entry_len >= 12 => {
+                    madt.local_apic_address = read_u64(entry, 4);
+                },
+                ENTRY_LOCAL_APIC | ENTRY_IO_APIC | ENTRY_INTERRUPT_SOURCE_OVERRIDE | ENTRY_NMI_SOURCE |
+                ENTRY_LOCAL_APIC_NMI | ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => return None,
+                _ => ()
+            }
+
+            offset += entry_len;
+        }
+
+        Some(madt)
+    }
+
+    /// Get the processors that can be used.
+    pub fn enabled_local_apics(&self) -> Vec<MadtLocalApic> {
+        self.local_apics.iter().filter(|local_apic| local_apic.is_enabled()).cloned().collect()
+    }
+}
+
+/// Sum all the bytes of a table.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MADT as generated by QEMU (pc machine) with `-smp 4`.
+    static QEMU_MADT: [u8; 144] = [
+        0x41, 0x50, 0x49, 0x43, 0x90, 0x00, 0x00, 0x00, 0x01, 0xae, 0x42, 0x4f, 0x43, 0x48, 0x53, 0x20,
+        0x42, 0x58, 0x50, 0x43, 0x41, 0x50, 0x49, 0x43, 0x01, 0x00, 0x00, 0x00, 0x42, 0x58, 0x50, 0x43,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe0, 0xfe, 0x01, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x08, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x08, 0x02, 0x02,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x08, 0x03, 0x03, 0x01, 0x00, 0x00, 0x00, 0x01, 0x0c, 0x00, 0x00,
+        0x00, 0x00, 0xc0, 0xfe, 0x00, 0x00, 0x00, 0x00, 0x02, 0x0a, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x02, 0x0a, 0x00, 0x05, 0x05, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x02, 0x0a, 0x00, 0x09,
+        0x09, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x02, 0x0a, 0x00, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x00,
+        0x02, 0x0a, 0x00, 0x0b, 0x0b, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x04, 0x06, 0xff, 0x00, 0x00, 0x01,
+    ];
+
+    #[test]
+    fn parse_qemu_table() {
+        let madt = Madt::parse(&QEMU_MADT).expect("valid MADT");
+
+        assert_eq!(madt.local_apic_address, 0xFEE0_0000);
+        assert_eq!(madt.flags, FLAG_PCAT_COMPAT);
+
+        assert_eq!(madt.local_apics.len(), 4);
+        for (index, local_apic) in madt.local_apics.iter().enumerate() {
+            assert_eq!(local_apic.processor_id as usize, index);
+            assert_eq!(local_apic.apic_id as usize, index);
+            assert!(local_apic.is_enabled());
+        }
+        assert_eq!(madt.enabled_local_apics().len(), 4);
+
+        assert_eq!(madt.io_apics.len(), 1);
+        assert_eq!(madt.io_apics[0], MadtIoApic { id: 0, address: 0xFEC0_0000, gsi_base: 0 });
+
+        assert_eq!(madt.interrupt_source_overrides.len(), 5);
+        assert_eq!(madt.interrupt_source_overrides[0],
+                   MadtInterruptSourceOverride { bus: 0, source: 0, gsi: 2, flags: 0 });
+        assert_eq!(madt.interrupt_source_overrides[4],
+                   MadtInterruptSourceOverride { bus: 0, source: 11, gsi: 11, flags: 0xd });
+
+        assert!(madt.nmi_sources.is_empty());
+        assert_eq!(madt.local_apic_nmis.len(), 1);
+        assert_eq!(madt.local_apic_nmis[0], MadtLocalApicNmi { processor_id: 0xff, flags: 0, lint: 1 });
+    }
+
+    #[test]
+    fn reject_bad_checksum() {
+        let mut table = QEMU_MADT;
+        table[9] ^= 1;
+        assert!(Madt::parse(&table).is_none());
+    }
+
+    #[test]
+    fn reject_other_signature() {
+        let mut table = QEMU_MADT;
+        table[0] = b'X';
+        table[9] = table[9].wrapping_add(b'A').wrapping_sub(b'X');
+        assert!(Madt::parse(&table).is_none());
+    }
+
+    /// Shrink the table to `length` bytes, with a valid checksum.
+    fn truncate(length: u8) -> [u8; 144] {
+        let mut table = QEMU_MADT;
+        table[4] = length;
+        table[9] = 0;
+        table[9] = 0u8.wrapping_sub(checksum(&table[..length as usize]));
+        assert_eq!(checksum(&table[..length as usize]), 0);
+        table
+    }
+
+    #[test]
+    fn reject_truncated_entry() {
+        // without its last entry the table is still valid
+        let madt = Madt::parse(&truncate(0x8a)).expect("valid MADT");
+        assert!(madt.local_apic_nmis.is_empty());
+
+        // the last entry goes past the end of the table
+        assert!(Madt::parse(&truncate(0x8e)).is_none());
+    }
+
+    #[test]
+    fn skip_disabled_and_unknown_entries() {
+        let mut table = QEMU_MADT;
+
+        // disable the last processor
+        table[72] = 0x00;
+        table[9] = table[9].wrapping_add(1);
+
+        // turn the NMI entry into an unknown type
+        table[138] = 0x7f;
+        table[9] = table[9].wrapping_sub(0x7f - 0x04);
+
+        let madt = Madt::parse(&table).expect("valid MADT");
+        assert_eq!(madt.local_apics.len(), 4);
+        let enabled = madt.enabled_local_apics();
+        assert_eq!(enabled.len(), 3);
+        assert!(enabled.iter().all(|local_apic| local_apic.apic_id != 3));
+        assert!(madt.local_apic_nmis.is_empty());
+    }
+}
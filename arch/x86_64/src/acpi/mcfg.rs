@@ -0,0 +1,119 @@
+//! PCI Express Memory-mapped Configuration Space Description Table (MCFG)
+//!
+//! Gives the base address of the Enhanced Configuration Access Mechanism (ECAM) area of each PCI
+//! segment group, where the configuration space of every function on its buses is mapped.
+//!
+//! The table is a header, 8 reserved bytes and a list of 16 bytes entries.
+//!
+//! References:
+//! - [OSDev PCI Express](http://wiki.osdev.org/PCI_Express)
+
+use collections::Vec;
+use core::{mem, slice};
+
+use super::sdt::{Sdt, read_u16, read_u32, read_u64};
+
+/// Size of each entry
+const ENTRY_SIZE: usize = 16;
+
+/// ECAM area of a PCI segment group
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct McfgEntry {
+    /// Physical address of the configuration space of the bus 0, even when `start_bus` isn't 0
+    pub base_address: u64,
+    /// PCI segment group number
+    pub segment: u16,
+    /// First bus decoded by this area
+    pub start_bus: u8,
+    /// Last bus decoded by this area
+    pub end_bus: u8
+}
+
+/// MCFG
+#[derive(Clone, Debug)]
+pub struct Mcfg {
+    /// ECAM area of each segment group
+    pub entries: Vec<McfgEntry>
+}
+
+impl Mcfg {
+    /// Parse the SDT as a MCFG
+    pub fn new(sdt: &'static Sdt) -> Option<Mcfg> {
+        if &sdt.signature == b"MCFG" {
+            let data = unsafe { slice::from_raw_parts(sdt as *const Sdt as *const u8, sdt.length as usize) };
+            Mcfg::parse(data)
+        } else {
+            None
+        }
+    }
+
+    /// Parse a MCFG from the bytes of the whole table, header included.
+    ///
+    /// ## Returns
+    /// `None` if it isn't a MCFG or the checksum doesn't match. Bytes after the last whole entry
+    /// are ignored.
+    pub fn parse(data: &[u8]) -> Option<Mcfg> {
+        let header_size = mem::size_of::<Sdt>();
+
+        // the header is followed by 8 reserved bytes
+        if data.len() < header_size + 8 || &data[0..4] != b"MCFG" {
+            return None;
+        }
+
+        let length = read_u32(data, 4) as usize;
+        if length < header_size + 8 || length > data.len() {
+            return None;
+        }
+        let data = &data[..length];
+
+        // all the bytes of the table must sum to zero
+        if data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return None;
+        }
+
+        let entries = data[header_size + 8..].chunks(ENTRY_SIZE)
+            .filter(|entry| entry.len() == ENTRY_SIZE)
+            .map(|entry| McfgEntry {
+                base_address: read_u64(entry, 0),
+                segment: read_u16(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11]
+            })
+            .collect();
+
+        Some(Mcfg { entries: entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MCFG as generated by QEMU (q35 machine).
+    static QEMU_MCFG: [u8; 60] = [
+        0x4d, 0x43, 0x46, 0x47, 0x3c, 0x00, 0x00, 0x00, 0x01, 0xef, 0x42, 0x4f, 0x43, 0x48, 0x53, 0x20,
+        0x42, 0x58, 0x50, 0x43, 0x4d, 0x43, 0x46, 0x47, 0x01, 0x00, 0x00, 0x00, 0x42, 0x58, 0x50, 0x43,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xb0,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn parse_qemu_table() {
+        let mcfg = Mcfg::parse(&QEMU_MCFG).expect("valid MCFG");
+
+        assert_eq!(mcfg.entries.len(), 1);
+        assert_eq!(mcfg.entries[0], McfgEntry { base_address: 0xB000_0000, segment: 0, start_bus: 0, end_bus: 0xff });
+    }
+
+    #[test]
+    fn reject_bad_checksum() {
+        let mut table = QEMU_MCFG;
+        table[9] ^= 1;
+        assert!(Mcfg::parse(&table).is_none());
+    }
+
+    #[test]
+    fn reject_truncated_table() {
+        assert!(Mcfg::parse(&QEMU_MCFG[..40]).is_none());
+    }
+}
@@ -3,6 +3,7 @@
 //! References:
 //! - [ACPI 5](http://www.acpi.info/DOWNLOADS/ACPI_5_Errata%20A.pdf)
 
+use core::str;
 use spin::Mutex;
 
 use memory::{MemoryController, Frame};
@@ -11,6 +12,9 @@ use memory::paging::{VirtualAddress, PhysicalAddress};
 use memory::paging::entry;
 use self::dsdt::Dsdt;
 use self::fadt::Fadt;
+use self::hpet::Hpet;
+use self::madt::Madt;
+use self::mcfg::Mcfg;
 use self::rsdp::Rsdp;
 use self::rsdt::Rsdt;
 use self::sdt::Sdt;
@@ -18,6 +22,9 @@ use self::xsdt::Xsdt;
 
 mod dsdt;
 mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
 mod rsdp;
 mod rsdt;
 mod sdt;
@@ -65,16 +72,11 @@ fn get_sdt(sdt_address: usize, memory_controller: &mut MemoryController) -> &'st
 
 /// Parse a SDT
 fn parse_sdt(sdt: &'static Sdt, memory_controller: &mut MemoryController) {
-    print!("\t");
-
-    // first, we print out the signature
-    for &chr in sdt.signature.iter() {
-        print!("{}", chr as char);
-    }
+    let signature = str::from_utf8(&sdt.signature).unwrap_or("????");
 
     if let Some(fadt) = Fadt::new(sdt) {
         // Print the address
-        println!(": {:x}", fadt.dsdt);
+        kinfo!("{}: {:x}", signature, fadt.dsdt);
 
         // parse the DSDT
         let dsdt = get_sdt(fadt.dsdt as usize, memory_controller);
@@ -84,12 +86,31 @@ fn parse_sdt(sdt: &'static Sdt, memory_controller: &mut MemoryController) {
         ACPI_TABLE.lock().fadt = Some(fadt);
     } else if let Some(dsdt) = Dsdt::new(sdt) {
         // Print out the number of elements
-        println!(": {}", dsdt.data().len());
+        kinfo!("{}: {}", signature, dsdt.data().len());
 
         // Save the DSDT reference
         ACPI_TABLE.lock().dsdt = Some(dsdt);
+    } else if let Some(madt) = Madt::new(sdt) {
+        // Print out the number of processors and I/O APICs
+        kinfo!("{}: {} CPUs, {} I/O APICs", signature, madt.enabled_local_apics().len(), madt.io_apics.len());
+
+        // Save the MADT
+        ACPI_TABLE.lock().madt = Some(madt);
+    } else if let Some(hpet) = Hpet::new(sdt) {
+        // Print out the address of the registers
+        let address = hpet.base_address.address;
+        kinfo!("{}: {:x}", signature, address);
+
+        // Save the HPET table
+        ACPI_TABLE.lock().hpet = Some(hpet);
+    } else if let Some(mcfg) = Mcfg::new(sdt) {
+        // Print out the number of ECAM areas
+        kinfo!("{}: {} segment groups", signature, mcfg.entries.len());
+
+        // Save the MCFG
+        ACPI_TABLE.lock().mcfg = Some(mcfg);
     } else {
-        println!(": Unknown");
+        kdebug!("{}: unknown", signature);
     }
 }
 
@@ -118,11 +139,7 @@ pub fn init(memory_controller: &mut MemoryController) {
         // map the (R|X)SDT into virtual memory
         let rxsdt = get_sdt(rsdp.sdt_address(), memory_controller);
 
-        // print out the signature
-        for &chr in rxsdt.signature.iter() {
-            print!("{}", chr as char);
-        }
-        println!(":");
+        kinfo!("{}", str::from_utf8(&rxsdt.signature).unwrap_or("????"));
 
         // Check if is a RSDT or a XSDT table
         if let Some(rsdt) = Rsdt::new(rxsdt) {
@@ -138,10 +155,10 @@ pub fn init(memory_controller: &mut MemoryController) {
                 parse_sdt(sdt, memory_controller);
             }
         } else {
-            println!("Unknown RSDT or XSDT signature");
+            kerror!("unknown RSDT or XSDT signature");
         }
     } else {
-        println!("ACPI: no RSDP found");
+        kwarn!("no RSDP found");
     }
 
     // TODO Clean the allocated memory after looking for RSDP
@@ -150,11 +167,17 @@ pub fn init(memory_controller: &mut MemoryController) {
 /// ACPI manager structure
 pub struct Acpi {
     pub fadt: Option<Fadt>,
-    pub dsdt: Option<Dsdt>
+    pub dsdt: Option<Dsdt>,
+    pub madt: Option<Madt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Option<Mcfg>
 }
 
 /// Static ACPI instance
 pub static ACPI_TABLE: Mutex<Acpi> = Mutex::new(Acpi {
     fadt: None,
-    dsdt: None
+    dsdt: None,
+    madt: None,
+    hpet: None,
+    mcfg: None
 });
@@ -43,3 +43,18 @@ impl Sdt {
         }
     }
 }
+
+/// Read a little endian `u16` from `data` at `offset`.
+pub fn read_u16(data: &[u8], offset: usize) -> u16 {
+    data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
+
+/// Read a little endian `u32` from `data` at `offset`.
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    read_u16(data, offset) as u32 | (read_u16(data, offset + 2) as u32) << 16
+}
+
+/// Read a little endian `u64` from `data` at `offset`.
+pub fn read_u64(data: &[u8], offset: usize) -> u64 {
+    read_u32(data, offset) as u64 | (read_u32(data, offset + 4) as u64) << 32
+}
@@ -0,0 +1,229 @@
+//! # ANSI escape sequences
+//!
+//! Parser for the subset of the ANSI/VT100 escape sequences the consoles understand. It's fed one
+//! byte at a time and returns what the console must do, so the same parser drives any console.
+//!
+//! - `ESC [ n A`, `B`, `C`, `D`: move the cursor up, down, forward and back.
+//! - `ESC [ row ; column H` and `f`: move the cursor, counting from one.
+//! - `ESC [ n J`, `K`: clear the screen or the line, after the cursor, before it, or all of it.
+//! - `ESC [ ... m`: select the colors.
+//! - `ESC [ s`, `u` and `ESC 7`, `8`: save and restore the cursor.
+//!
+//! ## References
+//! - [ANSI escape code](https://en.wikipedia.org/wiki/ANSI_escape_code)
+//! - [VT100 User Guide](https://vt100.net/docs/vt100-ug/chapter3.html)
+
+use core::cmp;
+
+/// Escape character
+const ESC: u8 = 0x1b;
+
+/// Maximum number of parameters of a control sequence, the others are ignored
+pub const MAX_PARAMS: usize = 8;
+
+/// Part of the screen or of the line to clear
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Erase {
+    /// From the cursor to the end
+    ToEnd,
+    /// From the start to the cursor
+    ToStart,
+    /// Everything
+    All
+}
+
+impl Erase {
+    fn from_param(param: u16) -> Erase {
+        match param {
+            1 => Erase::ToStart,
+            2 | 3 => Erase::All,
+            _ => Erase::ToEnd
+        }
+    }
+}
+
+/// What the console must do
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Print a character at the cursor and move it forward
+    Print(u8),
+    /// Move the cursor to the start of the next line
+    NewLine,
+    /// Move the cursor to the start of the line
+    CarriageReturn,
+    /// Move the cursor one character back
+    Backspace,
+    /// Move the cursor to the next tab stop
+    Tab,
+    CursorUp(usize),
+    CursorDown(usize),
+    CursorForward(usize),
+    CursorBack(usize),
+    /// Move the cursor, counting from zero
+    CursorPosition { row: usize, column: usize },
+    EraseDisplay(Erase),
+    EraseLine(Erase),
+    /// Select Graphic Rendition, the parameters are on the first `count` entries
+    SelectGraphic { params: [u16; MAX_PARAMS], count: usize },
+    SaveCursor,
+    RestoreCursor
+}
+
+/// Position on an escape sequence
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    /// Not on an escape sequence
+    Ground,
+    /// After `ESC`
+    Escape,
+    /// After `ESC [`
+    Csi
+}
+
+/// Escape sequences parser
+#[derive(Copy, Clone)]
+pub struct Parser {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    /// Number of parameters started
+    count: usize,
+    /// The sequence has a private mode marker, like `ESC [ ? 25 h`, it's ignored
+    private: bool
+}
+
+impl Parser {
+    /// Create a new parser, out of any escape sequence
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            count: 0,
+            private: false
+        }
+    }
+
+    /// Feed the next byte.
+    ///
+    /// ## Returns
+    /// The action to do, `None` when the byte is part of an unfinished escape sequence or isn't
+    /// supported.
+    pub fn advance(&mut self, byte: u8) -> Option<Action> {
+        match self.state {
+            State::Ground => self.ground(byte),
+            State::Escape => {
+                self.state = State::Ground;
+                match byte {
+                    b'[' => {
+                        self.state = State::Csi;
+                        self.params = [0; MAX_PARAMS];
+                        self.count = 0;
+                        self.private = false;
+                        None
+                    },
+                    b'7' => Some(Action::SaveCursor),
+                    b'8' => Some(Action::RestoreCursor),
+                    _ => None
+                }
+            },
+            State::Csi => self.csi(byte)
+        }
+    }
+
+    /// Handle a byte out of any escape sequence.
+    fn ground(&mut self, byte: u8) -> Option<Action> {
+        match byte {
+            ESC => {
+                self.state = State::Escape;
+                None
+            },
+            b'\n' => Some(Action::NewLine),
+            b'\r' => Some(Action::CarriageReturn),
+            b'\t' => Some(Action::Tab),
+            8 | 0x7f => Some(Action::Backspace),
+            0...0x1f => None,
+            _ => Some(Action::Print(byte))
+        }
+    }
+
+    /// Handle a byte of a control sequence.
+    fn csi(&mut self, byte: u8) -> Option<Action> {
+        match byte {
+            b'0'...b'9' => {
+                if self.count == 0 {
+                    self.count = 1;
+                }
+                if self.count <= MAX_PARAMS {
+                    let param = &mut self.params[self.count - 1];
+                    *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                }
+                None
+            },
+            b';' => {
+                // an empty first parameter still counts
+                self.count = if self.count == 0 { 2 } else { self.count + 1 };
+                None
+            },
+            b'?' => {
+                self.private = true;
+                None
+            },
+            // intermediate bytes, none of the supported sequences has them
+            0x20...0x2f | b'<'...b'>' => None,
+            0x40...0x7e => {
+                self.state = State::Ground;
+                if self.private {
+                    None
+                } else {
+                    self.dispatch(byte)
+                }
+            },
+            // a control character aborts the sequence
+            _ => {
+                self.state = State::Ground;
+                None
+            }
+        }
+    }
+
+    /// Get a parameter, or `default` when it's missing or zero.
+    fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params.get(index) {
+            Some(&param) if index < self.count && param != 0 => param,
+            _ => default
+        }
+    }
+
+    /// Get the action of a finished control sequence.
+    fn dispatch(&self, command: u8) -> Option<Action> {
+        let amount = self.param(0, 1) as usize;
+
+        match command {
+            b'A' => Some(Action::CursorUp(amount)),
+            b'B' => Some(Action::CursorDown(amount)),
+            b'C' => Some(Action::CursorForward(amount)),
+            b'D' => Some(Action::CursorBack(amount)),
+            b'H' | b'f' => Some(Action::CursorPosition {
+                row: self.param(0, 1) as usize - 1,
+                column: self.param(1, 1) as usize - 1
+            }),
+            b'J' => Some(Action::EraseDisplay(Erase::from_param(self.param(0, 0)))),
+            b'K' => Some(Action::EraseLine(Erase::from_param(self.param(0, 0)))),
+            b'm' => Some(Action::SelectGraphic {
+                params: self.params,
+                // `ESC [ m` is a reset, like `ESC [ 0 m`
+                count: if self.count == 0 { 1 } else { cmp::min(self.count, MAX_PARAMS) }
+            }),
+            b's' => Some(Action::SaveCursor),
+            b'u' => Some(Action::RestoreCursor),
+            _ => None
+        }
+    }
+}
+
+/// VGA color of each ANSI color
+const VGA_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// Convert an ANSI color, from 0 to 7, to the VGA color with the same look.
+pub fn vga_color(ansi: u16, bright: bool) -> u8 {
+    VGA_COLORS[(ansi & 7) as usize] | if bright { 8 } else { 0 }
+}
@@ -1,2 +1,30 @@
+/// End of the kernel image area, identity mapped on every address space: the low memory (BIOS
+/// data, VGA text buffer), the kernel image, linked at 1 MiB, and the multiboot information
+pub const KERNEL_IMAGE_END: usize = 0x0040_0000;
+
+/// Offset to the kernel heap, followed by the kernel stacks
+pub const KERNEL_HEAP_OFFSET: usize = 0x4000_0000;
+/// Size of the kernel heap and stacks area
+pub const KERNEL_HEAP_SIZE: usize = 0x4000_0000;
+
 /// Offset to user TCB
 pub const USER_TCB_OFFSET: usize = 0xB000_0000;
+
+/// Maximum number of CPUs, the others are left on the trampoline
+pub const MAX_CPU_COUNT: usize = 64;
+
+/// Offset to kernel percpu variables, each CPU has its own area
+pub const KERNEL_PERCPU_OFFSET: usize = 0xC000_0000;
+/// Size of kernel percpu variables
+pub const KERNEL_PERCPU_SIZE: usize = 64 * 1024; // 64kb
+
+/// Offset to the kernel device area, where device memory is mapped, right after the user stacks
+pub const KERNEL_DEVICE_OFFSET: usize = 0x0000_0200_0000_0000;
+/// Size of the kernel device area
+pub const KERNEL_DEVICE_SIZE: usize = 0x0000_0080_0000_0000;
+
+/// Offset to the chipset device registers (I/O APIC, HPET, Local APIC), up to 4 GiB. The Local APIC
+/// registers are identity mapped there, the others are on the kernel device area
+pub const DEVICE_IDENTITY_OFFSET: usize = 0xFEC0_0000;
+/// Size of the identity mapped device registers
+pub const DEVICE_IDENTITY_SIZE: usize = 0x0140_0000;
@@ -6,6 +6,9 @@ use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
 pub static CONTEXT_SWITCH_LOCK: AtomicBool = ATOMIC_BOOL_INIT;
 
 /// Architecture context structure
+///
+/// The FS segment isn't part of it: it always points to the kernel TCB of the CPU while the kernel
+/// runs, and the user TCB is only loaded when entering userspace, see `usermode`.
 #[derive(Clone, Debug)]
 pub struct Context {
     /// FX valid?
@@ -59,6 +62,11 @@ impl Context {
         self.cr3 = address;
     }
 
+    /// Get the page table address.
+    pub fn page_table(&self) -> usize {
+        self.cr3
+    }
+
     /// Set the stack address.
     pub fn set_stack(&mut self, address: usize) {
         self.rsp = address;
@@ -0,0 +1,253 @@
+//! # Linear framebuffer
+//!
+//! The multiboot header asks the bootloader for a linear framebuffer, and its address and pixel
+//! format come on the framebuffer tag of the boot information. The console draws its text on it
+//! with `font`, each glyph row twice so a cell is 8x16 pixels.
+//!
+//! Only the direct RGB formats of 24 and 32 bits per pixel are supported, otherwise the console
+//! stays on the VGA text buffer.
+//!
+//! ## References
+//! - [Multiboot2 Specification](https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Boot-information-format)
+
+use core::ptr;
+use spin::Mutex;
+
+use font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use memory::{MemoryController, PAGE_SIZE};
+use memory::paging::{entry, PhysicalAddress, VirtualAddress};
+use multiboot2::BootInformation;
+
+/// Type of the framebuffer tag
+const TAG_FRAMEBUFFER: u32 = 8;
+/// Type of the end tag
+const TAG_END: u32 = 0;
+
+/// Framebuffer type with the pixels as RGB values
+const TYPE_RGB: u8 = 1;
+
+/// Width of a text cell, in pixels
+pub const CELL_WIDTH: usize = GLYPH_WIDTH;
+/// Height of a text cell, in pixels, each glyph row is drawn twice
+pub const CELL_HEIGHT: usize = GLYPH_HEIGHT * 2;
+
+/// RGB value of each VGA color
+const PALETTE: [u32; 16] = [
+    0x000000, 0x0000aa, 0x00aa00, 0x00aaaa, 0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa,
+    0x555555, 0x5555ff, 0x55ff55, 0x55ffff, 0xff5555, 0xff55ff, 0xffff55, 0xffffff
+];
+
+/// Position of a color component on a pixel
+#[derive(Copy, Clone, Debug)]
+pub struct ColorField {
+    /// Bit of the least significant bit of the component
+    pub position: u8,
+    /// Number of bits of the component
+    pub size: u8
+}
+
+impl ColorField {
+    /// Put an 8 bits component on its bits of a pixel.
+    fn pack(&self, value: u32) -> u32 {
+        if self.size == 0 || self.size > 8 {
+            return 0;
+        }
+        (value >> (8 - self.size)) << self.position
+    }
+}
+
+/// Framebuffer given by the bootloader
+#[derive(Copy, Clone, Debug)]
+pub struct Info {
+    /// Physical address of the first pixel
+    pub address: PhysicalAddress,
+    /// Width, in pixels
+    pub width: usize,
+    /// Height, in pixels
+    pub height: usize,
+    /// Number of bytes between the start of two lines
+    pub pitch: usize,
+    /// Bits per pixel
+    pub bpp: u8,
+    pub red: ColorField,
+    pub green: ColorField,
+    pub blue: ColorField
+}
+
+impl Info {
+    /// Get the size of the framebuffer in bytes.
+    pub fn size(&self) -> usize {
+        self.pitch * self.height
+    }
+
+    /// Convert a `0xRRGGBB` color to a pixel value.
+    fn pixel(&self, rgb: u32) -> u32 {
+        self.red.pack(rgb >> 16 & 0xff) | self.green.pack(rgb >> 8 & 0xff) | self.blue.pack(rgb & 0xff)
+    }
+}
+
+/// Framebuffer found at boot, once it's mapped
+static INFO: Mutex<Option<Info>> = Mutex::new(None);
+
+/// Get the framebuffer, when the console is drawn on one.
+pub fn info() -> Option<Info> {
+    *INFO.lock()
+}
+
+/// Find the framebuffer tag on the boot information.
+///
+/// ## Returns
+/// The framebuffer, or `None` when the bootloader kept the VGA text mode or its pixel format isn't
+/// supported.
+pub fn find(boot_info: &BootInformation) -> Option<Info> {
+    let start = boot_info.start_address();
+    let end = boot_info.end_address();
+
+    // the tags follow the total size and a reserved field, each one aligned to 8 bytes
+    let mut address = start + 8;
+    while address + 8 <= end {
+        let (tag_type, tag_size) = unsafe {
+            (ptr::read(address as *const u32), ptr::read((address + 4) as *const u32) as usize)
+        };
+        if tag_type == TAG_END || tag_size < 8 {
+            break;
+        }
+
+        if tag_type == TAG_FRAMEBUFFER && tag_size >= 38 {
+            return unsafe { parse(address) };
+        }
+
+        address += (tag_size + 7) & !7;
+    }
+
+    None
+}
+
+/// Read a framebuffer tag.
+unsafe fn parse(tag: usize) -> Option<Info> {
+    let byte = |offset: usize| ptr::read((tag + offset) as *const u8);
+
+    let address = ptr::read((tag + 8) as *const u64) as PhysicalAddress;
+    let pitch = ptr::read((tag + 16) as *const u32) as usize;
+    let width = ptr::read((tag + 20) as *const u32) as usize;
+    let height = ptr::read((tag + 24) as *const u32) as usize;
+    let bpp = byte(28);
+
+    if byte(29) != TYPE_RGB {
+        kwarn!("{}x{} framebuffer isn't RGB, keeping the text mode", width, height);
+        return None;
+    }
+    if bpp != 24 && bpp != 32 {
+        kwarn!("{}x{} framebuffer has {} bits per pixel, keeping the text mode", width, height, bpp);
+        return None;
+    }
+
+    Some(Info {
+        address: address,
+        width: width,
+        height: height,
+        pitch: pitch,
+        bpp: bpp,
+        red: ColorField { position: byte(32), size: byte(33) },
+        green: ColorField { position: byte(34), size: byte(35) },
+        blue: ColorField { position: byte(36), size: byte(37) }
+    })
+}
+
+/// Map the framebuffer on the kernel device area and move the console to it.
+///
+/// This must be called after the memory is initialized.
+pub fn init(memory_controller: &mut MemoryController, info: Info) {
+    let base = match memory_controller.map_device(info.address, info.size(),
+                                                  entry::PRESENT | entry::WRITABLE | entry::NO_EXECUTE) {
+        Some(base) => base,
+        None => {
+            kwarn!("no room to map the {}x{} framebuffer, keeping the text mode", info.width, info.height);
+            return;
+        }
+    };
+
+    *INFO.lock() = Some(info);
+
+    ::vga_buffer::with_writer(|writer| writer.use_framebuffer(Framebuffer::new(info, base)));
+
+    kinfo!("{}x{}x{} framebuffer at {:#x}, {} pages", info.width, info.height, info.bpp, info.address,
+           (info.size() + PAGE_SIZE - 1) / PAGE_SIZE);
+}
+
+/// Text renderer on the framebuffer
+#[derive(Copy, Clone)]
+pub struct Framebuffer {
+    info: Info,
+    /// Virtual address of the first pixel
+    base: VirtualAddress,
+    /// Pixel value of each VGA color
+    palette: [u32; 16]
+}
+
+impl Framebuffer {
+    fn new(info: Info, base: VirtualAddress) -> Framebuffer {
+        let mut palette = [0; 16];
+        for (pixel, &rgb) in palette.iter_mut().zip(PALETTE.iter()) {
+            *pixel = info.pixel(rgb);
+        }
+
+        Framebuffer {
+            info: info,
+            base: base,
+            palette: palette
+        }
+    }
+
+    /// Number of text columns that fit on the screen
+    pub fn columns(&self) -> usize {
+        self.info.width / CELL_WIDTH
+    }
+
+    /// Number of text rows that fit on the screen
+    pub fn rows(&self) -> usize {
+        self.info.height / CELL_HEIGHT
+    }
+
+    /// Draw a character on a cell, with VGA colors.
+    pub fn draw_char(&self, row: usize, column: usize, character: u8, foreground: u8, background: u8) {
+        let foreground = self.palette[(foreground & 0x0f) as usize];
+        let background = self.palette[(background & 0x0f) as usize];
+        let glyph = font::glyph(character);
+
+        let x = column * CELL_WIDTH;
+        let y = row * CELL_HEIGHT;
+        for line in 0..CELL_HEIGHT {
+            let bits = glyph[line / 2];
+            for dot in 0..CELL_WIDTH {
+                let pixel = if bits & (0x80 >> dot) != 0 { foreground } else { background };
+                unsafe { self.write_pixel(x + dot, y + line, pixel); }
+            }
+        }
+    }
+
+    /// Move the text rows `1..rows` one row up, the last row keeps its pixels.
+    pub fn scroll(&self, rows: usize) {
+        if rows < 2 {
+            return;
+        }
+
+        let row_size = self.info.pitch * CELL_HEIGHT;
+        unsafe {
+            ptr::copy((self.base + row_size) as *const u8,
+                      self.base as *mut u8,
+                      row_size * (rows - 1));
+        }
+    }
+
+    unsafe fn write_pixel(&self, x: usize, y: usize, pixel: u32) {
+        let address = self.base + y * self.info.pitch + x * (self.info.bpp as usize / 8);
+        if self.info.bpp == 32 {
+            ptr::write_volatile(address as *mut u32, pixel);
+        } else {
+            ptr::write_volatile(address as *mut u8, pixel as u8);
+            ptr::write_volatile((address + 1) as *mut u8, (pixel >> 8) as u8);
+            ptr::write_volatile((address + 2) as *mut u8, (pixel >> 16) as u8);
+        }
+    }
+}
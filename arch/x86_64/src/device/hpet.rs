@@ -0,0 +1,234 @@
+//! # High Precision Event Timer (HPET)
+//!
+//! The HPET has a main counter, incremented at a fixed frequency of at least 10 MHz, and a set of
+//! comparators that fire an interrupt when the counter reaches them. The counter is used as a clock
+//! and as reference to calibrate the other timers. The comparator 0, routed to the IRQ 0 with the
+//! legacy replacement, is the interrupt source when the Local APIC timer is unusable.
+//!
+//! ## References
+//! - [IA-PC HPET Specification](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/software-developers-hpet-spec-1-0a.pdf)
+//! - [OSDev HPET](http://wiki.osdev.org/HPET)
+
+use core::intrinsics::{volatile_load, volatile_store};
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use acpi::ACPI_TABLE;
+use acpi::hpet::ADDRESS_SPACE_MEMORY;
+use interrupts::irq::{self, IrqHandler};
+use memory::MemoryController;
+use memory::paging::PhysicalAddress;
+use memory::paging::entry;
+use time::{self, Clock};
+
+/// Size of the registers
+const HPET_SIZE: usize = 0x400;
+
+/// General Capabilities and ID Register
+const CAPABILITIES: usize = 0x000;
+/// General Configuration Register
+const CONFIGURATION: usize = 0x010;
+/// Main Counter Value Register
+const MAIN_COUNTER: usize = 0x0f0;
+/// Configuration and Capability Register of the comparator 0, the others follow every 0x20 bytes
+const TIMER_0_CONFIGURATION: usize = 0x100;
+/// Comparator Value Register of the comparator 0
+const TIMER_0_COMPARATOR: usize = 0x108;
+
+/// Capabilities: the main counter is 64 bits wide
+const CAPABILITIES_COUNT_SIZE: u64 = 1 << 13;
+/// Capabilities: the legacy replacement route is supported
+const CAPABILITIES_LEGACY_ROUTE: u64 = 1 << 15;
+/// Capabilities: first bit of the counter period, in femtoseconds
+const CAPABILITIES_PERIOD_SHIFT: u64 = 32;
+
+/// Configuration: the main counter runs and the comparators can fire
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+/// Configuration: the comparators 0 and 1 are routed to the IRQs 0 and 8
+const CONFIGURATION_LEGACY_ROUTE: u64 = 1 << 1;
+
+/// Comparator configuration: the interrupt is enabled
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+/// Comparator configuration: periodic mode
+const TIMER_PERIODIC: u64 = 1 << 3;
+/// Comparator configuration: periodic mode is supported
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+/// Comparator configuration: the next write sets the accumulator, on periodic mode
+const TIMER_VALUE_SET: u64 = 1 << 6;
+
+/// IRQ of the comparator 0 with the legacy replacement route
+const TIMER_0_IRQ: u8 = 0;
+
+/// Femtoseconds per second
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+/// How the comparator 0 fires
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerMode {
+    /// Once, after the given time
+    OneShot,
+    /// Every time the given time elapses
+    Periodic
+}
+
+/// Address of the registers, on the kernel device area, zero when there isn't a HPET
+static BASE: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Counter frequency, in Hz
+static FREQUENCY: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Capabilities of the HPET
+static CAPABILITIES_VALUE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Read a register.
+unsafe fn read(base: usize, reg: usize) -> u64 {
+    volatile_load((base + reg) as *const u64)
+}
+
+/// Change the value of a register.
+unsafe fn write(base: usize, reg: usize, value: u64) {
+    volatile_store((base + reg) as *mut u64, value);
+}
+
+/// Check if there is a HPET.
+pub fn is_present() -> bool {
+    BASE.load(Ordering::SeqCst) != 0
+}
+
+/// Get the frequency of the counter, in Hz, zero when there isn't a HPET.
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::SeqCst) as u64
+}
+
+/// Read the main counter.
+///
+/// A 32 bits counter wraps around in a few minutes at most, so `time` only uses it as clock
+/// when it's 64 bits wide.
+pub fn counter() -> u64 {
+    let base = BASE.load(Ordering::SeqCst);
+    if base == 0 {
+        return 0;
+    }
+
+    unsafe { read(base, MAIN_COUNTER) }
+}
+
+/// Check if the main counter is 64 bits wide.
+fn is_64_bits() -> bool {
+    CAPABILITIES_VALUE.load(Ordering::SeqCst) as u64 & CAPABILITIES_COUNT_SIZE != 0
+}
+
+/// Busy wait for the given number of microseconds.
+pub fn wait(microseconds: u64) {
+    let ticks = frequency() * microseconds / 1_000_000;
+    let start = counter();
+
+    if is_64_bits() {
+        while counter().wrapping_sub(start) < ticks {}
+    } else {
+        while (counter() as u32).wrapping_sub(start as u32) as u64 < ticks {}
+    }
+}
+
+/// Start the comparator 0, which calls `handler` on the IRQ 0 when it fires.
+///
+/// ## Parameters
+/// - `mode`: fire once or periodically.
+/// - `nanoseconds`: time until the comparator fires, or between two interrupts.
+/// - `handler`: IRQ handler, it replaces the one that was registered on the IRQ 0.
+///
+/// ## Returns
+/// `false` if there isn't a HPET, it can't route the comparator 0 to the IRQ 0, or the mode isn't
+/// supported.
+pub fn start_timer(mode: TimerMode, nanoseconds: u64, handler: IrqHandler) -> bool {
+    let base = BASE.load(Ordering::SeqCst);
+    if base == 0 || CAPABILITIES_VALUE.load(Ordering::SeqCst) as u64 & CAPABILITIES_LEGACY_ROUTE == 0 {
+        return false;
+    }
+
+    let ticks = frequency() * nanoseconds / 1_000_000_000;
+
+    unsafe {
+        let timer = read(base, TIMER_0_CONFIGURATION);
+        if mode == TimerMode::Periodic && timer & TIMER_PERIODIC_CAPABLE == 0 {
+            return false;
+        }
+
+        irq::unregister(TIMER_0_IRQ);
+
+        // stop the counter while the comparator is programmed
+        let configuration = read(base, CONFIGURATION);
+        write(base, CONFIGURATION, configuration & !CONFIGURATION_ENABLE);
+
+        let now = read(base, MAIN_COUNTER);
+        let timer = timer & !(TIMER_PERIODIC | TIMER_VALUE_SET) | TIMER_INTERRUPT_ENABLE;
+        match mode {
+            TimerMode::OneShot => {
+                write(base, TIMER_0_CONFIGURATION, timer);
+                write(base, TIMER_0_COMPARATOR, now + ticks);
+            },
+            TimerMode::Periodic => {
+                // the first write sets the comparator and the second one the accumulator
+                write(base, TIMER_0_CONFIGURATION, timer | TIMER_PERIODIC | TIMER_VALUE_SET);
+                write(base, TIMER_0_COMPARATOR, now + ticks);
+                write(base, TIMER_0_COMPARATOR, ticks);
+            }
+        }
+
+        write(base, CONFIGURATION, configuration | CONFIGURATION_LEGACY_ROUTE | CONFIGURATION_ENABLE);
+    }
+
+    irq::register(TIMER_0_IRQ, handler)
+}
+
+/// Stop the comparator 0 and remove its IRQ handler.
+pub fn stop_timer() {
+    let base = BASE.load(Ordering::SeqCst);
+    if base == 0 {
+        return;
+    }
+
+    irq::unregister(TIMER_0_IRQ);
+    unsafe {
+        let timer = read(base, TIMER_0_CONFIGURATION);
+        write(base, TIMER_0_CONFIGURATION, timer & !TIMER_INTERRUPT_ENABLE);
+    }
+}
+
+/// Initialize the HPET described by the ACPI HPET table, and start its main counter.
+pub fn init(memory_controller: &mut MemoryController) {
+    let physical = match ACPI_TABLE.lock().hpet {
+        Some(ref hpet) if hpet.base_address.address_space == ADDRESS_SPACE_MEMORY => hpet.base_address.address as PhysicalAddress,
+        _ => return
+    };
+
+    let address = match memory_controller.map_device(physical, HPET_SIZE,
+                                                     entry::PRESENT | entry::WRITABLE | entry::NO_CACHE | entry::NO_EXECUTE) {
+        Some(address) => address,
+        None => {
+            kwarn!("no room to map the registers at {:#x}", physical);
+            return;
+        }
+    };
+
+    let capabilities = unsafe { read(address, CAPABILITIES) };
+    let period = capabilities >> CAPABILITIES_PERIOD_SHIFT;
+    if period == 0 || period > 100_000_000 {
+        kwarn!("invalid counter period {} fs", period);
+        return;
+    }
+    let frequency = FEMTOS_PER_SEC / period;
+
+    // start the main counter, the comparators stay disabled until `start_timer`
+    unsafe {
+        let configuration = read(address, CONFIGURATION);
+        write(address, CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+    }
+
+    CAPABILITIES_VALUE.store(capabilities as usize, Ordering::SeqCst);
+    FREQUENCY.store(frequency as usize, Ordering::SeqCst);
+    BASE.store(address, Ordering::SeqCst);
+
+    if is_64_bits() {
+        time::set_clock(Clock::Hpet, frequency);
+    }
+
+    kinfo!("{} MHz, {} bits counter", frequency / 1_000_000, if is_64_bits() { 64 } else { 32 });
+}
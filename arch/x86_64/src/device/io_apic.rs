@@ -0,0 +1,296 @@
+//! # I/O APIC
+//!
+//! The I/O APICs receive the external interrupts and send them to the Local APIC of a CPU. Each
+//! input pin is a Global System Interrupt (GSI) with an entry on the redirection table, which
+//! selects the vector, the destination CPU, the polarity, the trigger mode and the mask.
+//!
+//! IRQs 0 to 15 are the ISA ones, that the MADT may route to other GSIs with interrupt source
+//! overrides. IRQs 16 to 23 are the GSIs with the same number. IRQ `n` is always delivered on the
+//! vector `IRQ_OFFSET + n`, and all of them start masked.
+//!
+//! ## References
+//! - [Intel 82093AA I/O APIC datasheet](http://www.intel.com/design/chipsets/datashts/29056601.pdf)
+//! - [OSDev I/O APIC](http://wiki.osdev.org/IOAPIC)
+
+use collections::Vec;
+use core::intrinsics::{volatile_load, volatile_store};
+use spin::Mutex;
+use x86_64::registers::flags::{self, IF};
+
+use acpi::ACPI_TABLE;
+use acpi::madt::{Madt, MadtIoApic};
+use device::local_apic::LOCAL_APIC;
+use interrupts::{self, IRQ_COUNT, IRQ_OFFSET};
+use memory::MemoryController;
+use memory::paging::{PhysicalAddress, VirtualAddress};
+use memory::paging::entry;
+
+/// Register select
+const IOREGSEL: usize = 0x00;
+/// Register data window
+const IOWIN: usize = 0x10;
+/// Size of the registers
+const IO_APIC_SIZE: usize = 0x20;
+
+/// I/O APIC version register, bits 16-23 hold the index of the last redirection entry
+const IOAPICVER: u32 = 0x01;
+/// First register of the redirection table, each entry uses two registers
+const IOREDTBL: u32 = 0x10;
+
+/// Redirection entry: the input pin is active low
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+/// Redirection entry: level triggered
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+/// Redirection entry: masked
+const REDIRECTION_MASKED: u64 = 1 << 16;
+/// Redirection entry: first bit of the destination APIC id
+const REDIRECTION_DESTINATION_SHIFT: u64 = 56;
+
+/// MPS INTI flags: polarity bits
+const INTI_POLARITY_MASK: u16 = 0b11;
+/// MPS INTI flags: active low
+const INTI_POLARITY_LOW: u16 = 0b11;
+/// MPS INTI flags: trigger mode bits
+const INTI_TRIGGER_MASK: u16 = 0b11 << 2;
+/// MPS INTI flags: level triggered
+const INTI_TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+/// Number of ISA IRQs
+const ISA_IRQ_COUNT: usize = 16;
+
+/// An I/O APIC
+struct IoApic {
+    /// Address of the registers, on the kernel device area
+    base: VirtualAddress,
+    /// First GSI
+    gsi_base: u32,
+    /// Number of redirection entries
+    count: u32
+}
+
+impl IoApic {
+    /// Map the I/O APIC registers and read the number of redirection entries.
+    fn new(madt_io_apic: &MadtIoApic, memory_controller: &mut MemoryController) -> IoApic {
+        let base = memory_controller.map_device(madt_io_apic.address as PhysicalAddress, IO_APIC_SIZE,
+                                                entry::PRESENT | entry::WRITABLE | entry::NO_CACHE | entry::NO_EXECUTE)
+            .expect("no room to map the I/O APIC registers");
+
+        let mut io_apic = IoApic {
+            base: base,
+            gsi_base: madt_io_apic.gsi_base,
+            count: 0
+        };
+        io_apic.count = (io_apic.read(IOAPICVER) >> 16 & 0xFF) + 1;
+
+        io_apic
+    }
+
+    /// Read a register.
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            volatile_store((self.base + IOREGSEL) as *mut u32, reg);
+            volatile_load((self.base + IOWIN) as *const u32)
+        }
+    }
+
+    /// Change the value of a register.
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            volatile_store((self.base + IOREGSEL) as *mut u32, reg);
+            volatile_store((self.base + IOWIN) as *mut u32, value);
+        }
+    }
+
+    /// Check if the GSI is an input of this I/O APIC.
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.count
+    }
+
+    /// Read the redirection entry of a GSI.
+    fn redirection(&self, gsi: u32) -> u64 {
+        let reg = IOREDTBL + (gsi - self.gsi_base) * 2;
+        self.read(reg) as u64 | (self.read(reg + 1) as u64) << 32
+    }
+
+    /// Change the redirection entry of a GSI.
+    ///
+    /// The entry is masked while the destination is changed, so no interrupt uses half of it.
+    fn set_redirection(&self, gsi: u32, value: u64) {
+        let reg = IOREDTBL + (gsi - self.gsi_base) * 2;
+        self.write(reg, REDIRECTION_MASKED as u32);
+        self.write(reg + 1, (value >> 32) as u32);
+        self.write(reg, value as u32);
+    }
+}
+
+/// Where an IRQ is connected
+#[derive(Copy, Clone, Debug)]
+struct IrqRoute {
+    /// Global system interrupt
+    gsi: u32,
+    /// Polarity and trigger mode, as redirection entry bits
+    flags: u64
+}
+
+/// State of the I/O APIC driver
+struct IoApicState {
+    io_apics: Vec<IoApic>,
+    routes: [Option<IrqRoute>; IRQ_COUNT]
+}
+
+/// I/O APICs and IRQ routing, `None` until `init` finds the I/O APICs on the MADT
+static IO_APIC: Mutex<Option<IoApicState>> = Mutex::new(None);
+
+/// Convert MPS INTI flags to redirection entry bits.
+///
+/// When they conform to the bus specification, the ISA defaults are used: active high and edge
+/// triggered.
+fn redirection_flags(inti_flags: u16) -> u64 {
+    let mut flags = 0;
+    if inti_flags & INTI_POLARITY_MASK == INTI_POLARITY_LOW {
+        flags |= REDIRECTION_ACTIVE_LOW;
+    }
+    if inti_flags & INTI_TRIGGER_MASK == INTI_TRIGGER_LEVEL {
+        flags |= REDIRECTION_LEVEL;
+    }
+    flags
+}
+
+/// Find the GSI, polarity and trigger mode of each IRQ.
+fn irq_routes(madt: &Madt) -> [Option<IrqRoute>; IRQ_COUNT] {
+    let mut routes = [None; IRQ_COUNT];
+
+    // ISA IRQs are identity mapped, active high and edge triggered; the other GSIs are PCI
+    // interrupts, active low and level triggered.
+    for (irq, route) in routes.iter_mut().enumerate() {
+        let flags = if irq < ISA_IRQ_COUNT { 0 } else { REDIRECTION_ACTIVE_LOW | REDIRECTION_LEVEL };
+        *route = Some(IrqRoute { gsi: irq as u32, flags: flags });
+    }
+
+    for source_override in madt.interrupt_source_overrides.iter() {
+        let irq = source_override.source as usize;
+        if source_override.bus != 0 || irq >= ISA_IRQ_COUNT {
+            continue;
+        }
+
+        // the IRQ that was identity mapped to the GSI isn't connected to it
+        for route in routes.iter_mut() {
+            if route.map_or(false, |route| route.gsi == source_override.gsi) {
+                *route = None;
+            }
+        }
+
+        routes[irq] = Some(IrqRoute {
+            gsi: source_override.gsi,
+            flags: redirection_flags(source_override.flags)
+        });
+    }
+
+    routes
+}
+
+/// Run `f` with the driver state, if there is one.
+///
+/// Interrupts are disabled while the lock is held, so an IRQ handler on this CPU, that masks its
+/// IRQ, can't deadlock.
+fn with_state<F, T>(f: F) -> Option<T>
+    where F: FnOnce(&IoApicState) -> T
+{
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { interrupts::disable(); }
+
+    let result = IO_APIC.lock().as_ref().map(f);
+
+    if interrupts_enabled {
+        unsafe { interrupts::enable(); }
+    }
+
+    result
+}
+
+/// Change the redirection entry of an IRQ.
+///
+/// ## Returns
+/// `false` if the IRQ isn't connected to an I/O APIC.
+fn update_redirection<F>(irq: u8, f: F) -> bool
+    where F: FnOnce(u64) -> u64
+{
+    with_state(|state| {
+        let route = match state.routes.get(irq as usize) {
+            Some(&Some(route)) => route,
+            _ => return false
+        };
+
+        match state.io_apics.iter().find(|io_apic| io_apic.handles(route.gsi)) {
+            Some(io_apic) => {
+                let value = io_apic.redirection(route.gsi);
+                io_apic.set_redirection(route.gsi, f(value));
+                true
+            },
+            None => false
+        }
+    }).unwrap_or(false)
+}
+
+/// Mask an IRQ.
+pub fn mask(irq: u8) -> bool {
+    update_redirection(irq, |value| value | REDIRECTION_MASKED)
+}
+
+/// Unmask an IRQ.
+pub fn unmask(irq: u8) -> bool {
+    update_redirection(irq, |value| value & !REDIRECTION_MASKED)
+}
+
+/// Send an IRQ to the CPU with the given Local APIC id.
+pub fn set_destination(irq: u8, apic_id: u8) -> bool {
+    update_redirection(irq, |value| {
+        value & !(0xFF << REDIRECTION_DESTINATION_SHIFT) | (apic_id as u64) << REDIRECTION_DESTINATION_SHIFT
+    })
+}
+
+/// Initialize the I/O APICs listed on the MADT.
+///
+/// All the inputs are masked and the IRQs are routed to the BSP.
+pub fn init(memory_controller: &mut MemoryController) {
+    let madt = match ACPI_TABLE.lock().madt.clone() {
+        Some(madt) => madt,
+        None => {
+            kwarn!("no MADT, IRQs are disabled");
+            return;
+        }
+    };
+
+    let io_apics: Vec<IoApic> = madt.io_apics.iter()
+        .map(|madt_io_apic| IoApic::new(madt_io_apic, memory_controller))
+        .collect();
+
+    // mask everything the firmware may have left enabled
+    for io_apic in io_apics.iter() {
+        for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.count {
+            io_apic.set_redirection(gsi, REDIRECTION_MASKED);
+        }
+    }
+
+    // program the routed IRQs, they are unmasked when a handler is registered
+    let routes = irq_routes(&madt);
+    let destination = unsafe { LOCAL_APIC.id() } as u64;
+    let mut routed = 0;
+    for (irq, route) in routes.iter().enumerate() {
+        if let Some(route) = *route {
+            if let Some(io_apic) = io_apics.iter().find(|io_apic| io_apic.handles(route.gsi)) {
+                let vector = IRQ_OFFSET as u64 + irq as u64;
+                io_apic.set_redirection(route.gsi, vector | route.flags | REDIRECTION_MASKED |
+                                        destination << REDIRECTION_DESTINATION_SHIFT);
+                routed += 1;
+            }
+        }
+    }
+
+    kinfo!("{} controllers, {} IRQs routed", io_apics.len(), routed);
+
+    *IO_APIC.lock() = Some(IoApicState {
+        io_apics: io_apics,
+        routes: routes
+    });
+}
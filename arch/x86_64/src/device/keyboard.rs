@@ -0,0 +1,376 @@
+//! # Keyboard
+//!
+//! Turns the bytes the keyboard sends into key events. The key code of an event is its make code
+//! on the scancode set 1, plus `EXTENDED` for the keys prefixed by `0xe0`. Keyboards on the
+//! scancode set 2 are translated to it, so the rest of the kernel only knows the set 1.
+//!
+//! The character of a key press comes from the US layout, with the current modifiers. The events
+//! are queued until someone reads them.
+//!
+//! ## References
+//! - [OSDev PS/2 Keyboard](http://wiki.osdev.org/PS/2_Keyboard)
+//! - [Keyboard scancodes](https://www.win.tue.nl/~aeb/linux/kbd/scancodes-1.html)
+
+use core::cmp;
+use spin::Mutex;
+use x86_64::registers::flags::{self, IF};
+
+use interrupts;
+use vga_buffer;
+
+/// Added to the key code of the keys prefixed by `0xe0`
+pub const EXTENDED: u8 = 0x80;
+
+/// Prefix of the extended keys, on both sets
+const PREFIX_EXTENDED: u8 = 0xe0;
+/// Prefix of the Pause key, on both sets
+const PREFIX_PAUSE: u8 = 0xe1;
+/// Prefix of a key release, on the set 2
+const PREFIX_RELEASE: u8 = 0xf0;
+/// Bit of a key release, on the set 1
+const RELEASE_BIT: u8 = 0x80;
+
+/// Number of bytes after the Pause prefix, on each set
+const PAUSE_LENGTH_SET_1: u8 = 5;
+const PAUSE_LENGTH_SET_2: u8 = 7;
+
+/// Key codes the decoder needs to know
+const KEY_LEFT_CTRL: u8 = 0x1d;
+const KEY_LEFT_SHIFT: u8 = 0x2a;
+const KEY_RIGHT_SHIFT: u8 = 0x36;
+const KEY_LEFT_ALT: u8 = 0x38;
+const KEY_CAPS_LOCK: u8 = 0x3a;
+const KEY_NUM_LOCK: u8 = 0x45;
+const KEY_RIGHT_CTRL: u8 = EXTENDED | 0x1d;
+const KEY_RIGHT_ALT: u8 = EXTENDED | 0x38;
+const KEY_PAGE_UP: u8 = EXTENDED | 0x49;
+const KEY_PAGE_DOWN: u8 = EXTENDED | 0x51;
+/// First and last keys of the keypad
+const KEY_KEYPAD_FIRST: u8 = 0x47;
+const KEY_KEYPAD_LAST: u8 = 0x53;
+
+/// Number of events kept until they are read, the new ones are dropped when it's full
+const EVENT_QUEUE_SIZE: usize = 128;
+
+/// Lines scrolled on the console by Shift+Page Up and Shift+Page Down
+const SCROLL_LINES: isize = 12;
+
+/// Scancode set 1 make code of each scancode set 2 code
+const SET_2_TO_SET_1: [u8; 0x84] = [
+    0x00, 0x43, 0x00, 0x3f, 0x3d, 0x3b, 0x3c, 0x58, 0x00, 0x44, 0x42, 0x40, 0x3e, 0x0f, 0x29, 0x00,
+    0x00, 0x38, 0x2a, 0x00, 0x1d, 0x10, 0x02, 0x00, 0x00, 0x00, 0x2c, 0x1f, 0x1e, 0x11, 0x03, 0x00,
+    0x00, 0x2e, 0x2d, 0x20, 0x12, 0x05, 0x04, 0x00, 0x00, 0x39, 0x2f, 0x21, 0x14, 0x13, 0x06, 0x00,
+    0x00, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x00, 0x00, 0x00, 0x32, 0x24, 0x16, 0x08, 0x09, 0x00,
+    0x00, 0x33, 0x25, 0x17, 0x18, 0x0b, 0x0a, 0x00, 0x00, 0x34, 0x35, 0x26, 0x27, 0x19, 0x0c, 0x00,
+    0x00, 0x00, 0x28, 0x00, 0x1a, 0x0d, 0x00, 0x00, 0x3a, 0x36, 0x1c, 0x1b, 0x00, 0x2b, 0x00, 0x00,
+    0x00, 0x56, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x4f, 0x00, 0x4b, 0x47, 0x00, 0x00, 0x00,
+    0x52, 0x53, 0x50, 0x4c, 0x4d, 0x48, 0x01, 0x45, 0x57, 0x4e, 0x51, 0x4a, 0x37, 0x49, 0x46, 0x00,
+    0x00, 0x00, 0x00, 0x41,
+];
+
+/// Characters of the keys on the US layout, without and with Shift, indexed by key code
+const US_LAYOUT: [[char; 2]; 0x3a] = [
+    ['\0', '\0'], ['\x1b', '\x1b'], ['1', '!'], ['2', '@'],
+    ['3', '#'], ['4', '$'], ['5', '%'], ['6', '^'],
+    ['7', '&'], ['8', '*'], ['9', '('], ['0', ')'],
+    ['-', '_'], ['=', '+'], ['\x08', '\x08'], ['\t', '\t'],
+    ['q', 'Q'], ['w', 'W'], ['e', 'E'], ['r', 'R'],
+    ['t', 'T'], ['y', 'Y'], ['u', 'U'], ['i', 'I'],
+    ['o', 'O'], ['p', 'P'], ['[', '{'], [']', '}'],
+    ['\n', '\n'], ['\0', '\0'], ['a', 'A'], ['s', 'S'],
+    ['d', 'D'], ['f', 'F'], ['g', 'G'], ['h', 'H'],
+    ['j', 'J'], ['k', 'K'], ['l', 'L'], [';', ':'],
+    ['\'', '"'], ['`', '~'], ['\0', '\0'], ['\\', '|'],
+    ['z', 'Z'], ['x', 'X'], ['c', 'C'], ['v', 'V'],
+    ['b', 'B'], ['n', 'N'], ['m', 'M'], [',', '<'],
+    ['.', '>'], ['/', '?'], ['\0', '\0'], ['*', '*'],
+    ['\0', '\0'], [' ', ' ']
+];
+
+/// Characters of the keypad with Num Lock, from `KEY_KEYPAD_FIRST` to `KEY_KEYPAD_LAST`
+const US_KEYPAD: [char; 13] = ['7', '8', '9', '-', '4', '5', '6', '+', '1', '2', '3', '0', '.'];
+
+/// Scancode set the keyboard sends
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2
+}
+
+bitflags! {
+    /// Modifier keys held, and lock keys on, when a key event happens
+    pub flags Modifiers: u8 {
+        const SHIFT = 1,
+        const CTRL = 1 << 1,
+        const ALT = 1 << 2,
+        const CAPS_LOCK = 1 << 3,
+        const NUM_LOCK = 1 << 4,
+    }
+}
+
+/// A key pressed or released
+#[derive(Copy, Clone, Debug)]
+pub struct KeyEvent {
+    /// Scancode set 1 make code, plus `EXTENDED` for the extended keys
+    pub scancode: u8,
+    /// Character of the key on the US layout, with the modifiers, `'\0'` when it has none
+    pub character: char,
+    pub pressed: bool,
+    pub modifiers: Modifiers
+}
+
+const NO_EVENT: KeyEvent = KeyEvent {
+    scancode: 0,
+    character: '\0',
+    pressed: false,
+    modifiers: Modifiers { bits: 0 }
+};
+
+/// State of the scancode decoder
+struct Decoder {
+    set: ScancodeSet,
+    /// The last byte was the extended prefix
+    extended: bool,
+    /// The last byte was the release prefix
+    release: bool,
+    /// Number of bytes of the Pause sequence still to skip
+    skip: u8,
+    left_shift: bool,
+    right_shift: bool,
+    left_ctrl: bool,
+    right_ctrl: bool,
+    left_alt: bool,
+    right_alt: bool,
+    caps_lock: bool,
+    num_lock: bool,
+    /// Events not read yet
+    events: [KeyEvent; EVENT_QUEUE_SIZE],
+    /// Index of the oldest event
+    head: usize,
+    /// Number of events on the queue
+    len: usize
+}
+
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder {
+    set: ScancodeSet::Set1,
+    extended: false,
+    release: false,
+    skip: 0,
+    left_shift: false,
+    right_shift: false,
+    left_ctrl: false,
+    right_ctrl: false,
+    left_alt: false,
+    right_alt: false,
+    caps_lock: false,
+    num_lock: false,
+    events: [NO_EVENT; EVENT_QUEUE_SIZE],
+    head: 0,
+    len: 0
+});
+
+impl Decoder {
+    /// Decode the next byte.
+    ///
+    /// ## Returns
+    /// The key code and if it was pressed, `None` while the scancode isn't complete.
+    fn decode(&mut self, byte: u8) -> Option<(u8, bool)> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+
+        match byte {
+            PREFIX_EXTENDED => {
+                self.extended = true;
+                return None;
+            },
+            PREFIX_PAUSE => {
+                // the Pause key sends a make and a break code at once, and nothing on release
+                self.skip = if self.set == ScancodeSet::Set1 { PAUSE_LENGTH_SET_1 } else { PAUSE_LENGTH_SET_2 };
+                return None;
+            },
+            PREFIX_RELEASE if self.set == ScancodeSet::Set2 => {
+                self.release = true;
+                return None;
+            },
+            _ => ()
+        }
+
+        let extended = self.extended;
+        let release = self.release;
+        self.extended = false;
+        self.release = false;
+
+        let (code, pressed) = match self.set {
+            ScancodeSet::Set1 => (byte & !RELEASE_BIT, byte & RELEASE_BIT == 0),
+            ScancodeSet::Set2 => {
+                let code = match (extended, byte) {
+                    // the Windows and Menu keys only exist as extended keys
+                    (true, 0x1f) => 0x5b,
+                    (true, 0x27) => 0x5c,
+                    (true, 0x2f) => 0x5d,
+                    _ => SET_2_TO_SET_1.get(byte as usize).cloned().unwrap_or(0)
+                };
+                (code, !release)
+            }
+        };
+
+        // the fake Shift the extended keys send with Num Lock or Shift
+        if code == 0 || extended && (code == KEY_LEFT_SHIFT || code == KEY_RIGHT_SHIFT) {
+            return None;
+        }
+
+        Some((if extended { EXTENDED | code } else { code }, pressed))
+    }
+
+    /// Update the modifiers with a key event.
+    fn update_modifiers(&mut self, scancode: u8, pressed: bool) {
+        match scancode {
+            KEY_LEFT_SHIFT => self.left_shift = pressed,
+            KEY_RIGHT_SHIFT => self.right_shift = pressed,
+            KEY_LEFT_CTRL => self.left_ctrl = pressed,
+            KEY_RIGHT_CTRL => self.right_ctrl = pressed,
+            KEY_LEFT_ALT => self.left_alt = pressed,
+            KEY_RIGHT_ALT => self.right_alt = pressed,
+            KEY_CAPS_LOCK if pressed => self.caps_lock = !self.caps_lock,
+            KEY_NUM_LOCK if pressed => self.num_lock = !self.num_lock,
+            _ => ()
+        }
+    }
+
+    /// Get the modifiers currently held or on.
+    fn modifiers(&self) -> Modifiers {
+        let mut modifiers = Modifiers::empty();
+        if self.left_shift || self.right_shift {
+            modifiers.insert(SHIFT);
+        }
+        if self.left_ctrl || self.right_ctrl {
+            modifiers.insert(CTRL);
+        }
+        if self.left_alt || self.right_alt {
+            modifiers.insert(ALT);
+        }
+        if self.caps_lock {
+            modifiers.insert(CAPS_LOCK);
+        }
+        if self.num_lock {
+            modifiers.insert(NUM_LOCK);
+        }
+        modifiers
+    }
+
+    /// Add an event at the end of the queue, it's dropped if the queue is full.
+    fn push(&mut self, event: KeyEvent) {
+        if self.len < EVENT_QUEUE_SIZE {
+            self.events[(self.head + self.len) % EVENT_QUEUE_SIZE] = event;
+            self.len += 1;
+        }
+    }
+}
+
+/// Check if a character is an ASCII letter.
+fn is_letter(character: char) -> bool {
+    (character >= 'a' && character <= 'z') || (character >= 'A' && character <= 'Z')
+}
+
+/// Get the character of a key on the US layout.
+fn character(scancode: u8, modifiers: Modifiers) -> char {
+    let character = match scancode {
+        // the keypad slash and enter
+        0xb5 => '/',
+        0x9c => '\n',
+        KEY_KEYPAD_FIRST...KEY_KEYPAD_LAST => {
+            let character = US_KEYPAD[(scancode - KEY_KEYPAD_FIRST) as usize];
+            // without Num Lock, only the operators are characters
+            if modifiers.contains(NUM_LOCK) || character == '-' || character == '+' {
+                character
+            } else {
+                '\0'
+            }
+        },
+        _ if (scancode as usize) < US_LAYOUT.len() => {
+            let normal = US_LAYOUT[scancode as usize][0];
+            let shifted = US_LAYOUT[scancode as usize][1];
+
+            // Caps Lock only changes the letters
+            if modifiers.contains(SHIFT) != (is_letter(normal) && modifiers.contains(CAPS_LOCK)) {
+                shifted
+            } else {
+                normal
+            }
+        },
+        _ => '\0'
+    };
+
+    // Ctrl with a letter gives its control character
+    if modifiers.contains(CTRL) && is_letter(character) {
+        ((character as u8) & 0x1f) as char
+    } else {
+        character
+    }
+}
+
+/// Change the scancode set the decoder expects.
+pub fn set_scancode_set(set: ScancodeSet) {
+    let mut decoder = DECODER.lock();
+    decoder.set = set;
+    decoder.extended = false;
+    decoder.release = false;
+    decoder.skip = 0;
+}
+
+/// Handle a byte received from the keyboard, this is called by the keyboard IRQ handler.
+pub fn receive(byte: u8) {
+    let mut decoder = DECODER.lock();
+
+    let (scancode, pressed) = match decoder.decode(byte) {
+        Some(key) => key,
+        None => return
+    };
+    decoder.update_modifiers(scancode, pressed);
+
+    let modifiers = decoder.modifiers();
+
+    // Shift+Page Up and Shift+Page Down scroll the console, they aren't delivered
+    if modifiers.contains(SHIFT) && (scancode == KEY_PAGE_UP || scancode == KEY_PAGE_DOWN) {
+        if pressed {
+            let lines = if scancode == KEY_PAGE_UP { SCROLL_LINES } else { -SCROLL_LINES };
+            vga_buffer::WRITER.lock().scroll_view(lines);
+        }
+        return;
+    }
+
+    decoder.push(KeyEvent {
+        scancode: scancode,
+        character: if pressed { character(scancode, modifiers) } else { '\0' },
+        pressed: pressed,
+        modifiers: modifiers
+    });
+}
+
+/// Move the oldest events to `buffer`. This doesn't wait for them.
+///
+/// ## Returns
+/// The number of events moved.
+pub fn read(buffer: &mut [KeyEvent]) -> usize {
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { interrupts::disable(); }
+
+    let count = {
+        let mut decoder = DECODER.lock();
+        let count = cmp::min(buffer.len(), decoder.len);
+        for event in buffer[..count].iter_mut() {
+            *event = decoder.events[decoder.head];
+            decoder.head = (decoder.head + 1) % EVENT_QUEUE_SIZE;
+            decoder.len -= 1;
+        }
+        count
+    };
+
+    if interrupts_enabled {
+        unsafe { interrupts::enable(); }
+    }
+
+    count
+}
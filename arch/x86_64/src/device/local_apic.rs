@@ -1,7 +1,12 @@
+use core::{cmp, u32};
 use core::intrinsics::{volatile_load, volatile_store};
 use raw_cpuid::CpuId;
 use x86_64::registers::msr::*;
 
+use device::{hpet, pit};
+use device::pm_timer::PmTimer;
+use interrupts::{SPURIOUS_VECTOR, TIMER_VECTOR};
+use time::{self, Clock};
 use memory::{MemoryController, Frame};
 use memory::paging::Page;
 use memory::paging::{VirtualAddress, PhysicalAddress};
@@ -10,7 +15,10 @@ use memory::paging::entry;
 /// Bind containing an instance of the LocalApic struct
 pub static mut LOCAL_APIC: LocalApic = LocalApic {
     base: 0,
-    x2_support: false
+    x2_support: false,
+    timer_initial_count: DEFAULT_TIMER_INITIAL_COUNT,
+    tsc_deadline: false,
+    tsc_per_tick: 0
 };
 
 /// Initialize the Local APIC system
@@ -18,23 +26,84 @@ pub unsafe fn init(memory_controller: &mut MemoryController) {
     LOCAL_APIC.init(memory_controller);
 }
 
+/// Calibrate the Local APIC timer and the TSC, then start the timer of the BSP
+///
+/// The reference is the HPET, or the ACPI PM timer, when ACPI describes them, and the PIT
+/// otherwise. So this must be called after `acpi::init` and `hpet::init`.
+///
+/// ## Returns
+/// `false` if the timer doesn't count, then it isn't started.
+pub unsafe fn calibrate() -> bool {
+    let usable = LOCAL_APIC.calibrate();
+    if usable {
+        LOCAL_APIC.enable_timer();
+    }
+    usable
+}
+
+/// Initialize the Local APIC of an AP
+///
+/// The base address and the timer calibration are shared by all the CPUs, so only the APIC itself
+/// is enabled.
+pub unsafe fn init_ap() {
+    LOCAL_APIC.init_ap();
+    LOCAL_APIC.enable_timer();
+}
+
+/// Local APIC ID register
+const APIC_REG_ID: u32 = 0x20;
 /// End of interrupt register
 const APIC_REG_EOI: u32 = 0xb0;
+/// Spurious Interrupt Vector Register
+const APIC_REG_SIVR: u32 = 0xf0;
+/// Software enable bit of the Spurious Interrupt Vector Register
+const APIC_SIVR_ENABLE: u32 = 0x100;
 /// Interrupt Control Register (low)
 const APIC_REG_ICR_LOW: u32 = 0x300;
 /// Interrupt Control Register (higher)
 const APIC_REG_ICR_HIGH: u32 = 0x310;
+/// ICR level: assert
+const APIC_ICR_ASSERT: u64 = 1 << 14;
+/// ICR destination shorthand: all excluding self
+const APIC_ICR_ALL_EXCLUDING_SELF: u64 = 0b11 << 18;
 /// Timer Local Vector Table Entry
 const APIC_REG_TIMER_LOCAL_VECTOR: u32 = 0x320;
 /// Timer Initial Count Register
 const APIC_REG_TIMER_INIT_COUNT: u32 = 0x380;
+/// Timer Current Count Register
+const APIC_REG_TIMER_CURRENT_COUNT: u32 = 0x390;
 /// Timer Divide Configuration Register
 const APIC_REG_TIMER_DIVIDE: u32 = 0x3e0;
 
+/// First x2APIC MSR, each register is at `IA32_X2APIC_BASE + (offset >> 4)`
+const IA32_X2APIC_BASE: u32 = 0x800;
+/// TSC-deadline MSR, the timer fires when the TSC reaches it
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+/// Local Vector Table Entry: masked
+const APIC_LVT_MASKED: u32 = 1 << 16;
+/// Timer Local Vector Table Entry: periodic mode
+const APIC_TIMER_PERIODIC: u32 = 0b01 << 17;
+/// Timer Local Vector Table Entry: TSC-deadline mode
+const APIC_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+/// Timer Divide Configuration: divide by 16
+const APIC_TIMER_DIVIDE_16: u32 = 0x3;
+
+/// Timer initial count used when the calibration fails
+const DEFAULT_TIMER_INITIAL_COUNT: u32 = 0x10000;
+/// Duration of the calibration, in microseconds
+const CALIBRATION_TIME: u64 = 10_000;
+
 /// Local APIC
 pub struct LocalApic {
     base: usize,
-    x2_support: bool
+    x2_support: bool,
+    /// Timer initial count for one tick, on periodic mode
+    timer_initial_count: u32,
+    /// Whether the timer uses the TSC-deadline mode
+    tsc_deadline: bool,
+    /// TSC cycles per tick, on TSC-deadline mode
+    tsc_per_tick: u64
 }
 
 impl LocalApic {
@@ -55,12 +124,10 @@ impl LocalApic {
             memory_controller.flush_all();
         }
 
+        // the timer is started once it's calibrated
         self.init_ap();
 
-        // enable timer
-        self.enable_timer();
-
-        println!("APIC: Initialized!\n\tBase address: 0x{:>016x}\n\tx2APIC support: {:#?}", self.base, self.x2_support);
+        kinfo!("base address 0x{:>016x}, x2APIC {}", self.base, if self.x2_support { "supported" } else { "unsupported" });
     }
 
     /// Enable LAPIC.
@@ -70,9 +137,9 @@ impl LocalApic {
         unsafe {
             if self.x2_support {
                 wrmsr(IA32_APIC_BASE, rdmsr(IA32_APIC_BASE) | 1 << 10);
-                wrmsr(IA32_X2APIC_SIVR, 0x100);
+                wrmsr(IA32_X2APIC_SIVR, (APIC_SIVR_ENABLE | SPURIOUS_VECTOR as u32) as u64);
             } else {
-                self.write(0xf0, 0x100);
+                self.write(APIC_REG_SIVR, APIC_SIVR_ENABLE | SPURIOUS_VECTOR as u32);
             }
         }
     }
@@ -84,7 +151,11 @@ impl LocalApic {
     /// - `value`: register value
     fn write(&self, reg: u32, value: u32) {
         unsafe {
-            volatile_store((self.base + reg as usize) as *mut u32, value);
+            if self.x2_support {
+                wrmsr(IA32_X2APIC_BASE + (reg >> 4), value as u64);
+            } else {
+                volatile_store((self.base + reg as usize) as *mut u32, value);
+            }
         }
     }
 
@@ -96,8 +167,19 @@ impl LocalApic {
     /// ## Returns
     /// The register value.
     fn read(&self, reg: u32) -> u32 {
-        unsafe {
-            volatile_load((self.base + reg as usize) as *const u32)
+        if self.x2_support {
+            rdmsr(IA32_X2APIC_BASE + (reg >> 4)) as u32
+        } else {
+            unsafe { volatile_load((self.base + reg as usize) as *const u32) }
+        }
+    }
+
+    /// Get the id of this Local APIC.
+    pub fn id(&self) -> u32 {
+        if self.x2_support {
+            rdmsr(IA32_X2APIC_APICID) as u32
+        } else {
+            self.read(APIC_REG_ID) >> 24
         }
     }
 
@@ -128,26 +210,38 @@ impl LocalApic {
         }
     }
 
-    /// Throw an Inter-Processor Interrupt.
+    /// Get the destination field of the ICR that targets a Local APIC.
     ///
     /// ## Parameters
     /// - `apic_id`: LAPIC's ID of destination.
-    pub fn inter_processor_interrupt(&mut self, apic_id: usize) {
-        let mut icr = 0x4040;
-
-        // Set the destination
+    pub fn icr_destination(&self, apic_id: usize) -> u64 {
         if self.x2_support {
             // bits 63:32
-            icr |= (apic_id as u64) << 32;
+            (apic_id as u64) << 32
         } else {
             // bits 63:56
-            icr |= (apic_id as u64) << 56;
+            (apic_id as u64) << 56
         }
+    }
 
-        // set the ICR register
+    /// Throw an Inter-Processor Interrupt.
+    ///
+    /// ## Parameters
+    /// - `apic_id`: LAPIC's ID of destination.
+    /// - `vector`: interrupt vector.
+    pub fn inter_processor_interrupt(&mut self, apic_id: usize, vector: u8) {
+        let icr = self.icr_destination(apic_id) | APIC_ICR_ASSERT | vector as u64;
         self.set_icr(icr);
     }
 
+    /// Throw an Inter-Processor Interrupt to all the other CPUs.
+    ///
+    /// ## Parameters
+    /// - `vector`: interrupt vector.
+    pub fn broadcast_inter_processor_interrupt(&mut self, vector: u8) {
+        self.set_icr(APIC_ICR_ALL_EXCLUDING_SELF | APIC_ICR_ASSERT | vector as u64);
+    }
+
     /// Specific End of Interrupt
     pub fn end_of_interrupt(&mut self) {
         unsafe {
@@ -159,15 +253,89 @@ impl LocalApic {
         }
     }
 
+    /// Measure the frequency of the timer and of the TSC.
+    ///
+    /// The timer counts down from its maximum while the reference timer waits for
+    /// `CALIBRATION_TIME`. The TSC-deadline mode is used when the TSC is invariant and the CPU
+    /// supports it.
+    ///
+    /// ## Returns
+    /// `false` if the timer is too slow to tick at `time::TICK_FREQUENCY`.
+    fn calibrate(&mut self) -> bool {
+        let pm_timer = if hpet::is_present() { None } else { PmTimer::new() };
+
+        // one-shot and masked, from the maximum count
+        self.write(APIC_REG_TIMER_DIVIDE, APIC_TIMER_DIVIDE_16);
+        self.write(APIC_REG_TIMER_LOCAL_VECTOR, APIC_LVT_MASKED | TIMER_VECTOR as u32);
+        self.write(APIC_REG_TIMER_INIT_COUNT, u32::MAX);
+        let tsc_start = time::tsc();
+
+        let reference = if hpet::is_present() {
+            hpet::wait(CALIBRATION_TIME);
+            "HPET"
+        } else if let Some(ref pm_timer) = pm_timer {
+            pm_timer.wait(CALIBRATION_TIME);
+            "ACPI PM timer"
+        } else {
+            pit::wait(CALIBRATION_TIME);
+            "PIT"
+        };
+
+        let tsc_end = time::tsc();
+        let timer_ticks = u32::MAX - self.read(APIC_REG_TIMER_CURRENT_COUNT);
+        self.write(APIC_REG_TIMER_INIT_COUNT, 0);
+
+        let timer_frequency = timer_ticks as u64 * 1_000_000 / CALIBRATION_TIME;
+        let tsc_frequency = (tsc_end - tsc_start) * 1_000_000 / CALIBRATION_TIME;
+
+        let usable = timer_frequency >= time::TICK_FREQUENCY;
+        if usable {
+            self.timer_initial_count = cmp::min(timer_frequency / time::TICK_FREQUENCY, u32::MAX as u64) as u32;
+        }
+
+        let cpuid = CpuId::new();
+        let invariant_tsc = cpuid.get_extended_function_info().map_or(false, |info| info.has_invariant_tsc());
+        if invariant_tsc && tsc_frequency > 0 {
+            time::set_clock(Clock::Tsc, tsc_frequency);
+
+            self.tsc_deadline = cpuid.get_feature_info().map_or(false, |info| info.has_tsc_deadline());
+            self.tsc_per_tick = tsc_frequency / time::TICK_FREQUENCY;
+        }
+
+        kinfo!("timer at {} kHz, TSC at {} MHz{}{}, calibrated with the {}",
+              timer_frequency / 1000, tsc_frequency / 1_000_000,
+              if invariant_tsc { ", invariant" } else { "" },
+              if self.tsc_deadline { ", TSC-deadline mode" } else { "" },
+              reference);
+
+        usable
+    }
+
     /// Enable timer.
+    ///
+    /// It fires `time::TICK_FREQUENCY` times per second, once calibrated.
     pub fn enable_timer(&mut self) {
-        // Set the divider amount
-        self.write(APIC_REG_TIMER_DIVIDE, 0x3);
+        if self.tsc_deadline {
+            self.write(APIC_REG_TIMER_LOCAL_VECTOR, APIC_TIMER_TSC_DEADLINE | TIMER_VECTOR as u32);
+            self.timer_tick();
+        } else {
+            // Set the divider amount
+            self.write(APIC_REG_TIMER_DIVIDE, APIC_TIMER_DIVIDE_16);
+
+            // Set the start count value
+            self.write(APIC_REG_TIMER_INIT_COUNT, self.timer_initial_count);
 
-        // Set the start count value
-        self.write(APIC_REG_TIMER_INIT_COUNT, 0x10000);
+            // Enable the time interrupt
+            self.write(APIC_REG_TIMER_LOCAL_VECTOR, APIC_TIMER_PERIODIC | TIMER_VECTOR as u32);
+        }
+    }
 
-        // Enable the time interrupt
-        self.write(APIC_REG_TIMER_LOCAL_VECTOR, (1<<17) | 0x40);
+    /// Program the next tick of the timer.
+    ///
+    /// On periodic mode the timer reloads itself, so only the TSC-deadline mode needs this.
+    pub fn timer_tick(&mut self) {
+        if self.tsc_deadline {
+            unsafe { wrmsr(IA32_TSC_DEADLINE, time::tsc() + self.tsc_per_tick); }
+        }
     }
 }
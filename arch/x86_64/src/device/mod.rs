@@ -1,18 +1,59 @@
 use memory::MemoryController;
+use time;
 
+pub mod framebuffer;
+pub mod hpet;
+pub mod io_apic;
+pub mod keyboard;
 pub mod local_apic;
+pub mod pci;
+pub mod pic;
+pub mod pit;
+pub mod pm_timer;
+pub mod ps2;
 pub mod rtc;
 pub mod serial;
 
+/// IRQ handler of the HPET periodic timer
+fn hpet_tick(_irq: u8) {
+    time::tick();
+}
+
 /// Initialize some devices
 pub fn init(memory_controller: &mut MemoryController) {
     unsafe {
+        pic::init();
         local_apic::init(memory_controller);
     }
 }
 
+/// Initialize the core devices of an AP
+pub fn init_ap() {
+    unsafe {
+        local_apic::init_ap();
+    }
+}
+
 /// Initialize all non core devices
-pub fn init_non_core() {
+///
+/// The I/O APICs, the HPET, the ACPI PM timer and the PCI ECAM area are found on the ACPI tables,
+/// so this must be called after `acpi::init`.
+pub fn init_non_core(memory_controller: &mut MemoryController) {
+    io_apic::init(memory_controller);
+    hpet::init(memory_controller);
+
+    // the HPET ticks for the BSP when its Local APIC timer doesn't work
+    if !unsafe { local_apic::calibrate() } {
+        let period = 1_000_000_000 / time::TICK_FREQUENCY;
+        if hpet::start_timer(hpet::TimerMode::Periodic, period, hpet_tick) {
+            kinfo!("the HPET ticks instead of the Local APIC timer");
+        } else {
+            kwarn!("Local APIC timer unusable, the clock won't advance without a counter");
+        }
+    }
+
     rtc::init();
-    serial::init();
+    serial::init_interrupts();
+    ps2::init();
+    pci::init(memory_controller);
 }
@@ -0,0 +1,150 @@
+//! Base Address Registers
+//!
+//! A BAR gives the address of an I/O port range or of a memory range of the function. Its size is
+//! found by writing all ones on it: the address bits the function doesn't decode read back as
+//! zeros. A 64 bits memory BAR takes the next register for the high half of the address.
+//!
+//! ## References
+//! - [OSDev PCI, Base Address Registers](http://wiki.osdev.org/PCI#Base_Address_Registers)
+
+use core::fmt;
+
+use super::config::{self, PciAddress};
+
+/// Offset of the first BAR on the configuration space
+const BAR_OFFSET: u16 = 0x10;
+
+/// The BAR is on the I/O space
+const BAR_IO: u32 = 1 << 0;
+/// Memory BAR type, bits 1-2
+const BAR_MEMORY_TYPE: u32 = 0x3 << 1;
+/// Memory BAR type: 64 bits address
+const BAR_MEMORY_64: u32 = 0x2 << 1;
+/// The memory can be prefetched
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// Decoded Base Address Register
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bar {
+    /// Not implemented, or the high half of a 64 bits BAR
+    None,
+    /// I/O port range
+    Io { port: u16, size: u32 },
+    /// Memory range
+    Memory { address: u64, size: u64, prefetchable: bool, wide: bool }
+}
+
+impl Bar {
+    /// Decode a BAR from its value and the value it reads after writing all ones.
+    ///
+    /// `high` and `high_mask` are the same for the next register, they are only used when `low` is
+    /// a 64 bits memory BAR.
+    pub fn decode(low: u32, high: u32, low_mask: u32, high_mask: u32) -> Bar {
+        if low & BAR_IO == BAR_IO {
+            let mask = low_mask & !0x3;
+            if mask == 0 {
+                return Bar::None;
+            }
+
+            // the upper half may read as zeros, the I/O space has only 16 bits anyway
+            let size = (!(mask | 0xffff_0000)).wrapping_add(1);
+            return Bar::Io {
+                port: (low & !0x3) as u16,
+                size: size
+            };
+        }
+
+        let wide = low & BAR_MEMORY_TYPE == BAR_MEMORY_64;
+        let (address, mask) = if wide {
+            ((low & !0xf) as u64 | (high as u64) << 32, (low_mask & !0xf) as u64 | (high_mask as u64) << 32)
+        } else {
+            ((low & !0xf) as u64, (low_mask & !0xf) as u64 | 0xffff_ffff_0000_0000)
+        };
+        if mask == 0 || mask == 0xffff_ffff_0000_0000 {
+            return Bar::None;
+        }
+
+        Bar::Memory {
+            address: address,
+            size: (!mask).wrapping_add(1),
+            prefetchable: low & BAR_PREFETCHABLE == BAR_PREFETCHABLE,
+            wide: wide
+        }
+    }
+
+    /// Read and size the BAR `index` of a function.
+    ///
+    /// The function must not decode its I/O and memory spaces while this runs, see
+    /// `Device::probe`.
+    pub fn read(address: PciAddress, index: usize) -> Bar {
+        let (low, low_mask) = size_register(address, index);
+
+        let wide = low & BAR_IO == 0 && low & BAR_MEMORY_TYPE == BAR_MEMORY_64;
+        let (high, high_mask) = if wide { size_register(address, index + 1) } else { (0, 0) };
+
+        Bar::decode(low, high, low_mask, high_mask)
+    }
+
+    /// Check if the BAR takes the next register too.
+    pub fn is_wide(&self) -> bool {
+        match *self {
+            Bar::Memory { wide, .. } => wide,
+            _ => false
+        }
+    }
+}
+
+/// Read a BAR register, and what it reads after writing all ones on it. It's restored after.
+fn size_register(address: PciAddress, index: usize) -> (u32, u32) {
+    let offset = BAR_OFFSET + index as u16 * 4;
+
+    let value = config::read(address, offset);
+    config::write(address, offset, 0xffff_ffff);
+    let mask = config::read(address, offset);
+    config::write(address, offset, value);
+
+    (value, mask)
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bar::None => write!(f, "none"),
+            Bar::Io { port, size } => write!(f, "io {:#x} {:#x}", port, size),
+            Bar::Memory { address, size, prefetchable, wide } => {
+                write!(f, "memory{} {:#x} {:#x}{}", if wide { "64" } else { "32" }, address, size,
+                       if prefetchable { " prefetchable" } else { "" })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_io() {
+        // 32 ports at 0xc040, the upper half reads as zeros
+        assert_eq!(Bar::decode(0xc041, 0, 0x0000_ffe1, 0), Bar::Io { port: 0xc040, size: 32 });
+    }
+
+    #[test]
+    fn decode_memory_32() {
+        assert_eq!(Bar::decode(0xfebd_5000, 0, 0xffff_f000, 0),
+                   Bar::Memory { address: 0xfebd_5000, size: 0x1000, prefetchable: false, wide: false });
+    }
+
+    #[test]
+    fn decode_memory_64_prefetchable() {
+        // 16 MiB at 0x8_0000_0000
+        assert_eq!(Bar::decode(0x0000_000c, 0x8, 0xff00_000c, 0xffff_ffff),
+                   Bar::Memory { address: 0x8_0000_0000, size: 0x100_0000, prefetchable: true, wide: true });
+    }
+
+    #[test]
+    fn decode_unimplemented() {
+        assert_eq!(Bar::decode(0, 0, 0, 0), Bar::None);
+        assert!(!Bar::None.is_wide());
+    }
+}
@@ -0,0 +1,232 @@
+//! Configuration space access
+//!
+//! The configuration space of a function is read with the legacy mechanism, the `0xcf8` address
+//! port and the `0xcfc` data port, which only reaches its first 256 bytes. When the ACPI MCFG
+//! table describes an ECAM area for the bus, the whole 4 KiB are memory mapped instead.
+
+use core::fmt;
+use core::ptr;
+use spin::{Mutex, Once};
+
+use x86_64::instructions::port::{inl, outl};
+
+use acpi::ACPI_TABLE;
+use memory::{Frame, MemoryController};
+use memory::paging::{entry, Page, PhysicalAddress, VirtualAddress};
+
+/// Configuration address port
+const CONFIG_ADDRESS: u16 = 0xcf8;
+/// Configuration data port
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Enable bit of the configuration address
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+/// Size of the configuration space reachable with the legacy mechanism
+pub const LEGACY_SIZE: u16 = 256;
+/// Size of the configuration space of a PCI Express function
+pub const EXTENDED_SIZE: u16 = 4096;
+
+/// Location of a function
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    /// Device number, from 0 to 31
+    pub device: u8,
+    /// Function number, from 0 to 7
+    pub function: u8
+}
+
+impl PciAddress {
+    pub fn new(bus: u8, device: u8, function: u8) -> PciAddress {
+        PciAddress {
+            bus: bus,
+            device: device & 0x1f,
+            function: function & 0x07
+        }
+    }
+
+    /// Parse an address written as `bus:device.function`, with hexadecimal numbers.
+    pub fn parse(text: &str) -> Option<PciAddress> {
+        let mut parts = text.splitn(2, ':');
+        let bus = parts.next().and_then(|bus| u8::from_str_radix(bus, 16).ok());
+        let mut parts = match parts.next() {
+            Some(rest) => rest.splitn(2, '.'),
+            None => return None
+        };
+        let device = parts.next().and_then(|device| u8::from_str_radix(device, 16).ok());
+        let function = parts.next().and_then(|function| u8::from_str_radix(function, 16).ok());
+
+        match (bus, device, function) {
+            (Some(bus), Some(device), Some(function)) if device < 32 && function < 8 => {
+                Some(PciAddress::new(bus, device, function))
+            },
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{:x}", self.bus, self.device, self.function)
+    }
+}
+
+/// ECAM area of the segment group 0
+#[derive(Copy, Clone, Debug)]
+struct Ecam {
+    /// Physical address of the bus 0
+    base: PhysicalAddress,
+    /// Virtual address of the bus 0 on the kernel device area, the functions are mapped there as
+    /// they're probed
+    window: VirtualAddress,
+    start_bus: u8,
+    end_bus: u8
+}
+
+impl Ecam {
+    /// Get the offset of the configuration space of a function from the bus 0, if the area decodes
+    /// its bus.
+    fn offset(&self, address: PciAddress) -> Option<usize> {
+        if address.bus < self.start_bus || address.bus > self.end_bus {
+            return None;
+        }
+
+        Some((address.bus as usize) << 20 | (address.device as usize) << 15 | (address.function as usize) << 12)
+    }
+}
+
+/// ECAM area, `None` when the legacy mechanism is used
+static ECAM: Once<Option<Ecam>> = Once::new();
+
+/// The legacy mechanism takes two port accesses, that must not be interleaved
+static LEGACY_LOCK: Mutex<()> = Mutex::new(());
+
+/// Look for the ECAM area on the MCFG table, and reserve room for it on the kernel device area.
+///
+/// This must be called after `acpi::init`. Only the segment group 0 is used, the legacy mechanism
+/// can't reach the others anyway.
+pub fn init(memory_controller: &mut MemoryController) {
+    ECAM.call_once(|| {
+        let acpi_table = ACPI_TABLE.lock();
+        let entry = match acpi_table.mcfg.as_ref()
+            .and_then(|mcfg| mcfg.entries.iter().find(|entry| entry.segment == 0).cloned()) {
+            Some(entry) => entry,
+            None => return None
+        };
+
+        // the window starts at the bus 0, like the area itself
+        match memory_controller.reserve_device(((entry.end_bus as usize) + 1) << 20) {
+            Some(window) => Some(Ecam {
+                base: entry.base_address as PhysicalAddress,
+                window: window,
+                start_bus: entry.start_bus,
+                end_bus: entry.end_bus
+            }),
+            None => {
+                kwarn!("no room to map the ECAM area, using the legacy configuration mechanism");
+                None
+            }
+        }
+    });
+}
+
+/// Map the configuration space of a function, before its first access.
+///
+/// Only the functions that are probed are mapped, the whole ECAM area is far too large.
+pub fn map(memory_controller: &mut MemoryController, address: PciAddress) {
+    let (ecam, offset) = match ecam().and_then(|ecam| ecam.offset(address).map(|offset| (ecam, offset))) {
+        Some(location) => location,
+        None => return
+    };
+
+    let page = Page::containing_address(ecam.window + offset);
+    if memory_controller.active_table.translate_page(page).is_none() {
+        let frame = Frame::containing_address(ecam.base + offset);
+        memory_controller.map_to(page, frame, entry::PRESENT | entry::WRITABLE | entry::NO_CACHE | entry::NO_EXECUTE);
+    }
+}
+
+/// Get the ECAM area, if there is one.
+fn ecam() -> Option<&'static Ecam> {
+    ECAM.try().and_then(|ecam| ecam.as_ref())
+}
+
+/// Get the virtual address of the ECAM configuration space of a function.
+fn ecam_address(address: PciAddress) -> Option<VirtualAddress> {
+    ecam().and_then(|ecam| ecam.offset(address).map(|offset| ecam.window + offset))
+}
+
+/// Get the size of the configuration space that can be accessed for a function.
+pub fn size(address: PciAddress) -> u16 {
+    if ecam_address(address).is_some() { EXTENDED_SIZE } else { LEGACY_SIZE }
+}
+
+/// Get the legacy configuration address of a register.
+fn legacy_address(address: PciAddress, offset: u16) -> u32 {
+    CONFIG_ENABLE | (address.bus as u32) << 16 | (address.device as u32) << 11 |
+        (address.function as u32) << 8 | (offset as u32 & 0xfc)
+}
+
+/// Read a 32 bits register, `offset` is rounded down to a multiple of 4.
+///
+/// Registers out of the accessible configuration space read as all ones, like a missing function.
+/// With ECAM, the function must have been mapped by `map`, as all the probed ones are.
+pub fn read(address: PciAddress, offset: u16) -> u32 {
+    let offset = offset & !3;
+    if offset >= size(address) {
+        return 0xffff_ffff;
+    }
+
+    match ecam_address(address) {
+        Some(base) => unsafe { ptr::read_volatile((base + offset as usize) as *const u32) },
+        None => {
+            let _lock = LEGACY_LOCK.lock();
+            unsafe {
+                outl(CONFIG_ADDRESS, legacy_address(address, offset));
+                inl(CONFIG_DATA)
+            }
+        }
+    }
+}
+
+/// Write a 32 bits register, `offset` is rounded down to a multiple of 4.
+///
+/// Writes out of the accessible configuration space are ignored.
+pub fn write(address: PciAddress, offset: u16, value: u32) {
+    let offset = offset & !3;
+    if offset >= size(address) {
+        return;
+    }
+
+    match ecam_address(address) {
+        Some(base) => unsafe { ptr::write_volatile((base + offset as usize) as *mut u32, value) },
+        None => {
+            let _lock = LEGACY_LOCK.lock();
+            unsafe {
+                outl(CONFIG_ADDRESS, legacy_address(address, offset));
+                outl(CONFIG_DATA, value);
+            }
+        }
+    }
+}
+
+/// Read a 16 bits register, `offset` is rounded down to a multiple of 2.
+pub fn read_u16(address: PciAddress, offset: u16) -> u16 {
+    (read(address, offset) >> ((offset & 2) * 8)) as u16
+}
+
+/// Read an 8 bits register.
+pub fn read_u8(address: PciAddress, offset: u16) -> u8 {
+    (read(address, offset) >> ((offset & 3) * 8)) as u8
+}
+
+/// Write a 16 bits register, `offset` is rounded down to a multiple of 2.
+///
+/// The other half of the 32 bits register is written back with the value it's read with, so this
+/// must not be used next to registers with write one to clear bits.
+pub fn write_u16(address: PciAddress, offset: u16, value: u16) {
+    let shift = (offset & 2) * 8;
+    let register = read(address, offset) & !(0xffff << shift);
+    write(address, offset, register | (value as u32) << shift);
+}
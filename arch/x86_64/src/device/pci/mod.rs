@@ -0,0 +1,306 @@
+//! # PCI
+//!
+//! Enumeration of the PCI functions. The buses are scanned from the host bridges on the bus 0,
+//! following the PCI-to-PCI bridges to their secondary buses. The functions found are kept with
+//! their identification, their decoded BARs and their capabilities, for the drivers to look for
+//! their hardware. Their interrupts can be sent to a Local APIC as messages, see `msi`.
+//!
+//! ## References
+//! - [OSDev PCI](http://wiki.osdev.org/PCI)
+
+use collections::Vec;
+use spin::Once;
+
+use memory::MemoryController;
+
+pub use self::bar::Bar;
+pub use self::config::PciAddress;
+
+/// Base Address Registers
+pub mod bar;
+
+/// Configuration space access
+pub mod config;
+
+/// Message Signaled Interrupts
+pub mod msi;
+
+/// Configuration space registers
+const VENDOR_ID: u16 = 0x00;
+const DEVICE_ID: u16 = 0x02;
+const COMMAND: u16 = 0x04;
+const STATUS: u16 = 0x06;
+const REVISION: u16 = 0x08;
+const INTERFACE: u16 = 0x09;
+const SUBCLASS: u16 = 0x0a;
+const CLASS: u16 = 0x0b;
+const HEADER_TYPE: u16 = 0x0e;
+const SECONDARY_BUS: u16 = 0x19;
+const CAPABILITIES_POINTER: u16 = 0x34;
+const INTERRUPT_LINE: u16 = 0x3c;
+const INTERRUPT_PIN: u16 = 0x3d;
+
+/// Command: respond to the I/O space accesses
+pub const COMMAND_IO: u16 = 1 << 0;
+/// Command: respond to the memory space accesses
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+/// Command: the function can start DMA transfers
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+/// Command: the function can't signal the legacy INTx interrupts
+pub const COMMAND_INTERRUPT_DISABLE: u16 = 1 << 10;
+
+/// Status: the function has a capabilities list
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// Header type: the device has more than one function
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+/// Header type: general device
+const HEADER_GENERAL: u8 = 0x00;
+/// Header type: PCI-to-PCI bridge
+const HEADER_BRIDGE: u8 = 0x01;
+
+/// Vendor id read on a missing function
+const VENDOR_NONE: u16 = 0xffff;
+
+/// Capability: power management
+pub const CAPABILITY_POWER_MANAGEMENT: u8 = 0x01;
+/// Capability: Message Signaled Interrupts
+pub const CAPABILITY_MSI: u8 = 0x05;
+/// Capability: vendor specific
+pub const CAPABILITY_VENDOR: u8 = 0x09;
+/// Capability: PCI Express
+pub const CAPABILITY_PCI_EXPRESS: u8 = 0x10;
+/// Capability: MSI-X
+pub const CAPABILITY_MSIX: u8 = 0x11;
+
+/// Maximum number of capabilities walked, so a looping list can't hang the enumeration
+const MAX_CAPABILITIES: usize = 48;
+
+/// Number of devices on a bus
+const DEVICES_PER_BUS: u8 = 32;
+/// Number of functions of a device
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+
+/// Entry of the capabilities list
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset of the capability on the configuration space
+    pub offset: u8
+}
+
+/// PCI function
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    /// Programming interface
+    pub interface: u8,
+    pub revision: u8,
+    /// Header type, without the multifunction bit
+    pub header_type: u8,
+    /// Decoded BARs, 6 for a general device and 2 for a bridge
+    pub bars: Vec<Bar>,
+    /// Legacy IRQ routed by the firmware, 0xff when there is none
+    pub interrupt_line: u8,
+    /// INTx pin, from 1 for INTA, 0 when the function doesn't use one
+    pub interrupt_pin: u8,
+    pub capabilities: Vec<Capability>
+}
+
+impl Device {
+    /// Read a function, or `None` when there is none at `address`.
+    ///
+    /// The BARs are sized with the I/O and memory decoding disabled, so nothing answers at the
+    /// addresses they hold while they're all ones.
+    fn probe(address: PciAddress) -> Option<Device> {
+        let vendor_id = config::read_u16(address, VENDOR_ID);
+        if vendor_id == VENDOR_NONE {
+            return None;
+        }
+
+        let header_type = config::read_u8(address, HEADER_TYPE) & !HEADER_MULTIFUNCTION;
+        let bar_count = match header_type {
+            HEADER_GENERAL => 6,
+            HEADER_BRIDGE => 2,
+            _ => 0
+        };
+
+        // the status register, next to the command one, has write one to clear bits
+        let command = config::read_u16(address, COMMAND);
+        config::write(address, COMMAND, (command & !(COMMAND_IO | COMMAND_MEMORY)) as u32);
+
+        let mut bars = Vec::with_capacity(bar_count);
+        while bars.len() < bar_count {
+            let bar = Bar::read(address, bars.len());
+            bars.push(bar);
+            if bar.is_wide() && bars.len() < bar_count {
+                bars.push(Bar::None);
+            }
+        }
+
+        config::write(address, COMMAND, command as u32);
+
+        Some(Device {
+            address: address,
+            vendor_id: vendor_id,
+            device_id: config::read_u16(address, DEVICE_ID),
+            class: config::read_u8(address, CLASS),
+            subclass: config::read_u8(address, SUBCLASS),
+            interface: config::read_u8(address, INTERFACE),
+            revision: config::read_u8(address, REVISION),
+            header_type: header_type,
+            bars: bars,
+            interrupt_line: config::read_u8(address, INTERRUPT_LINE),
+            interrupt_pin: config::read_u8(address, INTERRUPT_PIN),
+            capabilities: read_capabilities(address)
+        })
+    }
+
+    /// Find a capability.
+    ///
+    /// ## Returns
+    /// The offset of the first capability with that id.
+    pub fn capability(&self, id: u8) -> Option<u8> {
+        self.capabilities.iter().find(|capability| capability.id == id).map(|capability| capability.offset)
+    }
+
+    /// Read the command register.
+    pub fn command(&self) -> u16 {
+        config::read_u16(self.address, COMMAND)
+    }
+
+    /// Write the command register, the status register is left as it is.
+    pub fn set_command(&self, command: u16) {
+        config::write(self.address, COMMAND, command as u32);
+    }
+}
+
+/// Walk the capabilities list of a function.
+fn read_capabilities(address: PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if config::read_u16(address, STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+
+    // the bottom two bits of the pointers are reserved
+    let mut offset = config::read_u8(address, CAPABILITIES_POINTER) & !0x3;
+    while offset >= 0x40 && capabilities.len() < MAX_CAPABILITIES {
+        let header = config::read_u16(address, offset as u16);
+        capabilities.push(Capability {
+            id: header as u8,
+            offset: offset
+        });
+        offset = (header >> 8) as u8 & !0x3;
+    }
+
+    capabilities
+}
+
+/// Functions found by `init`
+static DEVICES: Once<Vec<Device>> = Once::new();
+
+/// Get the functions found on the buses.
+pub fn devices() -> &'static [Device] {
+    DEVICES.try().map(|devices| &devices[..]).unwrap_or(&[])
+}
+
+/// Find a function by its address.
+pub fn find(address: PciAddress) -> Option<&'static Device> {
+    devices().iter().find(|device| device.address == address)
+}
+
+/// Scans the buses from the bus 0.
+struct Scanner<'a> {
+    memory_controller: &'a mut MemoryController,
+    devices: Vec<Device>,
+    /// Buses already scanned, so a misconfigured bridge can't make a loop
+    scanned: [bool; 256]
+}
+
+impl<'a> Scanner<'a> {
+    /// Probe a function, mapping it first.
+    fn probe(&mut self, address: PciAddress) -> Option<Device> {
+        config::map(self.memory_controller, address);
+        Device::probe(address)
+    }
+
+    fn scan_bus(&mut self, bus: u8) {
+        if self.scanned[bus as usize] {
+            return;
+        }
+        self.scanned[bus as usize] = true;
+
+        for device in 0..DEVICES_PER_BUS {
+            let first = match self.probe(PciAddress::new(bus, device, 0)) {
+                Some(first) => first,
+                None => continue
+            };
+
+            let multifunction = config::read_u8(first.address, HEADER_TYPE) & HEADER_MULTIFUNCTION != 0;
+            self.add(first);
+
+            if multifunction {
+                for function in 1..FUNCTIONS_PER_DEVICE {
+                    if let Some(other) = self.probe(PciAddress::new(bus, device, function)) {
+                        self.add(other);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keep a function, and scan the bus behind it when it's a bridge.
+    fn add(&mut self, device: Device) {
+        let secondary_bus = if device.header_type == HEADER_BRIDGE {
+            Some(config::read_u8(device.address, SECONDARY_BUS))
+        } else {
+            None
+        };
+
+        self.devices.push(device);
+
+        if let Some(bus) = secondary_bus {
+            self.scan_bus(bus);
+        }
+    }
+}
+
+/// Find all the PCI functions.
+///
+/// This must be called after `acpi::init`, so the ECAM area on the MCFG table is used.
+pub fn init(memory_controller: &mut MemoryController) {
+    config::init(memory_controller);
+
+    DEVICES.call_once(|| {
+        let mut scanner = Scanner {
+            memory_controller: memory_controller,
+            devices: Vec::new(),
+            scanned: [false; 256]
+        };
+
+        // with several host bridges, the function `n` of the device 0 is the bridge of the bus `n`
+        scanner.probe(PciAddress::new(0, 0, 0));
+        let multifunction = config::read_u8(PciAddress::new(0, 0, 0), HEADER_TYPE) & HEADER_MULTIFUNCTION != 0;
+        if multifunction {
+            for function in 0..FUNCTIONS_PER_DEVICE {
+                if scanner.probe(PciAddress::new(0, 0, function)).is_some() {
+                    scanner.scan_bus(function);
+                }
+            }
+        } else {
+            scanner.scan_bus(0);
+        }
+
+        scanner.devices.sort_by_key(|device| device.address);
+        scanner.devices
+    });
+
+    for device in devices() {
+        kinfo!("{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x}", device.address, device.vendor_id,
+               device.device_id, device.class, device.subclass, device.interface);
+    }
+}
@@ -0,0 +1,314 @@
+//! Message Signaled Interrupts (MSI and MSI-X)
+//!
+//! Instead of asserting an INTx pin, the function writes a message to an address decoded by the
+//! Local APICs: the address selects the destination APIC and the data selects the vector. MSI
+//! gives a function one message, programmed on its capability. MSI-X gives it a table of messages
+//! on one of its memory BARs, each with its own mask.
+//!
+//! The messages use the fixed delivery mode and are edge triggered, so they never need to be
+//! masked while a driver handles its device. The command register of a function is saved when its
+//! first message is enabled, and restored when its last one is disabled.
+//!
+//! ## References
+//! - [OSDev PCI, Message Signaled Interrupts](http://wiki.osdev.org/PCI#Message_Signaled_Interrupts)
+
+use collections::BTreeMap;
+use core::ptr;
+use spin::Mutex;
+
+use memory::MemoryController;
+use memory::paging::{entry, PhysicalAddress, VirtualAddress};
+
+use super::{Bar, Device, CAPABILITY_MSI, CAPABILITY_MSIX, COMMAND_BUS_MASTER, COMMAND_INTERRUPT_DISABLE, COMMAND_MEMORY};
+use super::config::{self, PciAddress};
+
+/// Base of the message address, decoded by the Local APICs
+const MESSAGE_ADDRESS: u64 = 0xfee0_0000;
+/// First bit of the destination APIC id on the message address
+const MESSAGE_DESTINATION_SHIFT: u64 = 12;
+
+/// MSI message control: enabled
+const MSI_ENABLE: u16 = 1 << 0;
+/// MSI message control: number of enabled messages, as a power of two, bits 4-6
+const MSI_MULTIPLE_ENABLE: u16 = 0x7 << 4;
+/// MSI message control: the message address has 64 bits
+const MSI_64: u16 = 1 << 7;
+/// MSI message control: the messages can be masked
+const MSI_PER_VECTOR_MASK: u16 = 1 << 8;
+
+/// MSI-X message control: size of the table minus one, bits 0-10
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+/// MSI-X message control: all the messages are masked
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+/// MSI-X message control: enabled
+const MSIX_ENABLE: u16 = 1 << 15;
+
+/// MSI-X table location: BAR index, bits 0-2, the rest is the offset on the BAR
+const MSIX_BIR: u32 = 0x7;
+/// Size of a MSI-X table entry
+const MSIX_ENTRY_SIZE: usize = 16;
+/// MSI-X vector control: masked
+const MSIX_MASKED: u32 = 1 << 0;
+
+lazy_static! {
+    /// Command register of each function with enabled messages, as it was before `use_messages`
+    /// changed it, and the number of messages enabled on the function
+    static ref COMMANDS: Mutex<BTreeMap<PciAddress, (u16, usize)>> = Mutex::new(BTreeMap::new());
+
+    /// Virtual address of the MSI-X table of each function, on the kernel device area. The tables
+    /// stay mapped once a message was enabled on them.
+    static ref MSIX_TABLES: Mutex<BTreeMap<PciAddress, VirtualAddress>> = Mutex::new(BTreeMap::new());
+}
+
+/// Get the message address that targets a Local APIC.
+pub fn message_address(apic_id: u8) -> u64 {
+    MESSAGE_ADDRESS | (apic_id as u64) << MESSAGE_DESTINATION_SHIFT
+}
+
+/// Get the message data of a vector, delivered as a fixed and edge triggered interrupt.
+pub fn message_data(vector: u8) -> u32 {
+    vector as u32
+}
+
+/// Get the offsets of the data and mask registers on a MSI capability, from its message control.
+fn msi_registers(offset: u16, control: u16) -> (u16, Option<u16>) {
+    let data = if control & MSI_64 == MSI_64 { offset + 0x0c } else { offset + 0x08 };
+    let mask = if control & MSI_PER_VECTOR_MASK == MSI_PER_VECTOR_MASK { Some(data + 4) } else { None };
+    (data, mask)
+}
+
+/// Split the MSI-X table location register into the BAR index and the offset on that BAR.
+fn msix_location(register: u32) -> (usize, u64) {
+    ((register & MSIX_BIR) as usize, (register & !MSIX_BIR) as u64)
+}
+
+/// Let the function write its messages, without its INTx pin, for a message being enabled. The
+/// `extra` bits of the command register are set too.
+fn use_messages(device: &Device, extra: u16) {
+    let mut commands = COMMANDS.lock();
+    let command = device.command();
+
+    let saved = commands.entry(device.address).or_insert((command, 0));
+    saved.1 += 1;
+    device.set_command(command | COMMAND_BUS_MASTER | COMMAND_INTERRUPT_DISABLE | extra);
+}
+
+/// Give the function its command register back, once its last message is disabled. `last` runs
+/// right before, so it can turn the messages off while no other message can be enabled.
+fn release_messages<F: FnOnce()>(device: &Device, last: F) {
+    let mut commands = COMMANDS.lock();
+
+    let command = match commands.get_mut(&device.address) {
+        Some(saved) => {
+            saved.1 -= 1;
+            if saved.1 == 0 { Some(saved.0) } else { None }
+        },
+        None => None
+    };
+    if let Some(command) = command {
+        commands.remove(&device.address);
+        last();
+        device.set_command(command);
+    }
+}
+
+/// Send the MSI of a function to a vector of a Local APIC, and enable it.
+///
+/// Only one message is enabled, even if the function asks for more.
+///
+/// ## Returns
+/// `false` if the function has no MSI capability or its MSI is already enabled.
+pub fn enable_msi(device: &Device, apic_id: u8, vector: u8) -> bool {
+    let offset = match device.capability(CAPABILITY_MSI) {
+        Some(offset) => offset as u16,
+        None => return false
+    };
+
+    let control = config::read_u16(device.address, offset + 2);
+    if control & MSI_ENABLE == MSI_ENABLE {
+        return false;
+    }
+
+    let address = message_address(apic_id);
+    let (data, mask) = msi_registers(offset, control);
+    config::write(device.address, offset + 4, address as u32);
+    if control & MSI_64 == MSI_64 {
+        config::write(device.address, offset + 8, (address >> 32) as u32);
+    }
+    config::write_u16(device.address, data, message_data(vector) as u16);
+    if let Some(mask) = mask {
+        let bits = config::read(device.address, mask);
+        config::write(device.address, mask, bits & !1);
+    }
+
+    use_messages(device, 0);
+    config::write_u16(device.address, offset + 2, control & !MSI_MULTIPLE_ENABLE | MSI_ENABLE);
+
+    true
+}
+
+/// Disable the MSI of a function.
+pub fn disable_msi(device: &Device) {
+    if let Some(offset) = device.capability(CAPABILITY_MSI) {
+        let offset = offset as u16;
+        let control = config::read_u16(device.address, offset + 2);
+        if control & MSI_ENABLE == MSI_ENABLE {
+            config::write_u16(device.address, offset + 2, control & !MSI_ENABLE);
+            release_messages(device, || ());
+        }
+    }
+}
+
+/// Get the number of entries of the MSI-X table of a function, if it has one.
+pub fn msix_count(device: &Device) -> Option<u16> {
+    device.capability(CAPABILITY_MSIX)
+        .map(|offset| (config::read_u16(device.address, offset as u16 + 2) & MSIX_TABLE_SIZE) + 1)
+}
+
+/// Find the MSI-X table of a function, on one of its memory BARs.
+///
+/// ## Returns
+/// The offset of the MSI-X capability, the physical address of the table and its number of entries.
+fn msix_table(device: &Device) -> Option<(u16, PhysicalAddress, u16)> {
+    let offset = match device.capability(CAPABILITY_MSIX) {
+        Some(offset) => offset as u16,
+        None => return None
+    };
+    let count = (config::read_u16(device.address, offset + 2) & MSIX_TABLE_SIZE) + 1;
+
+    let (bar, table) = msix_location(config::read(device.address, offset + 4));
+    match device.bars.get(bar) {
+        Some(&Bar::Memory { address, size, .. }) if table + count as u64 * MSIX_ENTRY_SIZE as u64 <= size => {
+            Some((offset, (address + table) as PhysicalAddress, count))
+        },
+        _ => None
+    }
+}
+
+/// Map the MSI-X table of a function on the kernel device area, unless it's already mapped.
+///
+/// ## Returns
+/// `false` if the function has no MSI-X table or there is no room to map it.
+fn map_msix_table(memory_controller: &mut MemoryController, device: &Device) -> bool {
+    let mut tables = MSIX_TABLES.lock();
+    if tables.contains_key(&device.address) {
+        return true;
+    }
+
+    let (address, count) = match msix_table(device) {
+        Some((_, address, count)) => (address, count),
+        None => return false
+    };
+    match memory_controller.map_device(address, count as usize * MSIX_ENTRY_SIZE,
+                                       entry::PRESENT | entry::WRITABLE | entry::NO_CACHE | entry::NO_EXECUTE) {
+        Some(table) => {
+            tables.insert(device.address, table);
+            true
+        },
+        None => false
+    }
+}
+
+/// Find an entry of the MSI-X table of a function, once the table is mapped by `map_msix_table`.
+///
+/// An entry holds the message address, on two registers, the message data and the vector control.
+///
+/// ## Returns
+/// The offset of the MSI-X capability and the virtual address of the entry.
+fn msix_entry(device: &Device, index: u16) -> Option<(u16, VirtualAddress)> {
+    let (offset, count) = match msix_table(device) {
+        Some((offset, _, count)) => (offset, count),
+        None => return None
+    };
+    if index >= count {
+        return None;
+    }
+
+    MSIX_TABLES.lock().get(&device.address)
+        .map(|&table| (offset, table + index as usize * MSIX_ENTRY_SIZE))
+}
+
+/// Send an entry of the MSI-X table of a function to a vector of a Local APIC, and unmask it.
+///
+/// MSI-X is enabled on the function the first time, with all the other entries left masked. The
+/// function decodes its memory BARs from then on, since the table is on one of them.
+///
+/// ## Returns
+/// `false` if the function has no such entry or it's already unmasked.
+pub fn enable_msix(memory_controller: &mut MemoryController, device: &Device, index: u16, apic_id: u8,
+                   vector: u8) -> bool {
+    if !map_msix_table(memory_controller, device) {
+        return false;
+    }
+    let (offset, entry_address) = match msix_entry(device, index) {
+        Some(entry) => entry,
+        None => return false
+    };
+
+    // the table can only be accessed while the function decodes memory
+    use_messages(device, COMMAND_MEMORY);
+
+    let registers = entry_address as *mut u32;
+    let control = unsafe { ptr::read_volatile(registers.offset(3)) };
+    if control & MSIX_MASKED == 0 {
+        release_messages(device, || ());
+        return false;
+    }
+
+    let address = message_address(apic_id);
+    unsafe {
+        ptr::write_volatile(registers, address as u32);
+        ptr::write_volatile(registers.offset(1), (address >> 32) as u32);
+        ptr::write_volatile(registers.offset(2), message_data(vector));
+    }
+
+    let message_control = config::read_u16(device.address, offset + 2);
+    config::write_u16(device.address, offset + 2, message_control & !MSIX_FUNCTION_MASK | MSIX_ENABLE);
+
+    unsafe { ptr::write_volatile(registers.offset(3), control & !MSIX_MASKED); }
+
+    true
+}
+
+/// Mask an entry of the MSI-X table of a function. MSI-X is disabled on the function with its last
+/// entry.
+///
+/// The entry must have been enabled by `enable_msix`, so its table is mapped.
+pub fn disable_msix(device: &Device, index: u16) {
+    if let Some((offset, entry_address)) = msix_entry(device, index) {
+        let vector_control = unsafe { (entry_address as *mut u32).offset(3) };
+        let control = unsafe { ptr::read_volatile(vector_control) };
+        if control & MSIX_MASKED == 0 {
+            unsafe { ptr::write_volatile(vector_control, control | MSIX_MASKED); }
+            release_messages(device, || {
+                let message_control = config::read_u16(device.address, offset + 2);
+                config::write_u16(device.address, offset + 2, message_control & !MSIX_ENABLE);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_targets_apic() {
+        assert_eq!(message_address(0), 0xfee0_0000);
+        assert_eq!(message_address(3), 0xfee0_3000);
+        assert_eq!(message_data(0x50), 0x50);
+    }
+
+    #[test]
+    fn msi_register_layout() {
+        assert_eq!(msi_registers(0x50, 0), (0x58, None));
+        assert_eq!(msi_registers(0x50, MSI_64), (0x5c, None));
+        assert_eq!(msi_registers(0x50, MSI_64 | MSI_PER_VECTOR_MASK), (0x5c, Some(0x60)));
+    }
+
+    #[test]
+    fn msix_table_location() {
+        // table at 0x2000 on the BAR 4
+        assert_eq!(msix_location(0x2004), (4, 0x2000));
+    }
+}
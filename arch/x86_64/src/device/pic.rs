@@ -0,0 +1,61 @@
+//! # Legacy 8259 Programmable Interrupt Controller (PIC)
+//!
+//! The IRQs are delivered by the I/O APIC, so the two PICs are remapped out of the way of the
+//! exception and IRQ vectors and all their lines are masked. A masked PIC can still raise spurious
+//! interrupts, those land on `PIC_OFFSET + 7` and `PIC_OFFSET + 15` and are ignored.
+//!
+//! ## References
+//! - [OSDev 8259 PIC](http://wiki.osdev.org/8259_PIC)
+
+use x86_64::instructions::port::outb;
+
+use interrupts::PIC_OFFSET;
+
+/// Master PIC command port
+const MASTER_COMMAND: u16 = 0x20;
+/// Master PIC data port
+const MASTER_DATA: u16 = 0x21;
+/// Slave PIC command port
+const SLAVE_COMMAND: u16 = 0xA0;
+/// Slave PIC data port
+const SLAVE_DATA: u16 = 0xA1;
+
+/// ICW1: initialization, ICW4 will be sent
+const ICW1_INIT: u8 = 0x11;
+/// ICW4: 8086 mode
+const ICW4_8086: u8 = 0x01;
+
+/// Give the PIC some time to process the previous command.
+unsafe fn io_wait() {
+    outb(0x80, 0);
+}
+
+/// Remap and mask both PICs.
+pub unsafe fn init() {
+    // start the initialization sequence
+    outb(MASTER_COMMAND, ICW1_INIT);
+    io_wait();
+    outb(SLAVE_COMMAND, ICW1_INIT);
+    io_wait();
+
+    // vector offsets
+    outb(MASTER_DATA, PIC_OFFSET);
+    io_wait();
+    outb(SLAVE_DATA, PIC_OFFSET + 8);
+    io_wait();
+
+    // the slave is connected to the IRQ 2 of the master
+    outb(MASTER_DATA, 1 << 2);
+    io_wait();
+    outb(SLAVE_DATA, 2);
+    io_wait();
+
+    outb(MASTER_DATA, ICW4_8086);
+    io_wait();
+    outb(SLAVE_DATA, ICW4_8086);
+    io_wait();
+
+    // mask all the lines
+    outb(MASTER_DATA, 0xFF);
+    outb(SLAVE_DATA, 0xFF);
+}
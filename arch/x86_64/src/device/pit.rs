@@ -0,0 +1,51 @@
+//! # Programmable Interval Timer (PIT)
+//!
+//! Only the channel 2 is used, as a one-shot timer to calibrate the other timers. Its gate and its
+//! output are on the NMI status and control port, so it can be polled without an interrupt.
+//!
+//! ## References
+//! - [OSDev PIT](http://wiki.osdev.org/Programmable_Interval_Timer)
+
+use core::cmp;
+use x86_64::instructions::port::{inb, outb};
+
+/// Frequency of the PIT oscillator, in Hz
+pub const FREQUENCY: u64 = 1_193_182;
+
+/// Channel 2 data port
+const CHANNEL_2: u16 = 0x42;
+/// Mode/command register
+const COMMAND: u16 = 0x43;
+/// NMI status and control port
+const NMI_SC: u16 = 0x61;
+
+/// NMI status and control: gate of the channel 2
+const NMI_SC_GATE: u8 = 1 << 0;
+/// NMI status and control: connect the channel 2 to the speaker
+const NMI_SC_SPEAKER: u8 = 1 << 1;
+/// NMI status and control: output of the channel 2
+const NMI_SC_OUTPUT: u8 = 1 << 5;
+
+/// Command: channel 2, low then high byte, mode 0 (interrupt on terminal count), binary
+const COMMAND_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// Busy wait for the given number of microseconds, up to about 54 ms.
+pub fn wait(microseconds: u64) {
+    let count = cmp::min(FREQUENCY * microseconds / 1_000_000, 0xFFFF) as u16;
+
+    unsafe {
+        // gate low, and the speaker off, while the count is programmed
+        let control = inb(NMI_SC) & !(NMI_SC_GATE | NMI_SC_SPEAKER);
+        outb(NMI_SC, control);
+
+        outb(COMMAND, COMMAND_CHANNEL_2_ONE_SHOT);
+        outb(CHANNEL_2, count as u8);
+        outb(CHANNEL_2, (count >> 8) as u8);
+
+        // the count starts when the gate goes high, and the output goes high when it reaches zero
+        outb(NMI_SC, control | NMI_SC_GATE);
+        while inb(NMI_SC) & NMI_SC_OUTPUT == 0 {}
+
+        outb(NMI_SC, control);
+    }
+}
@@ -0,0 +1,62 @@
+//! # ACPI Power Management Timer
+//!
+//! A free running counter at 3.579545 MHz, read from the I/O port given by the FADT. It's 24 bits
+//! wide, or 32 bits when the FADT `TMR_VAL_EXT` flag is set.
+//!
+//! ## References
+//! - ACPI 5, 4.8.2.1 Power Management Timer
+
+use x86_64::instructions::port::inl;
+
+use acpi::ACPI_TABLE;
+
+/// Frequency of the timer, in Hz
+pub const FREQUENCY: u64 = 3_579_545;
+
+/// FADT flag: the timer is 32 bits wide
+const FADT_TMR_VAL_EXT: u32 = 1 << 8;
+
+/// ACPI Power Management Timer
+pub struct PmTimer {
+    /// I/O port of the counter
+    port: u16,
+    /// Valid bits of the counter
+    mask: u32
+}
+
+impl PmTimer {
+    /// Find the timer on the FADT.
+    ///
+    /// ## Returns
+    /// `None` if there is no FADT, or if it doesn't have a timer.
+    pub fn new() -> Option<PmTimer> {
+        let acpi_table = ACPI_TABLE.lock();
+        let fadt = match acpi_table.fadt {
+            Some(ref fadt) => fadt,
+            None => return None
+        };
+
+        let port = fadt.pm_timer_block;
+        if port == 0 || port > 0xFFFF || fadt.pm_timer_length != 4 {
+            return None;
+        }
+
+        Some(PmTimer {
+            port: port as u16,
+            mask: if fadt.flags & FADT_TMR_VAL_EXT != 0 { 0xFFFF_FFFF } else { 0x00FF_FFFF }
+        })
+    }
+
+    /// Read the counter.
+    pub fn read(&self) -> u32 {
+        unsafe { inl(self.port) & self.mask }
+    }
+
+    /// Busy wait for the given number of microseconds, shorter than a counter wrap around.
+    pub fn wait(&self, microseconds: u64) {
+        let ticks = FREQUENCY * microseconds / 1_000_000;
+        let start = self.read();
+
+        while (self.read().wrapping_sub(start) & self.mask) as u64 < ticks {}
+    }
+}
@@ -0,0 +1,219 @@
+//! # PS/2 controller
+//!
+//! Driver of the i8042 controller and of the keyboard on its first port. The keyboard is asked for
+//! the scancode set 2, which is decoded without the controller translation. When it refuses, the
+//! controller translates to the set 1 instead.
+//!
+//! The received bytes are handled on the IRQ 1 and passed to `keyboard`.
+//!
+//! ## References
+//! - [OSDev "8042" PS/2 Controller](http://wiki.osdev.org/%228042%22_PS/2_Controller)
+
+use x86_64::instructions::port::{inb, outb};
+
+use device::keyboard::{self, ScancodeSet};
+use interrupts::ipi::{self, IpiTarget};
+use interrupts::irq;
+
+/// Data port
+const DATA: u16 = 0x60;
+/// Status register when read, command register when written
+const STATUS_COMMAND: u16 = 0x64;
+
+/// IRQ of the first port
+pub const KEYBOARD_IRQ: u8 = 1;
+
+/// Status: there is a byte to read on the data port
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Status: the controller didn't take the last byte written yet
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Status: the byte to read comes from the second port
+const STATUS_SECOND_PORT: u8 = 1 << 5;
+
+/// Controller commands
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND: u8 = 0xa7;
+const COMMAND_TEST_CONTROLLER: u8 = 0xaa;
+const COMMAND_TEST_FIRST: u8 = 0xab;
+const COMMAND_DISABLE_FIRST: u8 = 0xad;
+const COMMAND_ENABLE_FIRST: u8 = 0xae;
+
+/// Configuration: interrupt on the first port
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+/// Configuration: interrupt on the second port
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+/// Configuration: translation of the first port to the scancode set 1
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// Keyboard commands
+const KEYBOARD_SET_SCANCODE_SET: u8 = 0xf0;
+const KEYBOARD_ENABLE_SCANNING: u8 = 0xf4;
+const KEYBOARD_RESET: u8 = 0xff;
+
+/// Controller and keyboard responses
+const CONTROLLER_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+const KEYBOARD_ACK: u8 = 0xfa;
+const KEYBOARD_RESEND: u8 = 0xfe;
+const KEYBOARD_TEST_PASSED: u8 = 0xaa;
+
+/// Number of status polls before giving up on the controller
+const TIMEOUT: usize = 100_000;
+/// Number of times a keyboard command is sent again when the keyboard asks for it
+const RETRIES: usize = 3;
+
+fn status() -> u8 {
+    unsafe { inb(STATUS_COMMAND) }
+}
+
+/// Wait until the controller can take a byte.
+fn wait_write() -> bool {
+    (0..TIMEOUT).any(|_| status() & STATUS_INPUT_FULL == 0)
+}
+
+/// Wait until there is a byte to read.
+fn wait_read() -> bool {
+    (0..TIMEOUT).any(|_| status() & STATUS_OUTPUT_FULL != 0)
+}
+
+/// Send a command to the controller.
+fn command(command: u8) -> bool {
+    if !wait_write() {
+        return false;
+    }
+    unsafe { outb(STATUS_COMMAND, command); }
+    true
+}
+
+/// Write a byte on the data port, for the controller or for the keyboard.
+fn write(data: u8) -> bool {
+    if !wait_write() {
+        return false;
+    }
+    unsafe { outb(DATA, data); }
+    true
+}
+
+/// Read a byte from the data port.
+fn read() -> Option<u8> {
+    if wait_read() {
+        Some(unsafe { inb(DATA) })
+    } else {
+        None
+    }
+}
+
+/// Drop the bytes waiting on the data port.
+fn flush() {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        unsafe { inb(DATA); }
+    }
+}
+
+/// Send a command to the controller and read its response.
+fn command_read(command_byte: u8) -> Option<u8> {
+    if command(command_byte) { read() } else { None }
+}
+
+/// Send a byte to the keyboard and wait for it to acknowledge it.
+fn keyboard_write(data: u8) -> bool {
+    for _ in 0..RETRIES {
+        if !write(data) {
+            return false;
+        }
+        match read() {
+            Some(KEYBOARD_ACK) => return true,
+            Some(KEYBOARD_RESEND) => continue,
+            _ => return false
+        }
+    }
+    false
+}
+
+/// Initialize the controller and the keyboard, and start handling its IRQ.
+///
+/// This must be called after the I/O APIC is initialized.
+pub fn init() {
+    // without a controller, the status reads as all ones
+    if status() == 0xff {
+        kinfo!("no controller");
+        return;
+    }
+
+    // stop the devices while the controller is set up
+    command(COMMAND_DISABLE_FIRST);
+    command(COMMAND_DISABLE_SECOND);
+    flush();
+
+    let config = match command_read(COMMAND_READ_CONFIG) {
+        Some(config) => config & !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATION),
+        None => {
+            kwarn!("the controller doesn't answer");
+            return;
+        }
+    };
+    command(COMMAND_WRITE_CONFIG);
+    write(config);
+
+    if command_read(COMMAND_TEST_CONTROLLER) != Some(CONTROLLER_TEST_PASSED) {
+        kwarn!("controller self test failed");
+        return;
+    }
+    // some controllers reset on the self test
+    command(COMMAND_WRITE_CONFIG);
+    write(config);
+
+    if command_read(COMMAND_TEST_FIRST) != Some(PORT_TEST_PASSED) {
+        kwarn!("keyboard port test failed");
+        return;
+    }
+    command(COMMAND_ENABLE_FIRST);
+
+    if !keyboard_write(KEYBOARD_RESET) || read() != Some(KEYBOARD_TEST_PASSED) {
+        kwarn!("no keyboard");
+        return;
+    }
+
+    // prefer the set 2, every keyboard supports it, and let the controller translate otherwise
+    let set = if keyboard_write(KEYBOARD_SET_SCANCODE_SET) && keyboard_write(2) {
+        ScancodeSet::Set2
+    } else {
+        ScancodeSet::Set1
+    };
+    keyboard::set_scancode_set(set);
+
+    if !keyboard_write(KEYBOARD_ENABLE_SCANNING) {
+        kwarn!("the keyboard doesn't enable scanning");
+        return;
+    }
+    flush();
+
+    let translation = if set == ScancodeSet::Set1 { CONFIG_TRANSLATION } else { 0 };
+    command(COMMAND_WRITE_CONFIG);
+    write(config | CONFIG_FIRST_IRQ | translation);
+
+    if !irq::register(KEYBOARD_IRQ, irq_handler) {
+        kwarn!("IRQ {} is already in use", KEYBOARD_IRQ);
+        return;
+    }
+
+    kinfo!("keyboard on the scancode set {}", if set == ScancodeSet::Set2 { 2 } else { 1 });
+}
+
+/// IRQ handler of the keyboard, it decodes the received bytes.
+fn irq_handler(_irq: u8) {
+    loop {
+        let status = status();
+        if status & STATUS_OUTPUT_FULL == 0 || status & STATUS_SECOND_PORT != 0 {
+            break;
+        }
+        keyboard::receive(unsafe { inb(DATA) });
+    }
+
+    // the context waiting for a key may belong to a halted CPU
+    ipi::reschedule(IpiTarget::Other);
+}
@@ -1,38 +1,240 @@
 //! RTC Manager Module
 //!
+//! The RTC keeps the wall clock on the CMOS registers, as BCD or binary numbers and with 12 or 24
+//! hours, depending on the status register B. The century register, when there is one, is given by
+//! the FADT.
+//!
 //! References:
 //! - http://wiki.osdev.org/RTC
+//! - http://wiki.osdev.org/CMOS
 //! - http://www.ousob.com/ng/interrupts_and_ports/ng918f7.php
 
 use x86_64::instructions::port::{outb, inb};
+
+use acpi::ACPI_TABLE;
 use time;
 
+/// Seconds register
+const REG_SECONDS: u8 = 0x00;
+/// Minutes register
+const REG_MINUTES: u8 = 0x02;
+/// Hours register, bit 7 is the PM flag on 12 hours mode
+const REG_HOURS: u8 = 0x04;
+/// Day of month register
+const REG_DAY: u8 = 0x07;
+/// Month register
+const REG_MONTH: u8 = 0x08;
+/// Year of the century register
+const REG_YEAR: u8 = 0x09;
+/// Status register A
+const REG_STATUS_A: u8 = 0x0A;
+/// Status register B
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Status register A: an update is in progress, the registers must not be read
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status register B: the updates are stopped while the clock is set
+const STATUS_B_SET: u8 = 1 << 7;
+/// Status register B: binary numbers, BCD otherwise
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Status register B: 24 hours mode, 12 hours otherwise
+const STATUS_B_24_HOURS: u8 = 1 << 1;
+
+/// Hours register: PM flag on 12 hours mode
+const HOURS_PM: u8 = 1 << 7;
+
+/// Century used when the RTC doesn't have a century register
+const DEFAULT_CENTURY: u64 = 20;
+
+/// Seconds per day
+const SECS_PER_DAY: u64 = 86400;
+
 /// Initialize RTC
 pub fn init() {
     let mut rtc = Rtc::new();
     let cur_time = rtc.time();
-    time::START.lock().0 = cur_time;
+    time::set_realtime(cur_time);
+
+    kinfo!("current epoch {}", cur_time);
+}
+
+/// Change the wall clock, on the RTC and on `time`.
+///
+/// ## Parameters
+/// - `secs`: seconds since the Unix epoch.
+pub fn set_time(secs: u64) {
+    Rtc::new().set_time(secs);
+    time::set_realtime(secs);
+}
 
-    println!("RTC: Initialized\nCurrent epoch: {:}", cur_time);
+/// A date and time, in UTC
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u64,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    /// 0 to 23
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8
 }
 
-/// Convert BSD to binary
-fn cvt_bcd(value: usize) -> usize {
+/// Values of the RTC registers, as stored
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RtcRegisters {
+    pub second: u8,
+    pub minute: u8,
+    pub hour: u8,
+    pub day: u8,
+    pub month: u8,
+    pub year: u8,
+    /// Century register, `None` when there isn't one
+    pub century: Option<u8>,
+    /// Status register B, with the format of the other registers
+    pub status_b: u8
+}
+
+/// Convert BCD to binary
+fn cvt_bcd(value: u8) -> u8 {
     (value & 0xF) + ((value / 16) * 10)
 }
 
+/// Convert binary to BCD
+fn cvt_to_bcd(value: u8) -> u8 {
+    (value / 10) * 16 + value % 10
+}
+
+/// Get the number of days from 1970-01-01 to the given date, on the proleptic Gregorian calendar.
+///
+/// Based on the `days_from_civil` algorithm by Howard Hinnant. The years start on March, so the
+/// leap day is the last day of the year.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of `days_from_civil`.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400;
+
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Convert a date to seconds since the Unix epoch.
+///
+/// Dates before 1970 give 0.
+pub fn date_to_epoch(date: &DateTime) -> u64 {
+    if date.year < 1970 {
+        return 0;
+    }
+
+    days_from_civil(date.year, date.month as u64, date.day as u64) * SECS_PER_DAY +
+        date.hour as u64 * 3600 + date.minute as u64 * 60 + date.second as u64
+}
+
+/// Convert seconds since the Unix epoch to a date.
+pub fn epoch_to_date(secs: u64) -> DateTime {
+    let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+    let secs_of_day = secs % SECS_PER_DAY;
+
+    DateTime {
+        year: year,
+        month: month as u8,
+        day: day as u8,
+        hour: (secs_of_day / 3600) as u8,
+        minute: (secs_of_day / 60 % 60) as u8,
+        second: (secs_of_day % 60) as u8
+    }
+}
+
+impl RtcRegisters {
+    /// Decode the registers, following the format on the status register B.
+    pub fn decode(&self) -> DateTime {
+        let binary = self.status_b & STATUS_B_BINARY != 0;
+        let convert = |value: u8| if binary { value } else { cvt_bcd(value) };
+
+        // on 12 hours mode, 12 AM is the midnight and 12 PM the noon
+        let mut hour = convert(self.hour & !HOURS_PM);
+        if self.status_b & STATUS_B_24_HOURS == 0 {
+            hour %= 12;
+            if self.hour & HOURS_PM != 0 {
+                hour += 12;
+            }
+        }
+
+        let century = self.century.map_or(DEFAULT_CENTURY, |century| convert(century) as u64);
+
+        DateTime {
+            year: century * 100 + convert(self.year) as u64,
+            month: convert(self.month),
+            day: convert(self.day),
+            hour: hour,
+            minute: convert(self.minute),
+            second: convert(self.second)
+        }
+    }
+
+    /// Encode a date, on the format of the given status register B.
+    ///
+    /// ## Parameters
+    /// - `has_century`: whether the century register is set.
+    pub fn encode(date: &DateTime, status_b: u8, has_century: bool) -> RtcRegisters {
+        let binary = status_b & STATUS_B_BINARY != 0;
+        let convert = |value: u8| if binary { value } else { cvt_to_bcd(value) };
+
+        let hour = if status_b & STATUS_B_24_HOURS == 0 {
+            let hour_12 = if date.hour % 12 == 0 { 12 } else { date.hour % 12 };
+            convert(hour_12) | if date.hour >= 12 { HOURS_PM } else { 0 }
+        } else {
+            convert(date.hour)
+        };
+
+        RtcRegisters {
+            second: convert(date.second),
+            minute: convert(date.minute),
+            hour: hour,
+            day: convert(date.day),
+            month: convert(date.month),
+            year: convert((date.year % 100) as u8),
+            century: if has_century { Some(convert((date.year / 100) as u8)) } else { None },
+            status_b: status_b
+        }
+    }
+}
+
 /// RTC
 pub struct Rtc {
     address: u16,
-    data: u16
+    data: u16,
+    /// Century register given by the FADT
+    century: Option<u8>
 }
 
 impl Rtc {
-    /// Create a new empty RTC
+    /// Create a new RTC, with the century register of the FADT if there is one
     pub fn new() -> Self {
+        let century = ACPI_TABLE.lock().fadt.as_ref().map_or(0, |fadt| fadt.century);
+
         return Rtc {
             address: 0x70,
-            data: 0x71
+            data: 0x71,
+            century: if century != 0 { Some(century) } else { None }
         }
     }
 
@@ -47,91 +249,177 @@ impl Rtc {
         }
     }
 
-    /// Wait
+    /// Write
+    fn write(&mut self, reg: u8, value: u8) {
+        unsafe {
+            outb(self.address, reg);
+            outb(self.data, value);
+        }
+    }
+
+    /// Wait until there is no update in progress
     pub fn wait(&mut self) {
-        while self.read(0xA) & 0x80 != 0x80 {}
-        while self.read(0xA) & 0x80 != 0x80 {}
+        while self.read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {}
+    }
+
+    /// Read the date and time registers once.
+    fn read_registers(&mut self) -> RtcRegisters {
+        self.wait();
+
+        let century = self.century;
+        RtcRegisters {
+            second: self.read(REG_SECONDS),
+            minute: self.read(REG_MINUTES),
+            hour: self.read(REG_HOURS),
+            day: self.read(REG_DAY),
+            month: self.read(REG_MONTH),
+            year: self.read(REG_YEAR),
+            century: century.map(|reg| self.read(reg)),
+            status_b: self.read(REG_STATUS_B)
+        }
+    }
+
+    /// Get the current date and time.
+    ///
+    /// The registers are read until two reads match, so an update in the middle of a read isn't
+    /// mistaken for a valid time.
+    pub fn date(&mut self) -> DateTime {
+        let mut registers = self.read_registers();
+        loop {
+            let again = self.read_registers();
+            if again == registers {
+                return registers.decode();
+            }
+            registers = again;
+        }
     }
 
     /// Get current time in epoch format.
     pub fn time(&mut self) -> u64 {
-        use bit_field::BitField;
+        date_to_epoch(&self.date())
+    }
 
-        let mut second;
-        let mut minute;
-        let mut hour;
-        let mut day;
-        let mut month;
-        let mut year;
-        let mut century;
-        let mut register_b;
+    /// Change the date and time.
+    ///
+    /// ## Parameters
+    /// - `secs`: seconds since the Unix epoch.
+    pub fn set_time(&mut self, secs: u64) {
+        let status_b = self.read(REG_STATUS_B);
+        let registers = RtcRegisters::encode(&epoch_to_date(secs), status_b, self.century.is_some());
 
-        self.wait();
-        second = self.read(0) as usize;
-        minute = self.read(2) as usize;
-        hour = self.read(4) as usize;
-        day = self.read(7) as usize;
-        month = self.read(8) as usize;
-        year = self.read(9) as usize;
-        // TODO use ACPI FADT to get the current century. VirtualBox introduces some challenges.
-        century = 20 as usize;
-        register_b = self.read(0xb);
-
-        // If the bit 4 is set, that means that the system used BCD instead of binary numbers
-        if !register_b.get_bit(4) {
-            second = cvt_bcd(second);
-            minute = cvt_bcd(minute);
-            hour = cvt_bcd(hour);
-            day = cvt_bcd(day);
-            month = cvt_bcd(month);
-            year = cvt_bcd(year);
-            // TODO implement century correctly
-            // century = cvt_bcd(century);
-        }
+        // stop the updates while the registers are written
+        self.write(REG_STATUS_B, status_b | STATUS_B_SET);
 
-        // If the bit 2 is set that means that the system is using the a 24-hour mode.
-        if register_b.get_bit(2) || hour & 0x80 == 0x80 {
-            hour = ((hour & 0x7f) + 12) % 24;
+        self.write(REG_SECONDS, registers.second);
+        self.write(REG_MINUTES, registers.minute);
+        self.write(REG_HOURS, registers.hour);
+        self.write(REG_DAY, registers.day);
+        self.write(REG_MONTH, registers.month);
+        self.write(REG_YEAR, registers.year);
+        if let (Some(reg), Some(century)) = (self.century, registers.century) {
+            self.write(reg, century);
         }
 
-        // compute the current year
-        year += century * 100;
+        self.write(REG_STATUS_B, status_b & !STATUS_B_SET);
+    }
+}
 
-        // Unix time from clock
-        let mut secs: u64 = (year as u64 - 1970) * 31536000;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // check if the year is a leap year.
-        let mut leap_days = (year as u64 - 1972) / 4 + 1;
-        if year % 4 == 0 {
-            if month <= 2 {
-                leap_days -= 1;
-            }
-        }
-        secs += leap_days * 86400;
-
-        // add the seconds of the passed months
-        match month {
-            2 => secs += 2678400,
-            3 => secs += 5097600,
-            4 => secs += 7776000,
-            5 => secs += 10368000,
-            6 => secs += 13046400,
-            7 => secs += 15638400,
-            8 => secs += 18316800,
-            9 => secs += 20995200,
-            10 => secs += 23587200,
-            11 => secs += 26265600,
-            12 => secs += 28857600,
-            _ => (),
+    fn date(year: u64, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime { year: year, month: month, day: day, hour: hour, minute: minute, second: second }
+    }
+
+    #[test]
+    fn epoch_of_known_dates() {
+        assert_eq!(date_to_epoch(&date(1970, 1, 1, 0, 0, 0)), 0);
+        assert_eq!(date_to_epoch(&date(2000, 1, 1, 0, 0, 0)), 946684800);
+        assert_eq!(date_to_epoch(&date(2017, 6, 15, 13, 45, 30)), 1497534330);
+        assert_eq!(date_to_epoch(&date(2038, 1, 19, 3, 14, 8)), 2147483648);
+        assert_eq!(date_to_epoch(&date(1969, 12, 31, 23, 59, 59)), 0);
+    }
+
+    #[test]
+    fn leap_years() {
+        // 2000 is a leap year, divisible by 400
+        assert_eq!(date_to_epoch(&date(2000, 2, 29, 0, 0, 0)), 951782400);
+        assert_eq!(date_to_epoch(&date(2000, 3, 1, 0, 0, 0)), 951868800);
+        // 2100 isn't, divisible by 100
+        assert_eq!(date_to_epoch(&date(2100, 3, 1, 0, 0, 0)) - date_to_epoch(&date(2100, 2, 28, 0, 0, 0)),
+                   SECS_PER_DAY);
+        // 2016 is, divisible by 4
+        assert_eq!(date_to_epoch(&date(2016, 3, 1, 0, 0, 0)) - date_to_epoch(&date(2016, 2, 28, 0, 0, 0)),
+                   2 * SECS_PER_DAY);
+        assert_eq!(date_to_epoch(&date(2016, 12, 31, 0, 0, 0)), 1483142400);
+    }
+
+    #[test]
+    fn epoch_round_trip() {
+        for &secs in [0, 951782400, 1483142400, 1497534330, 4107542399, 4107542400].iter() {
+            assert_eq!(date_to_epoch(&epoch_to_date(secs)), secs);
         }
+        assert_eq!(epoch_to_date(951782400), date(2000, 2, 29, 0, 0, 0));
+    }
+
+    #[test]
+    fn decode_bcd_24_hours() {
+        let registers = RtcRegisters {
+            second: 0x30, minute: 0x45, hour: 0x13, day: 0x15, month: 0x06, year: 0x17,
+            century: Some(0x20), status_b: STATUS_B_24_HOURS
+        };
+        assert_eq!(registers.decode(), date(2017, 6, 15, 13, 45, 30));
+    }
 
-        secs += (day as u64 - 1) * 86400;
-        secs += hour as u64 * 3600;
-        secs += minute as u64 * 60;
-        secs += second as u64;
+    #[test]
+    fn decode_binary_24_hours() {
+        let registers = RtcRegisters {
+            second: 30, minute: 45, hour: 13, day: 15, month: 6, year: 17,
+            century: Some(20), status_b: STATUS_B_BINARY | STATUS_B_24_HOURS
+        };
+        assert_eq!(registers.decode(), date(2017, 6, 15, 13, 45, 30));
+    }
 
-        secs
+    #[test]
+    fn decode_12_hours() {
+        let mut registers = RtcRegisters {
+            second: 0, minute: 0, hour: 0x12, day: 0x01, month: 0x01, year: 0x99,
+            century: Some(0x19), status_b: 0
+        };
+        // 12 AM is the midnight
+        assert_eq!(registers.decode().hour, 0);
+        // 12 PM is the noon
+        registers.hour = 0x12 | HOURS_PM;
+        assert_eq!(registers.decode().hour, 12);
+        // 1 PM
+        registers.hour = 0x01 | HOURS_PM;
+        assert_eq!(registers.decode().hour, 13);
+        // 11 PM, binary
+        registers.hour = 11 | HOURS_PM;
+        registers.status_b = STATUS_B_BINARY;
+        assert_eq!(registers.decode().hour, 23);
+    }
+
+    #[test]
+    fn decode_without_century() {
+        let registers = RtcRegisters {
+            second: 0, minute: 0, hour: 0, day: 1, month: 1, year: 24,
+            century: None, status_b: STATUS_B_BINARY | STATUS_B_24_HOURS
+        };
+        assert_eq!(registers.decode().year, 2024);
     }
-}
 
-// epoch time
+    #[test]
+    fn encode_round_trip() {
+        let formats = [0, STATUS_B_BINARY, STATUS_B_24_HOURS, STATUS_B_BINARY | STATUS_B_24_HOURS];
+        let dates = [date(2017, 6, 15, 13, 45, 30), date(1999, 12, 31, 0, 0, 0), date(2000, 2, 29, 12, 59, 59)];
+
+        for &status_b in formats.iter() {
+            for date in dates.iter() {
+                let registers = RtcRegisters::encode(date, status_b, true);
+                assert_eq!(registers.decode(), *date);
+            }
+        }
+    }
+}
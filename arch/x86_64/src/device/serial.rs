@@ -1,22 +1,106 @@
 //! Serial Device Manager
 //!
+//! The two serial ports are configured at the start of the boot, so `println!` can mirror the
+//! kernel output on COM1. Once the I/O APIC is up, the receive interrupts are enabled and the
+//! received bytes are kept on a ring buffer until someone reads them.
+//!
 //! References:
 //! - http://retired.beyondlogic.org/serial/serial.htm
 
 use x86_64::instructions::port::{outb, inb};
+use x86_64::registers::flags::{self, IF};
 
 use core::fmt::{self, Write};
+use core::str;
 use spin::Mutex;
 
-pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::new(0x3f8));
-pub static COM2: Mutex<SerialPort> = Mutex::new(SerialPort::new(0x2f8));
+use interrupts;
+use interrupts::ipi::{self, IpiTarget};
+use interrupts::irq;
+
+/// IRQ of COM1
+const COM1_IRQ: u8 = 4;
+/// IRQ of COM2
+const COM2_IRQ: u8 = 3;
+
+static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::new(0x3f8, COM1_IRQ));
+static COM2: Mutex<SerialPort> = Mutex::new(SerialPort::new(0x2f8, COM2_IRQ));
+
+/// Number of serial ports, numbered from 1
+pub const PORT_COUNT: usize = 2;
 
-/// Initialize the two serials.
+/// Clock of the UART, the baud rate is this divided by the divisor
+const UART_CLOCK: u32 = 115200;
+
+/// Size of the receive ring buffer of each port
+const INPUT_BUFFER_SIZE: usize = 4096;
+
+/// Configure the two serials, so the kernel output is mirrored on COM1.
+///
+/// The receive interrupts are only enabled by `init_interrupts`.
 pub fn init() {
     COM1.lock().init();
     COM2.lock().init();
+}
 
-    println!("Serial: Initialized");
+/// Enable the receive interrupts of the two serials.
+///
+/// This must be called after the I/O APIC is initialized.
+pub fn init_interrupts() {
+    for number in 1..PORT_COUNT + 1 {
+        with_port(number, |port| {
+            if irq::register(port.irq, irq_handler) {
+                port.enable_receive_interrupt();
+            } else {
+                kwarn!("IRQ {} is already in use", port.irq);
+            }
+        });
+    }
+
+    kinfo!("initialized");
+}
+
+/// Run `f` on the serial port `number`, with interrupts disabled so its IRQ handler can't take
+/// the port lock while it's held.
+///
+/// ## Returns
+/// `None` if there is no such port.
+pub fn with_port<F, T>(number: usize, f: F) -> Option<T> where F: FnOnce(&mut SerialPort) -> T {
+    let port = match number {
+        1 => &COM1,
+        2 => &COM2,
+        _ => return None
+    };
+
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { interrupts::disable(); }
+
+    let result = f(&mut port.lock());
+
+    if interrupts_enabled {
+        unsafe { interrupts::enable(); }
+    }
+
+    Some(result)
+}
+
+/// Mirror the kernel output on COM1.
+///
+/// This must be called with interrupts disabled, as `vga_buffer::print` does.
+pub fn print(args: fmt::Arguments) {
+    let mut com1 = COM1.lock();
+    if com1.is_enable() {
+        com1.write_fmt(args).unwrap();
+    }
+}
+
+/// IRQ handler of the two serials, it moves the received bytes to the ring buffer.
+fn irq_handler(irq: u8) {
+    let port = if irq == COM1_IRQ { &COM1 } else { &COM2 };
+    port.lock().receive();
+
+    // the context waiting for input may belong to a halted CPU
+    ipi::reschedule(IpiTarget::Other);
 }
 
 bitflags! {
@@ -40,6 +124,161 @@ bitflags! {
     }
 }
 
+/// Line control: divisor latch access
+const LINE_CTRL_DLAB: u8 = 1 << 7;
+/// Line control: two stop bits
+const LINE_CTRL_TWO_STOP_BITS: u8 = 1 << 2;
+/// Line control: parity enabled
+const LINE_CTRL_PARITY: u8 = 1 << 3;
+/// Line control: even parity, when the parity is enabled
+const LINE_CTRL_EVEN_PARITY: u8 = 1 << 4;
+
+/// Parity bit of each character
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even
+}
+
+/// Baud rate and character format of a serial port
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineSettings {
+    pub baud_rate: u32,
+    /// Between 5 and 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2
+    pub stop_bits: u8
+}
+
+/// Settings used at boot, 38400 8N1
+const BOOT_SETTINGS: LineSettings = LineSettings {
+    baud_rate: 38400,
+    data_bits: 8,
+    parity: Parity::None,
+    stop_bits: 1
+};
+
+impl LineSettings {
+    /// Parse settings written like `115200 8N1`.
+    ///
+    /// ## Returns
+    /// `None` if the text isn't valid or the settings aren't supported.
+    pub fn parse(text: &[u8]) -> Option<LineSettings> {
+        let text = match str::from_utf8(text) {
+            Ok(text) => text.trim(),
+            Err(_) => return None
+        };
+
+        let mut parts = text.split_whitespace();
+        let baud_rate = match parts.next().map(|part| part.parse::<u32>()) {
+            Some(Ok(baud_rate)) => baud_rate,
+            _ => return None
+        };
+        let format = match parts.next() {
+            Some(format) if format.len() == 3 && parts.next().is_none() => format.as_bytes(),
+            _ => return None
+        };
+
+        let parity = match format[1] {
+            b'N' | b'n' => Parity::None,
+            b'O' | b'o' => Parity::Odd,
+            b'E' | b'e' => Parity::Even,
+            _ => return None
+        };
+
+        let settings = LineSettings {
+            baud_rate: baud_rate,
+            data_bits: format[0].wrapping_sub(b'0'),
+            parity: parity,
+            stop_bits: format[2].wrapping_sub(b'0')
+        };
+
+        if settings.is_valid() {
+            Some(settings)
+        } else {
+            None
+        }
+    }
+
+    /// Check if the UART supports these settings.
+    ///
+    /// The baud rate must divide the UART clock, so the divisor is exact.
+    pub fn is_valid(&self) -> bool {
+        self.baud_rate != 0 && self.baud_rate <= UART_CLOCK && UART_CLOCK % self.baud_rate == 0 &&
+            self.data_bits >= 5 && self.data_bits <= 8 &&
+            (self.stop_bits == 1 || self.stop_bits == 2)
+    }
+
+    /// Get the line control register value of these settings.
+    fn line_ctrl(&self) -> u8 {
+        let mut value = self.data_bits - 5;
+        if self.stop_bits == 2 {
+            value |= LINE_CTRL_TWO_STOP_BITS;
+        }
+        match self.parity {
+            Parity::None => (),
+            Parity::Odd => value |= LINE_CTRL_PARITY,
+            Parity::Even => value |= LINE_CTRL_PARITY | LINE_CTRL_EVEN_PARITY
+        }
+        value
+    }
+}
+
+impl fmt::Display for LineSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E'
+        };
+        write!(f, "{} {}{}{}", self.baud_rate, self.data_bits, parity, self.stop_bits)
+    }
+}
+
+/// Received bytes that weren't read yet. When it's full, the new bytes are dropped.
+struct InputBuffer {
+    data: [u8; INPUT_BUFFER_SIZE],
+    /// Index of the oldest byte
+    head: usize,
+    /// Number of bytes on the buffer
+    len: usize
+}
+
+impl InputBuffer {
+    const fn new() -> InputBuffer {
+        InputBuffer {
+            data: [0; INPUT_BUFFER_SIZE],
+            head: 0,
+            len: 0
+        }
+    }
+
+    /// Add a byte at the end, it's dropped if the buffer is full.
+    fn push(&mut self, byte: u8) {
+        if self.len < INPUT_BUFFER_SIZE {
+            self.data[(self.head + self.len) % INPUT_BUFFER_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    /// Move the oldest bytes to `buffer`.
+    ///
+    /// ## Returns
+    /// The number of bytes moved.
+    fn pop(&mut self, buffer: &mut [u8]) -> usize {
+        let mut count = 0;
+        while count < buffer.len() && self.len > 0 {
+            buffer[count] = self.data[self.head];
+            self.head = (self.head + 1) % INPUT_BUFFER_SIZE;
+            self.len -= 1;
+            count += 1;
+        }
+        count
+    }
+}
+
 /// Structure for a Serial Port
 pub struct SerialPort {
     /// Data register, read to receive, write to send
@@ -56,6 +295,12 @@ pub struct SerialPort {
     line_sts: u16,
     /// Modem status (read-only)
     modem_sts: u16,
+    /// IRQ of the port
+    irq: u8,
+    /// Current baud rate and character format
+    settings: LineSettings,
+    /// Received bytes not read yet
+    input: InputBuffer,
     // This inform if the console is enabled
     enabled: bool
 }
@@ -65,10 +310,11 @@ impl SerialPort {
     ///
     /// ## Parameters
     /// - `base` - base address for the serial port.
+    /// - `irq` - IRQ of the serial port.
     ///
     /// ## Returns
     /// A new SerialPort instance.
-    const fn new(base: u16) -> Self {
+    const fn new(base: u16, irq: u8) -> Self {
         SerialPort {
             data: base,
             int_en: base + 1,
@@ -77,6 +323,9 @@ impl SerialPort {
             modem_ctrl: base + 4,
             line_sts: base + 5,
             modem_sts: base + 6,
+            irq: irq,
+            settings: BOOT_SETTINGS,
+            input: InputBuffer::new(),
             enabled: false
         }
     }
@@ -84,29 +333,63 @@ impl SerialPort {
     /// Initialize the serial port.
     pub fn init(&mut self) {
         unsafe {
-            use bit_field::BitField;
-
             // Disable all interrupts
             outb(self.int_en, 0x00);
-            // Enable DLAB (set baud rate divisor)
-            outb(self.line_ctrl, 0x80);
-            // Set divisor to 3 (lo byte) 38400 baud
-            outb(self.data, 0x03);
-            // Set divisor to 3 (hi byte) 38400 baud
-            outb(self.int_en, 0x00);
-            // 8 bits, no parity, one stop bit
-            outb(self.line_ctrl, 0x03);
+        }
+
+        let settings = self.settings;
+        self.configure(settings);
+
+        unsafe {
             // Enable FIFO, clear them, with 14-byte threshold
             outb(self.fifo_ctrl, 0xc7);
             // IRQs enabled, RTS/DSR set
             outb(self.modem_ctrl, 0x0b);
+        }
 
-            // Wait for transmit to be empty
-            while ! inb(self.line_sts).get_bit(5) {}
+        // set the console as enabled
+        self.enabled = true
+    }
 
-            // set the console as enabled
-            self.enabled = true
+    /// Change the baud rate and the character format.
+    ///
+    /// ## Returns
+    /// `false` if the settings aren't supported, the port keeps the previous ones.
+    pub fn configure(&mut self, settings: LineSettings) -> bool {
+        if !settings.is_valid() {
+            return false;
         }
+
+        // Wait for transmit to be empty, so the pending bytes go out with the old settings
+        while ! self.line_sts().contains(OUTPUT_EMPTY) {}
+
+        let divisor = (UART_CLOCK / settings.baud_rate) as u16;
+        unsafe {
+            // Enable DLAB (set baud rate divisor)
+            outb(self.line_ctrl, LINE_CTRL_DLAB);
+            outb(self.data, divisor as u8);
+            outb(self.int_en, (divisor >> 8) as u8);
+            // Disable DLAB and set the character format
+            outb(self.line_ctrl, settings.line_ctrl());
+        }
+
+        self.settings = settings;
+        true
+    }
+
+    /// Get the current baud rate and character format.
+    pub fn settings(&self) -> LineSettings {
+        self.settings
+    }
+
+    /// Get the IRQ of the port.
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    /// Interrupt when a byte is received.
+    fn enable_receive_interrupt(&mut self) {
+        unsafe { outb(self.int_en, RECEIVED.bits()); }
     }
 
     /// True is the console is enable.
@@ -120,6 +403,31 @@ impl SerialPort {
         LineStsFlags::from_bits_truncate(unsafe { inb(self.line_sts) })
     }
 
+    /// Move the received bytes from the FIFO to the input buffer.
+    fn receive(&mut self) {
+        while self.line_sts().contains(INPUT_FULL) {
+            let byte = unsafe { inb(self.data) };
+            self.input.push(byte);
+        }
+    }
+
+    /// Read the received bytes. This doesn't wait for them.
+    ///
+    /// ## Returns
+    /// The number of bytes read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        // bytes may be waiting on the FIFO before the receive interrupt is enabled
+        self.receive();
+        self.input.pop(buffer)
+    }
+
+    /// Send bytes as they are.
+    pub fn write_bytes(&mut self, buffer: &[u8]) {
+        for &byte in buffer {
+            self.write(byte);
+        }
+    }
+
     /// Write one byte for the serial port.
     ///
     /// ## Parameters
@@ -139,6 +447,11 @@ impl Write for SerialPort {
                     self.write(b' ');
                     self.write(8);
                 },
+                // terminals need a carriage return to go back to the start of the line
+                b'\n' => {
+                    self.write(b'\r');
+                    self.write(b'\n');
+                },
                 _ => {
                     self.write(byte);
                 }
@@ -49,9 +49,21 @@ pub extern "x86-interrupt" fn device_not_available(stack_frame: &mut ExceptionSt
 }
 
 /// Handler to catch double faults
+///
+/// A kernel stack overflow hits the guard page below the stack, and the page fault handler can't
+/// run on the overflowed stack, so it ends up here. We use the fault address to report it as such.
 pub extern "x86-interrupt" fn double_fault(stack_frame: &mut ExceptionStackFrame, error_code: u64) {
-    println!("\nDouble fault: {:x} at {:>02x}:{:>016x}", error_code, stack_frame.code_segment,
-             stack_frame.instruction_pointer);
+    use x86_64::registers::control_regs;
+    use memory;
+
+    let fault_address = control_regs::cr2();
+    if memory::is_stack_guard_page(fault_address.0) {
+        println!("\nKernel stack overflow: guard page {:>016x} hit at {:>02x}:{:>016x}", fault_address,
+                 stack_frame.code_segment, stack_frame.instruction_pointer);
+    } else {
+        println!("\nDouble fault: {:x} at {:>02x}:{:>016x}", error_code, stack_frame.code_segment,
+                 stack_frame.instruction_pointer);
+    }
     loop {}
 }
 
@@ -1,8 +1,268 @@
+//! # Inter-Processor Interrupts (IPIs)
+//!
+//! Each kind of IPI has its own vector. The kinds that carry data, TLB shootdowns and function
+//! calls, put a message on the queue of each destination CPU before interrupting it, and the
+//! sender waits until all the destinations handled the message. The queues are allocated at boot
+//! with a fixed capacity, so sending a message never allocates memory. When a queue is full, the
+//! sender waits until its CPU takes a message.
+//!
+//! - `Reschedule`: wakes the CPU from `hlt`, so its scheduler looks for runnable contexts again.
+//! - `Tlb`: invalidates the TLB entries of a range of pages.
+//! - `Call`: runs a function on the CPU.
+//! - `Halt`: stops the CPU, used when the kernel panics.
+
+use collections::{Vec, VecDeque};
+use core::intrinsics::{atomic_load, atomic_store};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::registers::flags::{self, IF};
 use x86_64::structures::idt::ExceptionStackFrame;
 
 use device::local_apic::LOCAL_APIC;
+use memory::paging::flush;
+use start;
+use MAX_CPU_COUNT;
+
+/// Kind of an IPI, the value is its vector
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IpiKind {
+    Reschedule = 0x41,
+    Tlb = 0x42,
+    Call = 0x43,
+    Halt = 0x44
+}
+
+/// Destination of an IPI
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IpiTarget {
+    /// The CPU with the given id
+    Cpu(usize),
+    /// The CPUs whose bits are set on the mask, bit `n` being the CPU with id `n`
+    Mask(u64),
+    /// All the CPUs except the current one
+    Other
+}
+
+impl IpiTarget {
+    /// Check if a CPU is a destination of this target.
+    fn includes(&self, cpu_id: usize) -> bool {
+        match *self {
+            IpiTarget::Cpu(id) => id == cpu_id,
+            IpiTarget::Mask(mask) => cpu_id < MAX_CPU_COUNT && mask & 1 << cpu_id != 0,
+            IpiTarget::Other => cpu_id != start::cpu_id()
+        }
+    }
+}
+
+/// Work to do on a destination CPU
+#[derive(Copy, Clone)]
+enum Message {
+    /// Invalidate the pages of the range `start..end`
+    Tlb { start: usize, end: usize },
+    /// Call `function` with `argument`
+    Call { function: fn(usize), argument: usize }
+}
+
+impl Message {
+    /// Do the work of the message on the current CPU.
+    fn run(&self) {
+        match *self {
+            Message::Tlb { start, end } => flush::flush_local(start, end),
+            Message::Call { function, argument } => function(argument)
+        }
+    }
+}
+
+/// Number of messages each CPU queue holds
+const QUEUE_SIZE: usize = 8;
+
+/// A message queued on a CPU
+#[derive(Copy, Clone)]
+struct Request {
+    message: Message,
+    /// Number of destinations that didn't handle the message yet. It lives on the stack of the
+    /// sender, which waits for it to reach zero.
+    pending: *const AtomicUsize
+}
+
+unsafe impl Send for Request {}
+
+lazy_static! {
+    /// Message queue of each CPU, indexed by CPU id. It never grows beyond `QUEUE_SIZE`.
+    static ref QUEUES: Vec<Mutex<VecDeque<Request>>> = {
+        let mut queues = Vec::with_capacity(MAX_CPU_COUNT);
+        for _ in 0..MAX_CPU_COUNT {
+            queues.push(Mutex::new(VecDeque::with_capacity(QUEUE_SIZE)));
+        }
+        queues
+    };
+}
+
+/// Local APIC id of each CPU, indexed by CPU id
+static mut APIC_IDS: [usize; MAX_CPU_COUNT] = [0; MAX_CPU_COUNT];
+
+/// Register the current CPU as an IPI destination.
+///
+/// This must be called on each CPU after its Local APIC and its id are set up.
+pub fn init() {
+    unsafe {
+        atomic_store(&mut APIC_IDS[start::cpu_id()], LOCAL_APIC.id() as usize);
+    }
+
+    // allocate the queues before any IPI handler can use them
+    lazy_static::initialize(&QUEUES);
+}
+
+/// Get the Local APIC id of a CPU, to send it interrupts.
+pub fn apic_id(cpu_id: usize) -> Option<u8> {
+    if cpu_id < start::cpu_count() {
+        Some(unsafe { atomic_load(&APIC_IDS[cpu_id]) } as u8)
+    } else {
+        None
+    }
+}
+
+/// Handle all the messages on the queue of the current CPU.
+///
+/// This must be called with interrupts disabled, so the IPI handler can't take the queue lock
+/// while it's held.
+fn handle_queue() {
+    let queue = &QUEUES[start::cpu_id()];
+
+    loop {
+        let request = queue.lock().pop_front();
+        match request {
+            Some(request) => {
+                request.message.run();
+                unsafe { (*request.pending).fetch_sub(1, Ordering::SeqCst); }
+            },
+            None => break
+        }
+    }
+}
 
-/// Handler for a Inter-Process Interrupt (IPI)
-pub extern "x86-interrupt" fn ipi(stack_frame: &mut ExceptionStackFrame) {
+/// Queue a request on a CPU, waiting until its queue has room.
+///
+/// The messages sent to the current CPU are handled while waiting, otherwise two CPUs sending
+/// messages to each other would wait forever.
+fn post(cpu_id: usize, request: Request) {
+    loop {
+        {
+            let mut queue = QUEUES[cpu_id].lock();
+            if queue.len() < QUEUE_SIZE {
+                queue.push_back(request);
+                return;
+            }
+        }
+
+        handle_queue();
+        super::pause();
+    }
+}
+
+/// Interrupt the target CPUs.
+fn interrupt(target: IpiTarget, kind: IpiKind) {
+    unsafe {
+        match target {
+            IpiTarget::Cpu(cpu_id) => {
+                let apic_id = atomic_load(&APIC_IDS[cpu_id]);
+                LOCAL_APIC.inter_processor_interrupt(apic_id, kind as u8);
+            },
+            IpiTarget::Mask(_) => {
+                for cpu_id in (0..start::cpu_count()).filter(|&cpu_id| target.includes(cpu_id)) {
+                    let apic_id = atomic_load(&APIC_IDS[cpu_id]);
+                    LOCAL_APIC.inter_processor_interrupt(apic_id, kind as u8);
+                }
+            },
+            IpiTarget::Other => LOCAL_APIC.broadcast_inter_processor_interrupt(kind as u8)
+        }
+    }
+}
+
+/// Post a message on the target CPUs, interrupt them and wait until all of them handled it.
+fn send(target: IpiTarget, kind: IpiKind, message: Message) {
+    let cpu_id = start::cpu_id();
+
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { super::disable(); }
+
+    // the current CPU runs the message itself, without an interrupt
+    if target.includes(cpu_id) {
+        message.run();
+    }
+
+    // each destination is interrupted as soon as its queue holds the message, so a full queue is
+    // always emptied by its CPU
+    let pending = AtomicUsize::new(0);
+    for destination in (0..start::cpu_count()).filter(|&destination| destination != cpu_id) {
+        if target.includes(destination) {
+            pending.fetch_add(1, Ordering::SeqCst);
+            post(destination, Request {
+                message: message,
+                pending: &pending
+            });
+            interrupt(IpiTarget::Cpu(destination), kind);
+        }
+    }
+
+    // handle the messages sent to this CPU while waiting, for the same reason as `post`
+    while pending.load(Ordering::SeqCst) != 0 {
+        handle_queue();
+        super::pause();
+    }
+
+    if interrupts_enabled {
+        unsafe { super::enable(); }
+    }
+}
+
+/// Wake the target CPUs, so they look for runnable contexts. This doesn't wait.
+pub fn reschedule(target: IpiTarget) {
+    if target != IpiTarget::Cpu(start::cpu_id()) {
+        interrupt(target, IpiKind::Reschedule);
+    }
+}
+
+/// Invalidate the TLB entries of the pages on the range `start..end` on the target CPUs, and wait
+/// until all of them are done. `memory::paging::flush` does it on the CPUs that use the page table.
+pub fn tlb_shootdown(target: IpiTarget, start: usize, end: usize) {
+    send(target, IpiKind::Tlb, Message::Tlb { start: start, end: end });
+}
+
+/// Call `function(argument)` on the target CPUs, and wait until all of them returned.
+///
+/// The function runs on the interrupt context, so it must not block nor allocate memory.
+pub fn call(target: IpiTarget, function: fn(usize), argument: usize) {
+    send(target, IpiKind::Call, Message::Call { function: function, argument: argument });
+}
+
+/// Stop the target CPUs. This doesn't wait.
+pub fn halt(target: IpiTarget) {
+    if start::cpu_count() > 1 {
+        interrupt(target, IpiKind::Halt);
+    }
+}
+
+/// Handler for the `Reschedule` IPI, interrupting `hlt` is all it needs to do.
+pub extern "x86-interrupt" fn reschedule_handler(_stack_frame: &mut ExceptionStackFrame) {
     unsafe { LOCAL_APIC.end_of_interrupt(); }
 }
+
+/// Handler for the IPIs that carry a message, `Tlb` and `Call`.
+pub extern "x86-interrupt" fn message_handler(stack_frame: &mut ExceptionStackFrame) {
+    let _tls = super::KernelTls::enter(stack_frame);
+    handle_queue();
+
+    unsafe { LOCAL_APIC.end_of_interrupt(); }
+}
+
+/// Handler for the `Halt` IPI, the CPU never returns from it.
+pub extern "x86-interrupt" fn halt_handler(_stack_frame: &mut ExceptionStackFrame) {
+    loop {
+        unsafe {
+            super::disable();
+            asm!("hlt" : : : : "intel", "volatile");
+        }
+    }
+}
@@ -1,18 +1,210 @@
-use x86_64::structures::idt::ExceptionStackFrame;
+use core::intrinsics::{atomic_cxchg, atomic_load, atomic_store, atomic_xadd};
+use core::mem;
+use x86_64::structures::idt::{ExceptionStackFrame, HandlerFunc};
 
+use start;
 use time;
-use device::local_apic;
+use device::{io_apic, local_apic};
+use super::{IRQ_COUNT, IRQ_OFFSET, MSI_COUNT, MSI_OFFSET, KernelTls};
 
-pub extern "x86-interrupt" fn timer(stack_frame: &mut ExceptionStackFrame) {
-    const UPDATE_RATE: u64 = 0x10000;
+/// Handler of an IRQ, called with the IRQ number on the interrupt context.
+pub type IrqHandler = fn(u8);
+
+/// Number of IRQs, the I/O APIC inputs followed by the Message Signaled Interrupts (MSIs)
+const TOTAL_COUNT: usize = IRQ_COUNT + MSI_COUNT;
+
+/// Registered handlers, as addresses, indexed by IRQ. Zero means there is no handler.
+///
+/// The handler of an MSI also marks its vector as allocated.
+static mut IRQ_HANDLERS: [usize; TOTAL_COUNT] = [0; TOTAL_COUNT];
+
+/// Number of times that each IRQ fired since the kernel started.
+static mut IRQ_COUNTS: [usize; TOTAL_COUNT] = [0; TOTAL_COUNT];
+
+/// Check if an IRQ is an MSI, which doesn't go through the I/O APIC.
+pub fn is_msi(irq: u8) -> bool {
+    irq as usize >= IRQ_COUNT && (irq as usize) < TOTAL_COUNT
+}
+
+/// Get the vector an IRQ is delivered on.
+pub fn vector(irq: u8) -> Option<u8> {
+    if (irq as usize) < IRQ_COUNT {
+        Some(IRQ_OFFSET + irq)
+    } else if is_msi(irq) {
+        Some(MSI_OFFSET + (irq as usize - IRQ_COUNT) as u8)
+    } else {
+        None
+    }
+}
+
+/// Get the number of times an IRQ fired.
+///
+/// This doesn't take any lock, the scheduler uses it to wake the contexts that wait for an IRQ.
+pub fn count(irq: u8) -> usize {
+    unsafe { atomic_load(&IRQ_COUNTS[irq as usize]) }
+}
 
-    let mut offset = time::OFFSET.lock();
-    let sum = offset.1 + UPDATE_RATE;
-    offset.1 = sum % 1000000000;
-    offset.0 += sum / 1000000000;
+/// Register the handler of an IRQ and unmask it.
+///
+/// ## Returns
+/// `false` if the IRQ doesn't exist or already has a handler.
+pub fn register(irq: u8, handler: IrqHandler) -> bool {
+    if irq as usize >= IRQ_COUNT {
+        return false;
+    }
+
+    let (_, registered) = unsafe { atomic_cxchg(&mut IRQ_HANDLERS[irq as usize], 0, handler as usize) };
+    if registered {
+        io_apic::unmask(irq);
+    }
+
+    registered
+}
+
+/// Mask an IRQ and remove its handler.
+pub fn unregister(irq: u8) {
+    if irq as usize >= IRQ_COUNT {
+        return;
+    }
+
+    io_apic::mask(irq);
+    unsafe { atomic_store(&mut IRQ_HANDLERS[irq as usize], 0); }
+}
+
+/// Allocate a vector for an MSI and register its handler.
+///
+/// The device must then be programmed with `vector(irq)`, see `device::pci::msi`.
+///
+/// ## Returns
+/// The IRQ of the vector, or `None` if they're all in use.
+pub fn allocate_msi(handler: IrqHandler) -> Option<u8> {
+    (IRQ_COUNT..TOTAL_COUNT).find(|&irq| {
+        let (_, allocated) = unsafe { atomic_cxchg(&mut IRQ_HANDLERS[irq], 0, handler as usize) };
+        allocated
+    }).map(|irq| irq as u8)
+}
+
+/// Remove the handler of an MSI and free its vector.
+///
+/// The device must not signal it anymore.
+pub fn free_msi(irq: u8) {
+    if is_msi(irq) {
+        unsafe { atomic_store(&mut IRQ_HANDLERS[irq as usize], 0); }
+    }
+}
+
+/// Count an IRQ, call its handler and signal the end of the interrupt.
+fn dispatch(irq: u8) {
+    unsafe { atomic_xadd(&mut IRQ_COUNTS[irq as usize], 1); }
+
+    let handler = unsafe { atomic_load(&IRQ_HANDLERS[irq as usize]) };
+    if handler != 0 {
+        let handler: IrqHandler = unsafe { mem::transmute(handler) };
+        handler(irq);
+    }
 
     unsafe {
         local_apic::LOCAL_APIC.end_of_interrupt();
     }
+}
 
+/// Define the interrupt handler of an IRQ
+macro_rules! irq_handler {
+    ($name:ident, $irq:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: &mut ExceptionStackFrame) {
+            let _tls = KernelTls::enter(stack_frame);
+            dispatch($irq);
+        }
+    }
 }
+
+irq_handler!(irq_0, 0);
+irq_handler!(irq_1, 1);
+irq_handler!(irq_2, 2);
+irq_handler!(irq_3, 3);
+irq_handler!(irq_4, 4);
+irq_handler!(irq_5, 5);
+irq_handler!(irq_6, 6);
+irq_handler!(irq_7, 7);
+irq_handler!(irq_8, 8);
+irq_handler!(irq_9, 9);
+irq_handler!(irq_10, 10);
+irq_handler!(irq_11, 11);
+irq_handler!(irq_12, 12);
+irq_handler!(irq_13, 13);
+irq_handler!(irq_14, 14);
+irq_handler!(irq_15, 15);
+irq_handler!(irq_16, 16);
+irq_handler!(irq_17, 17);
+irq_handler!(irq_18, 18);
+irq_handler!(irq_19, 19);
+irq_handler!(irq_20, 20);
+irq_handler!(irq_21, 21);
+irq_handler!(irq_22, 22);
+irq_handler!(irq_23, 23);
+irq_handler!(irq_24, 24);
+irq_handler!(irq_25, 25);
+irq_handler!(irq_26, 26);
+irq_handler!(irq_27, 27);
+irq_handler!(irq_28, 28);
+irq_handler!(irq_29, 29);
+irq_handler!(irq_30, 30);
+irq_handler!(irq_31, 31);
+irq_handler!(irq_32, 32);
+irq_handler!(irq_33, 33);
+irq_handler!(irq_34, 34);
+irq_handler!(irq_35, 35);
+irq_handler!(irq_36, 36);
+irq_handler!(irq_37, 37);
+irq_handler!(irq_38, 38);
+irq_handler!(irq_39, 39);
+irq_handler!(irq_40, 40);
+irq_handler!(irq_41, 41);
+irq_handler!(irq_42, 42);
+irq_handler!(irq_43, 43);
+irq_handler!(irq_44, 44);
+irq_handler!(irq_45, 45);
+irq_handler!(irq_46, 46);
+irq_handler!(irq_47, 47);
+irq_handler!(irq_48, 48);
+irq_handler!(irq_49, 49);
+irq_handler!(irq_50, 50);
+irq_handler!(irq_51, 51);
+irq_handler!(irq_52, 52);
+irq_handler!(irq_53, 53);
+irq_handler!(irq_54, 54);
+irq_handler!(irq_55, 55);
+
+/// Interrupt handlers of the IRQs, indexed by IRQ
+pub static HANDLERS: [HandlerFunc; IRQ_COUNT] = [
+    irq_0, irq_1, irq_2, irq_3, irq_4, irq_5, irq_6, irq_7,
+    irq_8, irq_9, irq_10, irq_11, irq_12, irq_13, irq_14, irq_15,
+    irq_16, irq_17, irq_18, irq_19, irq_20, irq_21, irq_22, irq_23
+];
+
+/// Interrupt handlers of the MSI vectors, indexed by `vector - MSI_OFFSET`
+pub static MSI_HANDLERS: [HandlerFunc; MSI_COUNT] = [
+    irq_24, irq_25, irq_26, irq_27, irq_28, irq_29, irq_30, irq_31,
+    irq_32, irq_33, irq_34, irq_35, irq_36, irq_37, irq_38, irq_39,
+    irq_40, irq_41, irq_42, irq_43, irq_44, irq_45, irq_46, irq_47,
+    irq_48, irq_49, irq_50, irq_51, irq_52, irq_53, irq_54, irq_55
+];
+
+pub extern "x86-interrupt" fn timer(stack_frame: &mut ExceptionStackFrame) {
+    let _tls = KernelTls::enter(stack_frame);
+
+    // every CPU has its own timer, only the BSP keeps the time
+    if start::cpu_id() == 0 {
+        time::tick();
+    }
+
+    unsafe {
+        local_apic::LOCAL_APIC.timer_tick();
+        local_apic::LOCAL_APIC.end_of_interrupt();
+    }
+}
+
+/// Handler for spurious interrupts, of the Local APIC and of the masked PIC.
+///
+/// They must not be acknowledged.
+pub extern "x86-interrupt" fn spurious(_stack_frame: &mut ExceptionStackFrame) {}
@@ -1,18 +1,84 @@
 //! # Exception handler system
 
+use alloc::boxed::Box;
 use memory::MemoryController;
 use x86_64::PrivilegeLevel;
 use x86_64::structures::tss::TaskStateSegment;
-use x86_64::structures::idt::Idt;
-use spin::Once;
+use x86_64::structures::idt::{ExceptionStackFrame, Idt};
+use x86_64::registers::msr::{rdmsr, wrmsr, IA32_FS_BASE};
 
 mod gdt;
-mod ipi;
-mod irq;
+pub mod ipi;
+pub mod irq;
 mod exceptions;
 
 const DOUBLE_FAULT_IST_INDEX: usize = 0;
 
+/// Number of pages of the double fault stack of each CPU. The handler walks the page tables and
+/// prints the fault, which doesn't fit on a single page.
+pub const DOUBLE_FAULT_STACK_PAGES: usize = 4;
+
+/// Vector of the IRQ 0, the others follow it
+pub const IRQ_OFFSET: u8 = 0x20;
+/// Number of IRQs, the inputs of the first I/O APIC
+pub const IRQ_COUNT: usize = 24;
+/// Local APIC timer vector, the Inter-Processor Interrupts (IPIs) follow it, see `ipi::IpiKind`
+pub const TIMER_VECTOR: u8 = 0x40;
+/// Vector of the first Message Signaled Interrupt (MSI), the others follow it
+pub const MSI_OFFSET: u8 = 0x50;
+/// Number of MSI vectors, allocated with `irq::allocate_msi`. They're the IRQs from `IRQ_COUNT`.
+pub const MSI_COUNT: usize = 32;
+/// Vector of the IRQ 0 of the masked 8259 PIC
+pub const PIC_OFFSET: u8 = 0xE0;
+/// Local APIC spurious interrupt vector
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Selector of the kernel thread local segment, whose base is the TCB of the CPU
+const KERNEL_TLS_SELECTOR: u16 = 3 << 3;
+/// Selector of the user thread local segment
+pub const USER_TLS_SELECTOR: u16 = 6 << 3 | 3;
+
+/// Gives the kernel its thread local storage back while an interrupt from userspace is handled.
+///
+/// The FS segment points to the kernel TCB of the CPU while the kernel runs, and to the TCB of the
+/// context while userspace runs, see `usermode`. Interrupt handlers that use the kernel TLS, or
+/// that can switch contexts, start by creating this guard. When the interrupt came from
+/// userspace, the kernel segment is loaded, and the user one is loaded back when it's dropped.
+pub struct KernelTls {
+    /// FS base of the interrupted userspace
+    user_fs_base: Option<u64>
+}
+
+impl KernelTls {
+    /// Load the kernel TLS if the interrupt came from userspace.
+    pub fn enter(stack_frame: &ExceptionStackFrame) -> KernelTls {
+        if stack_frame.code_segment & 3 == 3 {
+            let user_fs_base = rdmsr(IA32_FS_BASE);
+            unsafe { asm!("mov fs, $0" : : "r"(KERNEL_TLS_SELECTOR) : "memory" : "intel", "volatile"); }
+            KernelTls { user_fs_base: Some(user_fs_base) }
+        } else {
+            KernelTls { user_fs_base: None }
+        }
+    }
+}
+
+impl Drop for KernelTls {
+    fn drop(&mut self) {
+        if let Some(user_fs_base) = self.user_fs_base {
+            unsafe {
+                asm!("mov fs, $0" : : "r"(USER_TLS_SELECTOR) : "memory" : "intel", "volatile");
+                wrmsr(IA32_FS_BASE, user_fs_base);
+            }
+        }
+    }
+}
+
+/// Get the index of a vector on the `interrupts` array of the IDT, which starts after the
+/// exceptions.
+fn interrupt_index(vector: u8) -> usize {
+    vector as usize - 32
+}
+
 // The IDT is allocated statically to ensure that this stays in memory until the end of the kernel
 // execution.
 lazy_static! {
@@ -50,69 +116,87 @@ lazy_static! {
         idt.security_exception.set_handler_fn(exceptions::security_exception);
         // 31 reserved
 
+        // set IRQ handlers
+        for (irq, handler) in irq::HANDLERS.iter().enumerate() {
+            idt.interrupts[interrupt_index(IRQ_OFFSET) + irq].set_handler_fn(*handler);
+        }
+
+        // set MSI handlers
+        for (index, handler) in irq::MSI_HANDLERS.iter().enumerate() {
+            idt.interrupts[interrupt_index(MSI_OFFSET) + index].set_handler_fn(*handler);
+        }
+
         // set timer interrupt
-        idt.interrupts[0x40].set_handler_fn(irq::timer).set_privilege_level(PrivilegeLevel::Ring3);
+        idt.interrupts[interrupt_index(TIMER_VECTOR)].set_handler_fn(irq::timer).set_privilege_level(PrivilegeLevel::Ring3);
+
+        // set IPI handlers
+        idt.interrupts[interrupt_index(ipi::IpiKind::Reschedule as u8)].set_handler_fn(ipi::reschedule_handler);
+        idt.interrupts[interrupt_index(ipi::IpiKind::Tlb as u8)].set_handler_fn(ipi::message_handler);
+        idt.interrupts[interrupt_index(ipi::IpiKind::Call as u8)].set_handler_fn(ipi::message_handler);
+        idt.interrupts[interrupt_index(ipi::IpiKind::Halt as u8)].set_handler_fn(ipi::halt_handler);
 
-        // set IPI handler
-        // TODO implement this properly. For this this is just a null interrupt.
-        idt.interrupts[64].set_handler_fn(ipi::ipi);
+        // ignore spurious interrupts
+        idt.interrupts[interrupt_index(PIC_OFFSET + 7)].set_handler_fn(irq::spurious);
+        idt.interrupts[interrupt_index(PIC_OFFSET + 15)].set_handler_fn(irq::spurious);
+        idt.interrupts[interrupt_index(SPURIOUS_VECTOR)].set_handler_fn(irq::spurious);
 
         idt
     };
 }
 
-static TSS: Once<TaskStateSegment> = Once::new();
-static GDT: Once<gdt::Gdt> = Once::new();
-
-// TODO this must be adapted to add support to multi thread systems
-/// Initialize the IDT
+/// Initialize the GDT, TSS and IDT of the BSP
 pub fn init(memory_controller: &mut MemoryController, tcb_offset: usize) {
+    // allocate a double fault stack
+    let double_fault_stack = memory_controller.alloc_stack(DOUBLE_FAULT_STACK_PAGES)
+        .expect("could not allocate double fault stack");
+
+    init_ap(double_fault_stack.top(), tcb_offset);
+}
+
+/// Initialize the GDT, TSS and IDT of the current CPU
+///
+/// Each CPU has its own TSS, with its own double fault stack, and its own GDT, since the thread
+/// local segment points to its own TCB. The IDT is shared.
+pub fn init_ap(double_fault_stack_top: usize, tcb_offset: usize) {
     use x86_64::instructions::segmentation;
     use x86_64::instructions::tables::load_tss;
     use x86_64::PrivilegeLevel;
     use x86_64::VirtualAddress;
     use x86_64::structures::gdt::SegmentSelector;
 
-    // allocate a double fault stack
-    let double_fault_stack = memory_controller.alloc_stack(1).expect("could not allocate double fault stack");
-
-    // configure the task state segment
-    let tss = TSS.call_once(|| {
+    // configure the task state segment, it's used until the CPU is turned off
+    let tss: &'static TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] = VirtualAddress(double_fault_stack.top());
-        tss
-    });
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] = VirtualAddress(double_fault_stack_top);
+        unsafe { &*Box::into_raw(Box::new(tss)) }
+    };
 
     // configure GDT
-    let mut code_selector = SegmentSelector(0);
-    let mut data_selector = SegmentSelector(0);
-    let mut tls_selector = SegmentSelector(0);
-    let mut tss_selector = SegmentSelector(0);
-    let gdt = GDT.call_once(|| {
-        let mut gdt = gdt::Gdt::new();
-        // 1. setup the kernel code segment
-        code_selector = gdt.add_entry(gdt::Descriptor::kernel_code_segment());
+    let mut gdt = gdt::Gdt::new();
+
+    // 1. setup the kernel code segment
+    let code_selector = gdt.add_entry(gdt::Descriptor::kernel_code_segment());
 
-        // 2. setup the kernel data segment
-        data_selector = gdt.add_entry(gdt::Descriptor::kernel_data_segment());
+    // 2. setup the kernel data segment
+    let data_selector = gdt.add_entry(gdt::Descriptor::kernel_data_segment());
 
-        // 3. setup the thread local segment
-        tls_selector = gdt.add_entry(gdt::Descriptor::thread_local_segment(tcb_offset));
+    // 3. setup the thread local segment
+    let tls_selector = gdt.add_entry(gdt::Descriptor::thread_local_segment(tcb_offset));
 
-        // 4. User code
-        gdt.add_entry_user(gdt::Descriptor::user_code_segment());
+    // 4. User code
+    gdt.add_entry_user(gdt::Descriptor::user_code_segment());
 
-        // 5. User data
-        gdt.add_entry_user(gdt::Descriptor::user_data_segment());
+    // 5. User data
+    gdt.add_entry_user(gdt::Descriptor::user_data_segment());
 
-        // 6. User TLS
-        gdt.add_entry_user(gdt::Descriptor::user_thread_local_segment(::USER_TCB_OFFSET));
+    // 6. User TLS
+    gdt.add_entry_user(gdt::Descriptor::user_thread_local_segment(::USER_TCB_OFFSET));
 
-        // 7/8. setup the TSS segment
-        tss_selector = gdt.add_entry(gdt::Descriptor::tss_segment(&tss));
+    // 7/8. setup the TSS segment
+    let tss_selector = gdt.add_entry(gdt::Descriptor::tss_segment(tss));
 
-        gdt
-    });
+    // like the TSS, the GDT is used until the CPU is turned off
+    let gdt: &'static gdt::Gdt = unsafe { &*Box::into_raw(Box::new(gdt)) };
     gdt.load();
 
     unsafe {
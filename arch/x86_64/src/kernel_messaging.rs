@@ -1,13 +1,7 @@
-use vga_buffer;
-use vga_buffer::ColorCode as ColorCode;
-use vga_buffer::Color as Color;
+//! Colored kernel messages, kept for compatibility: they now go through the kernel log, which
+//! colors the screen output by level.
 
-const DEFAULT_COLOR_CODE: ColorCode = vga_buffer::DEFAULT_COLOR_CODE;
-// const DEFAULT_COLOR_CODE: ColorCode = ColorCode::new(Color::White, Color::Black);
-const SUCCESS_COLOR_CODE: ColorCode = ColorCode::new(Color::White, Color::Green);
-const ERROR_COLOR_CODE: ColorCode = ColorCode::new(Color::White, Color::Red);
-const WARNING_COLOR_CODE: ColorCode = ColorCode::new(Color::White, Color::Brown);
-const INFO_COLOR_CODE: ColorCode = ColorCode::new(Color::White, Color::Blue);
+use klog::{self, Level};
 
 pub enum MessageType {
     SUCCESS,
@@ -17,30 +11,12 @@ pub enum MessageType {
     DEFAULT
 }
 
-fn restore_defauls() {
-    vga_buffer::WRITER.lock().set_color_code(DEFAULT_COLOR_CODE);
-}
-
-
 pub fn kprint(message_type: MessageType, message: &str) {
-    match message_type {
-        MessageType::SUCCESS => {
-            vga_buffer::WRITER.lock().set_color_code(SUCCESS_COLOR_CODE);
-        }
-        MessageType::ERROR => {
-            vga_buffer::WRITER.lock().set_color_code(ERROR_COLOR_CODE);
-        }
-        MessageType::WARNING => {
-            vga_buffer::WRITER.lock().set_color_code(WARNING_COLOR_CODE);
-        }
-        MessageType::INFO => {
-            vga_buffer::WRITER.lock().set_color_code(INFO_COLOR_CODE);
-        }
-        MessageType::DEFAULT => {
-            vga_buffer::WRITER.lock().set_color_code(DEFAULT_COLOR_CODE);
-        }
-    }
-    println!("{}", message);
-    restore_defauls();
-}
+    let level = match message_type {
+        MessageType::ERROR => Level::Error,
+        MessageType::WARNING => Level::Warn,
+        MessageType::SUCCESS | MessageType::INFO | MessageType::DEFAULT => Level::Info
+    };
 
+    klog::log(level, "kernel", format_args!("{}", message));
+}
@@ -0,0 +1,272 @@
+//! # Kernel log
+//!
+//! Each message has a level and the module that logged it as tag. The messages up to the current
+//! level are kept on a ring buffer, from where the `log:` scheme drains them, and are printed on
+//! the enabled sinks: the VGA screen, colored by level, and COM1.
+//!
+//! Each record is a line like `[    1.234567] INFO  hpet: 14 MHz, 64 bits counter`, timestamped
+//! with the monotonic clock.
+
+use core::cmp;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::registers::flags::{self, IF};
+
+use device::serial;
+use interrupts;
+use time;
+use vga_buffer::{self, Color, ColorCode};
+
+/// Log a message with the given level, tagged with the current module.
+#[macro_export]
+macro_rules! klog {
+    ($level:expr, $($arg:tt)*) => ({
+        $crate::klog::log($level, module_path!(), format_args!($($arg)*));
+    });
+}
+
+/// Log an error
+#[macro_export]
+macro_rules! kerror {
+    ($($arg:tt)*) => (klog!($crate::klog::Level::Error, $($arg)*));
+}
+
+/// Log a warning
+#[macro_export]
+macro_rules! kwarn {
+    ($($arg:tt)*) => (klog!($crate::klog::Level::Warn, $($arg)*));
+}
+
+/// Log an information
+#[macro_export]
+macro_rules! kinfo {
+    ($($arg:tt)*) => (klog!($crate::klog::Level::Info, $($arg)*));
+}
+
+/// Log a debug message
+#[macro_export]
+macro_rules! kdebug {
+    ($($arg:tt)*) => (klog!($crate::klog::Level::Debug, $($arg)*));
+}
+
+/// Log a trace message
+#[macro_export]
+macro_rules! ktrace {
+    ($($arg:tt)*) => (klog!($crate::klog::Level::Trace, $($arg)*));
+}
+
+/// Size of the ring buffer, in bytes
+pub const LOG_SIZE: usize = 64 * 1024;
+
+/// Importance of a message, the lower the more important
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5
+}
+
+impl Level {
+    /// Get the level with the given value.
+    pub fn from_usize(value: usize) -> Option<Level> {
+        match value {
+            1 => Some(Level::Error),
+            2 => Some(Level::Warn),
+            3 => Some(Level::Info),
+            4 => Some(Level::Debug),
+            5 => Some(Level::Trace),
+            _ => None
+        }
+    }
+
+    /// Get the level with the given name, like `warn`.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None
+        }
+    }
+
+    /// Get the name of the level, as `from_name` takes it.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace"
+        }
+    }
+
+    /// Get the label of the level on the records.
+    fn label(&self) -> &'static str {
+        match *self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE"
+        }
+    }
+
+    /// Get the color of the level on the screen.
+    fn color_code(&self) -> ColorCode {
+        match *self {
+            Level::Error => ColorCode::new(Color::White, Color::Red),
+            Level::Warn => ColorCode::new(Color::White, Color::Brown),
+            Level::Info => vga_buffer::DEFAULT_COLOR_CODE,
+            Level::Debug | Level::Trace => ColorCode::new(Color::DarkGray, Color::White)
+        }
+    }
+}
+
+bitflags! {
+    /// Outputs where the messages are printed, besides the ring buffer
+    pub flags Sinks: usize {
+        const SINK_VGA = 1,
+        const SINK_SERIAL = 1 << 1,
+    }
+}
+
+/// Most verbose level kept, as a `Level`
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+/// Enabled sinks, as `Sinks`
+static SINKS: AtomicUsize = AtomicUsize::new(SINK_VGA.bits | SINK_SERIAL.bits);
+
+static BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+
+/// Get the most verbose level kept.
+pub fn level() -> Level {
+    Level::from_usize(LEVEL.load(Ordering::SeqCst)).unwrap_or(Level::Info)
+}
+
+/// Change the most verbose level kept, the messages above it are dropped.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::SeqCst);
+}
+
+/// Get the enabled sinks.
+pub fn sinks() -> Sinks {
+    Sinks::from_bits_truncate(SINKS.load(Ordering::SeqCst))
+}
+
+/// Change the enabled sinks. The ring buffer always keeps the messages.
+pub fn set_sinks(sinks: Sinks) {
+    SINKS.store(sinks.bits(), Ordering::SeqCst);
+}
+
+/// Messages as text, the oldest ones are overwritten when it's full
+struct LogBuffer {
+    data: [u8; LOG_SIZE],
+    /// Number of bytes written since the boot, the next byte goes at `written % LOG_SIZE`
+    written: usize
+}
+
+impl LogBuffer {
+    const fn new() -> LogBuffer {
+        LogBuffer {
+            data: [0; LOG_SIZE],
+            written: 0
+        }
+    }
+
+    /// Position of the oldest byte still on the buffer
+    fn oldest(&self) -> usize {
+        self.written.saturating_sub(LOG_SIZE)
+    }
+
+    fn byte(&self, position: usize) -> u8 {
+        self.data[position % LOG_SIZE]
+    }
+}
+
+impl Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.data[self.written % LOG_SIZE] = byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Log a message, use `klog!` and the macros of each level instead.
+///
+/// ## Parameters
+/// - `level`: importance of the message, it's dropped when it's above `level()`.
+/// - `module`: path of the module that logged it, its last part is the tag.
+/// - `args`: the message.
+pub fn log(level: Level, module: &str, args: fmt::Arguments) {
+    if level > self::level() {
+        return;
+    }
+
+    let tag = module.rsplit("::").next().unwrap_or(module);
+    let (secs, nanos) = time::monotonic();
+    let micros = nanos / 1000;
+
+    // an interrupt handler that logs must not find the locks taken on this CPU
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { interrupts::disable(); }
+
+    write!(BUFFER.lock(), "[{:>5}.{:06}] {:<5} {}: {}\n", secs, micros, level.label(), tag, args).unwrap();
+
+    let sinks = sinks();
+    if sinks.contains(SINK_VGA) {
+        let mut writer = vga_buffer::WRITER.lock();
+        writer.set_color_code(level.color_code());
+        write!(writer, "{}: {}", tag, args).unwrap();
+        writer.set_color_code(vga_buffer::DEFAULT_COLOR_CODE);
+        writer.write_str("\n").unwrap();
+    }
+    if sinks.contains(SINK_SERIAL) {
+        serial::print(format_args!("[{:>5}.{:06}] {:<5} {}: {}\n", secs, micros, level.label(), tag, args));
+    }
+
+    if interrupts_enabled {
+        unsafe { interrupts::enable(); }
+    }
+}
+
+/// Copy the log, starting at `position`, to `buffer`.
+///
+/// A position that was already overwritten moves to the oldest full record still on the buffer,
+/// so a slow reader loses whole records.
+///
+/// ## Returns
+/// The number of bytes copied and the position after them, zero bytes when the reader is up to
+/// date.
+pub fn read(position: usize, buffer: &mut [u8]) -> (usize, usize) {
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { interrupts::disable(); }
+
+    let log = BUFFER.lock();
+
+    let mut position = position;
+    if position < log.oldest() {
+        position = log.oldest();
+        while position < log.written && log.byte(position) != b'\n' {
+            position += 1;
+        }
+        position = cmp::min(position + 1, log.written);
+    }
+
+    let count = cmp::min(buffer.len(), log.written.saturating_sub(position));
+    for i in 0..count {
+        buffer[i] = log.byte(position + i);
+    }
+
+    drop(log);
+    if interrupts_enabled {
+        unsafe { interrupts::enable(); }
+    }
+
+    (count, position + count)
+}
@@ -6,6 +6,7 @@
 #![no_std]
 #![feature(alloc, collections)]
 #![feature(core_intrinsics)]
+#![feature(thread_local)]
 
 extern crate bit_field;
 #[macro_use]
@@ -29,11 +30,18 @@ extern crate once;
 // Make constants public
 pub use consts::*;
 
+/// ANSI escape sequences parser
+pub mod ansi;
+
 #[macro_use]
 /// Console handling
 pub mod vga_buffer;
 
-/// Kernel message writer
+#[macro_use]
+/// Leveled kernel log
+pub mod klog;
+
+/// Kernel message writer, on top of `klog`
 pub mod kernel_messaging;
 
 /// ACPI manager
@@ -48,12 +56,18 @@ pub mod context;
 /// Devices management
 pub mod device;
 
+/// Bitmap font of the framebuffer console
+pub mod font;
+
 /// Memory management
 pub mod memory;
 
 /// Interrupt instructions
 pub mod interrupts;
 
+/// Symmetric multiprocessing
+pub mod smp;
+
 /// Initialization and start function
 pub mod start;
 
@@ -68,6 +82,9 @@ extern "C" fn eh_personality() {}
 #[lang = "panic_fmt"]
 #[no_mangle]
 pub extern "C" fn panic_fmt(fmt: core::fmt::Arguments, file: &'static str, line: u32) -> ! {
+    // stop the other CPUs, so they don't keep running on a broken kernel
+    interrupts::ipi::halt(interrupts::ipi::IpiTarget::Other);
+
     println!("\n\nPANIC in {} at line {}:", file, line);
     println!("    {}", fmt);
     loop {}
@@ -81,9 +98,14 @@ pub extern "C" fn _Unwind_Resume() -> ! {
 
 /// Enter in usermode.
 ///
+/// The FS segment is switched from the kernel TCB of the CPU to the user TCB at `fs_base`, with
+/// interrupts disabled until `iretq`, since the kernel TLS can't be used from there.
+///
 /// This functions never returns.
-pub unsafe fn usermode(ip: usize, sp: usize) -> ! {
+pub unsafe fn usermode(ip: usize, sp: usize, fs_base: usize) -> ! {
     asm!("
+        cli
+
         mov ds, ax
         mov es, ax
         mov fs, bx
@@ -95,10 +117,17 @@ pub unsafe fn usermode(ip: usize, sp: usize) -> ! {
         push rsi
         push rdi
 
+        mov ecx, 0xC0000100
+        mov rax, r8
+        mov rdx, r8
+        shr rdx, 32
+        wrmsr
+
         iretq"
         :
         :   "{rax}"(5 << 3 | 3)         // Data segment
-            "{rbx}"(6 << 3 | 3)         // TLS segment
+            "{rbx}"(interrupts::USER_TLS_SELECTOR) // TLS segment
+            "{r8}"(fs_base)             // TLS segment base (IA32_FS_BASE)
             "{rcx}"(sp)                 // Stack pointer
             "{rdx}"(3 << 12 | 1 << 9)   // Flags - Set IOPL and interrupt enable flag
             "{rsi}"(4 << 3 | 3)         // Code segment
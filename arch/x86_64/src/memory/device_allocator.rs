@@ -0,0 +1,39 @@
+use memory::PAGE_SIZE;
+use memory::paging::VirtualAddress;
+
+/// Allocator of the kernel device area, where the device memory (configuration spaces, BARs, the
+/// framebuffer) is mapped. The area is above all the user areas, so device memory never takes an
+/// address that userspace could ask for.
+///
+/// The allocations are never given back, devices stay mapped until the machine stops.
+pub struct DeviceAllocator {
+    /// First address that isn't allocated yet
+    next: VirtualAddress,
+    /// End of the area (exclusive)
+    end: VirtualAddress,
+}
+
+impl DeviceAllocator {
+    pub fn new(start: VirtualAddress, size: usize) -> DeviceAllocator {
+        DeviceAllocator {
+            next: start,
+            end: start + size,
+        }
+    }
+
+    /// Reserve `size` bytes of the area, rounded up to whole pages. The pages aren't mapped.
+    pub fn reserve(&mut self, size: usize) -> Option<VirtualAddress> {
+        let pages_size = match size.checked_add(PAGE_SIZE - 1) {
+            Some(size) => size / PAGE_SIZE * PAGE_SIZE,
+            None => return None
+        };
+
+        if pages_size == 0 || pages_size > self.end - self.next {
+            return None;
+        }
+
+        let address = self.next;
+        self.next += pages_size;
+        Some(address)
+    }
+}
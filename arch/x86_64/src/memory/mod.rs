@@ -1,14 +1,18 @@
 pub use self::area_frame_allocator::AreaFrameAllocator;
 pub use self::paging::ActivePageTable;
-pub use self::paging::remap_the_kernel;
+pub use self::paging::{remap_the_kernel, init_tcb};
 pub use self::stack_allocator::Stack;
 
-use self::paging::PhysicalAddress;
+use self::paging::{PhysicalAddress, VirtualAddress};
 use multiboot2::BootInformation;
+use {KERNEL_DEVICE_OFFSET, KERNEL_DEVICE_SIZE, KERNEL_HEAP_OFFSET, KERNEL_HEAP_SIZE, KERNEL_IMAGE_END};
 
 /// Frame allocator.
 mod area_frame_allocator;
 
+/// Device memory allocator.
+mod device_allocator;
+
 /// Paging system.
 pub mod paging;
 
@@ -18,6 +22,12 @@ mod stack_allocator;
 /// Size of a page
 pub const PAGE_SIZE: usize = 4096;
 
+/// Number of pages reserved for stacks (guard pages included).
+const STACK_AREA_PAGES: usize = 1024;
+
+/// Memory below this address is never allocated. It holds BIOS data and the AP trampoline.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
 /// A memory map area
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(C)]
@@ -84,14 +94,26 @@ pub fn init(cpu_id: usize, boot_info: &BootInformation) -> (MemoryController, us
     // get the kernel end address
     let kernel_end = elf_sections_tag.sections().map(|s| s.addr + s.size).max().unwrap();
 
+    // the kernel image and the multiboot information are mapped on every address space, so user
+    // images must stay out of their area
+    assert!(kernel_end as usize <= KERNEL_IMAGE_END && boot_info.end_address() <= KERNEL_IMAGE_END,
+            "the kernel image and the multiboot information must end below {:#x}", KERNEL_IMAGE_END);
+
     // make a entire copy from the multiboot areas. This is needed in order to put the MemoryController available to the kernel.
     unsafe {
         let mut index = 0;
         for cur_area in memory_map_tag.memory_areas() {
+            // skip the low memory
+            let area_end = cur_area.base_addr + cur_area.length;
+            let base_addr = if cur_area.base_addr < LOW_MEMORY_END { LOW_MEMORY_END } else { cur_area.base_addr };
+            if area_end <= base_addr {
+                continue;
+            }
+
             let mut entry = &mut MEMORY_MAP[index];
 
-            entry.base_addr = cur_area.base_addr;
-            entry.length = cur_area.length;
+            entry.base_addr = base_addr;
+            entry.length = area_end - base_addr;
             entry._type = 1;
 
             // increment the index
@@ -123,7 +145,12 @@ pub fn init(cpu_id: usize, boot_info: &BootInformation) -> (MemoryController, us
     let stack_allocator = {
         // calculate the start and end address of the stack
         let stack_alloc_start = heap_end_page + 1;
-        let stack_alloc_end = stack_alloc_start + 100;
+        let stack_alloc_end = stack_alloc_start + STACK_AREA_PAGES;
+
+        // user images must stay out of the heap and stacks area too
+        assert!(HEAP_START == KERNEL_HEAP_OFFSET &&
+                stack_alloc_end.start_address() + PAGE_SIZE <= KERNEL_HEAP_OFFSET + KERNEL_HEAP_SIZE,
+                "the kernel heap and stacks must be inside of their area");
 
         // create a new page range with the stack start address and end address
         let stack_alloc_range = Page::range_inclusive(stack_alloc_start, stack_alloc_end);
@@ -136,13 +163,46 @@ pub fn init(cpu_id: usize, boot_info: &BootInformation) -> (MemoryController, us
     let memory_controller = MemoryController {
         active_table: active_table,
         frame_allocator: frame_allocator,
-        stack_allocator: stack_allocator
+        stack_allocator: stack_allocator,
+        device_allocator: device_allocator::DeviceAllocator::new(KERNEL_DEVICE_OFFSET, KERNEL_DEVICE_SIZE)
     };
 
     // returns the memory controller and the tcb offset
     (memory_controller, tcb_offset)
 }
 
+/// Map the per-CPU area of an AP.
+///
+/// This is called by the BSP before starting the AP, which then sets up its TCB with `init_tcb`.
+pub fn init_ap(cpu_id: usize, memory_controller: &mut MemoryController) {
+    let &mut MemoryController {
+        ref mut active_table,
+        ref mut frame_allocator,
+        .. } = memory_controller;
+
+    paging::map_percpu(active_table, cpu_id, frame_allocator);
+}
+
+/// Check if the address belongs to the guard page of a stack allocated with
+/// `MemoryController::alloc_stack`.
+///
+/// A guard page is an unmapped page, inside of the stack area, that is immediately followed by the
+/// bottom of a mapped stack. This doesn't take any lock, so it's safe to call from a double fault.
+pub fn is_stack_guard_page(address: usize) -> bool {
+    use self::paging::VirtualAddress;
+
+    if !stack_allocator::in_stack_area(address as VirtualAddress) {
+        return false;
+    }
+
+    let active_table = unsafe { ActivePageTable::new() };
+    let page_start = address & !(PAGE_SIZE - 1);
+
+    active_table.translate(page_start).is_none() &&
+        stack_allocator::in_stack_area(page_start + PAGE_SIZE) &&
+        active_table.translate(page_start + PAGE_SIZE).is_some()
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Frame {
     number: usize,
@@ -197,6 +257,7 @@ pub struct MemoryController {
     pub active_table: paging::ActivePageTable,
     pub frame_allocator: AreaFrameAllocator,
     stack_allocator: stack_allocator::StackAllocator,
+    device_allocator: device_allocator::DeviceAllocator,
 }
 
 impl MemoryController {
@@ -216,6 +277,48 @@ impl MemoryController {
         stack_allocator.alloc_stack(active_table, frame_allocator, size_in_pages)
     }
 
+    /// Free a stack allocated with `alloc_stack`, so it can be allocated again.
+    ///
+    /// Nothing must be running on the stack anymore.
+    pub fn free_stack(&mut self, stack: Stack) {
+        self.stack_allocator.free_stack(stack);
+    }
+
+    /// Reserve `size` bytes of the kernel device area, rounded up to whole pages, to map device
+    /// memory on them later with `map_to`.
+    pub fn reserve_device(&mut self, size: usize) -> Option<VirtualAddress> {
+        self.device_allocator.reserve(size)
+    }
+
+    /// Map `size` bytes of device memory, from the physical address `address`, on the kernel device
+    /// area.
+    ///
+    /// ## Returns
+    /// The virtual address of `address`, or `None` when the area is full.
+    pub fn map_device(&mut self, address: PhysicalAddress, size: usize,
+                      flags: paging::entry::EntryFlags) -> Option<VirtualAddress> {
+        use self::paging::Page;
+
+        let offset = address % PAGE_SIZE;
+        let size = match size.checked_add(offset) {
+            Some(size) => size,
+            None => return None
+        };
+        let start = match self.device_allocator.reserve(size) {
+            Some(start) => start,
+            None => return None
+        };
+
+        let start_page = Page::containing_address(start);
+        let end_page = Page::containing_address(start + size - 1);
+        for (index, page) in Page::range_inclusive(start_page, end_page).enumerate() {
+            let frame = Frame::containing_address(address - offset + index * PAGE_SIZE);
+            self.map_to(page, frame, flags);
+        }
+
+        Some(start + offset)
+    }
+
     /// Maps the page to the frame with the provided flags.
     /// The `PRESENT` flag is added by default. Needs a `FrameAllocator` as it might need to create
     /// new page tables.
@@ -230,12 +333,14 @@ impl MemoryController {
     }
 
     /// Update flags for a page
-    pub fn remap(&mut self, active_table: &mut ActivePageTable, page: paging::Page, flags: paging::entry::EntryFlags) {
+    ///
+    /// The page must be flushed from the TLBs with the returned `MapperFlush`.
+    pub fn remap(&mut self, active_table: &mut ActivePageTable, page: paging::Page, flags: paging::entry::EntryFlags) -> paging::MapperFlush {
         active_table.remap(page, flags)
     }
 
-    /// Flush the TLB table
+    /// Flush the whole TLB, on the CPUs that have the current page table loaded
     pub fn flush_all(&mut self) {
-        self.active_table.flush_all();
+        paging::flush::flush_all();
     }
 }
@@ -0,0 +1,155 @@
+//! # TLB flushing
+//!
+//! Changing a page table entry leaves the old translation on the TLB of the CPUs that used it.
+//! The change is invalidated with `invlpg` on the current CPU and with a TLB shootdown IPI on the
+//! other CPUs that have the same page table loaded. Each CPU records the page table it loads, with
+//! `set_loaded_table`, before loading it.
+//!
+//! The `Mapper` functions that change an entry return a `MapperFlush`. When many pages change at
+//! once, they are added to a `FlushRange`, so the other CPUs are only interrupted once.
+
+use core::cmp;
+use core::intrinsics::{atomic_load, atomic_store};
+use x86_64::VirtualAddress as X86VirtualAddress;
+use x86_64::instructions::tlb;
+use x86_64::registers::control_regs;
+
+use interrupts::ipi::{self, IpiTarget};
+use memory::PAGE_SIZE;
+use start;
+use MAX_CPU_COUNT;
+use super::{Page, PhysicalAddress, VirtualAddress};
+
+/// Above this number of pages, the whole TLB is flushed instead of each page
+const FLUSH_ALL_THRESHOLD: usize = 32;
+
+/// Physical address of the page table loaded on each CPU, indexed by CPU id. It's zero until the
+/// CPU can handle TLB shootdowns.
+static mut LOADED_TABLES: [PhysicalAddress; MAX_CPU_COUNT] = [0; MAX_CPU_COUNT];
+
+/// Record the page table that the current CPU loaded at boot, so it gets its TLB shootdowns.
+///
+/// This must be called on each CPU once the kernel TLS is set up.
+pub fn init() {
+    set_loaded_table(control_regs::cr3().0 as PhysicalAddress);
+}
+
+/// Record the page table that the current CPU is about to load.
+///
+/// It must be recorded before loading it, so a change made meanwhile is either seen by the new
+/// translations or flushed by a TLB shootdown.
+pub fn set_loaded_table(address: PhysicalAddress) {
+    unsafe { atomic_store(&mut LOADED_TABLES[start::cpu_id()], address); }
+}
+
+/// Get the other CPUs that have the page table of the current CPU loaded, as a mask of CPU ids.
+fn sharing_cpus() -> u64 {
+    let cpu_id = start::cpu_id();
+    let table = unsafe { atomic_load(&LOADED_TABLES[cpu_id]) };
+
+    (0..start::cpu_count())
+        .filter(|&other| other != cpu_id && unsafe { atomic_load(&LOADED_TABLES[other]) } == table)
+        .fold(0, |mask, other| mask | 1 << other)
+}
+
+/// Invalidate the TLB entries of the pages on the range `start..end`, on the current CPU only.
+pub fn flush_local(start: VirtualAddress, end: VirtualAddress) {
+    let start_page = start / PAGE_SIZE;
+    let end_page = end / PAGE_SIZE + if end % PAGE_SIZE != 0 { 1 } else { 0 };
+
+    if end_page.saturating_sub(start_page) > FLUSH_ALL_THRESHOLD {
+        tlb::flush_all();
+    } else {
+        for page in start_page..end_page {
+            tlb::flush(X86VirtualAddress(page * PAGE_SIZE));
+        }
+    }
+}
+
+/// Invalidate the TLB entries of the pages on the range `start..end`, on the CPUs that have the
+/// current page table loaded.
+///
+/// This waits until the other CPUs are done.
+pub fn flush_range(start: VirtualAddress, end: VirtualAddress) {
+    flush_local(start, end);
+
+    // before the APs start there is no one else to tell, nor a kernel TLS to find the current CPU
+    if start::cpu_count() > 1 {
+        let mask = sharing_cpus();
+        if mask != 0 {
+            ipi::tlb_shootdown(IpiTarget::Mask(mask), start, end);
+        }
+    }
+}
+
+/// Flush the whole TLB, on the CPUs that have the current page table loaded.
+pub fn flush_all() {
+    flush_range(0, usize::max_value());
+}
+
+/// A page whose entry changed, and which must be flushed from the TLBs
+#[must_use = "the page must be flushed from the TLBs after changing its entry"]
+pub struct MapperFlush(Page);
+
+impl MapperFlush {
+    /// Create a new flush for the given page
+    pub fn new(page: Page) -> MapperFlush {
+        MapperFlush(page)
+    }
+
+    /// Invalidate the page on the CPUs that have the current page table loaded.
+    pub fn flush(self) {
+        let start = self.0.start_address();
+        flush_range(start, start + PAGE_SIZE);
+    }
+
+    /// Invalidate the page on the current CPU only, for when the other CPUs can't handle a TLB
+    /// shootdown and flush their TLBs by themselves later.
+    pub fn flush_local(self) {
+        let start = self.0.start_address();
+        flush_local(start, start + PAGE_SIZE);
+    }
+
+    /// Don't flush the page now, the caller flushes it later, usually with a `FlushRange`.
+    pub fn ignore(self) {}
+}
+
+/// A range of pages whose entries changed, flushed all at once
+#[must_use = "the range must be flushed from the TLBs after changing its entries"]
+pub struct FlushRange {
+    start: VirtualAddress,
+    end: VirtualAddress
+}
+
+impl FlushRange {
+    /// Create an empty range
+    pub fn new() -> FlushRange {
+        FlushRange {
+            start: 0,
+            end: 0
+        }
+    }
+
+    /// Add a changed page to the range.
+    ///
+    /// The range grows to cover it, so this is meant for pages that are close to each other.
+    pub fn add(&mut self, flush: MapperFlush) {
+        let start = flush.0.start_address();
+        let end = start + PAGE_SIZE;
+
+        if self.start == self.end {
+            self.start = start;
+            self.end = end;
+        } else {
+            self.start = cmp::min(self.start, start);
+            self.end = cmp::max(self.end, end);
+        }
+    }
+
+    /// Invalidate all the pages of the range on the CPUs that have the current page table loaded.
+    pub fn flush(self) {
+        if self.start != self.end {
+            flush_range(self.start, self.end);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use super::{VirtualAddress, PhysicalAddress, Page, ENTRY_COUNT};
+use super::flush::MapperFlush;
 use super::entry::*;
 use super::table::{self, Table, Level4};
 use memory::{PAGE_SIZE, Frame, FrameAllocator};
@@ -67,6 +68,16 @@ impl Mapper {
             .or_else(huge_page)
     }
 
+    /// Get the flags of the entry that maps a page.
+    /// Returns `None` if the page is not mapped, or is part of a huge page.
+    pub fn page_flags(&self, page: Page) -> Option<EntryFlags> {
+        self.p4().next_table(page.p4_index())
+            .and_then(|p3| p3.next_table(page.p3_index()))
+            .and_then(|p2| p2.next_table(page.p2_index()))
+            .map(|p1| p1[page.p1_index()].flags())
+            .and_then(|flags| if flags.contains(PRESENT) { Some(flags) } else { None })
+    }
+
     /// Maps the page to the frame with the provided flags.
     /// The `PRESENT` flag is added by default. Needs a `FrameAllocator` as it might need to create
     /// new page tables.
@@ -91,18 +102,16 @@ impl Mapper {
     }
 
     /// Update flags for a page
-    pub fn remap(&mut self, page: Page, flags: EntryFlags) {
-        use x86_64::instructions::tlb;
-        use x86_64::VirtualAddress;
-
+    ///
+    /// The page must be flushed from the TLBs with the returned `MapperFlush`.
+    pub fn remap(&mut self, page: Page, flags: EntryFlags) -> MapperFlush {
         let mut p3 = self.p4_mut().next_table_mut(page.p4_index()).expect("Failed to remap: no p3");
         let mut p2 = p3.next_table_mut(page.p3_index()).expect("Failed to remap: no p2");
         let mut p1 = p2.next_table_mut(page.p2_index()).expect("Failed to remap: no p1");
         let frame = p1[page.p1_index()].pointed_frame().expect("Failed to remap: not mapped");
         p1[page.p1_index()].set(frame, flags | PRESENT);
 
-        // Flush page TLB
-        tlb::flush(VirtualAddress(page.start_address()));
+        MapperFlush::new(page)
     }
 
     /// Identity map the given frame with the provided flags.
@@ -115,12 +124,11 @@ impl Mapper {
     }
 
     /// Unmaps the given page and adds all freed frames to the given `FrameAllocator`.
-    pub fn unmap<A>(&mut self, page: Page, allocator: &mut A)
+    ///
+    /// The page must be flushed from the TLBs with the returned `MapperFlush`.
+    pub fn unmap<A>(&mut self, page: Page, allocator: &mut A) -> MapperFlush
         where A: FrameAllocator
     {
-        use x86_64::VirtualAddress;
-        use x86_64::instructions::tlb;
-
         assert!(self.translate(page.start_address()).is_some());
 
         let p1 = self.p4_mut()
@@ -131,10 +139,9 @@ impl Mapper {
         let frame = p1[page.p1_index()].pointed_frame().unwrap();
         p1[page.p1_index()].set_unused();
 
-        // flush page address from the TLB
-        tlb::flush(VirtualAddress(page.start_address()));
-
         // TODO free p(1,2,3) table if empty
         // allocator.deallocate_frame(frame);
+
+        MapperFlush::new(page)
     }
 }
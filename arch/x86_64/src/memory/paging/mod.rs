@@ -5,22 +5,20 @@ use core::{mem, ptr};
 use core::ops::{Add, Deref, DerefMut};
 use memory::{PAGE_SIZE, Frame, FrameAllocator};
 use self::temporary_page::TemporaryPage;
+pub use self::flush::{FlushRange, MapperFlush};
 pub use self::mapper::Mapper;
 pub use self::entry::*;
 use multiboot2::BootInformation;
+use {KERNEL_PERCPU_OFFSET, KERNEL_PERCPU_SIZE};
 
 pub mod entry;
+pub mod flush;
 mod mapper;
 mod table;
 mod temporary_page;
 
 const ENTRY_COUNT: usize = 512;
 
-// TODO move this to a separated module
-const KERNEL_PERCPU_OFFSET: usize = 0xc000_0000;
-/// Size of kernel percpu variables
-const KERNEL_PERCPU_SIZE: usize = 64 * 1024; // 64kb
-
 pub type PhysicalAddress = usize;
 pub type VirtualAddress = usize;
 
@@ -166,7 +164,7 @@ impl ActivePageTable {
         old_table
     }
 
-    /// Flush all the TLB table
+    /// Flush all the TLB table of the current CPU
     pub fn flush_all(&mut self) {
         use x86_64::instructions::tlb;
 
@@ -205,8 +203,34 @@ impl InactivePageTable {
     }
 }
 
+/// Map the per-CPU area of the given CPU, where its kernel TLS lives.
+pub fn map_percpu<A>(mapper: &mut Mapper, cpu_id: usize, allocator: &mut A)
+    where A: FrameAllocator
+{
+    extern {
+        /// The starting byte of the thread data segment
+        static mut __tdata_start: u8;
+        /// The ending byte of the thread BSS segment
+        static mut __tbss_end: u8;
+    }
+
+    // get the thread segment size.
+    let size = unsafe { & __tbss_end as *const _ as usize - & __tdata_start as *const _ as usize };
+
+    let start = KERNEL_PERCPU_OFFSET + KERNEL_PERCPU_SIZE * cpu_id;
+    let end = start + size;
+
+    let start_page = Page::containing_address(start as VirtualAddress);
+    let end_page = Page::containing_address((end - 1) as VirtualAddress);
+    for page in Page::range_inclusive(start_page, end_page) {
+        mapper.map(page, PRESENT | GLOBAL | NO_EXECUTE | WRITABLE, allocator);
+    }
+}
+
 /// Copy tdata, clear tbss, set TCB self pointer
-unsafe fn init_tcb(cpu_id: usize) -> usize {
+///
+/// The per-CPU area of the CPU must be already mapped.
+pub unsafe fn init_tcb(cpu_id: usize) -> usize {
     extern {
         /// The starting byte of the thread data segment
         static mut __tdata_start: u8;
@@ -281,20 +305,7 @@ pub unsafe fn remap_the_kernel<A>(cpu_id: usize, allocator: &mut A, boot_info: &
 
     active_table.with(&mut new_table, &mut temporary_page, |mapper| {
         // Map tdata and tbss
-        {
-            // get the thread segment size.
-            let size = & __tbss_end as *const _ as usize - & __tdata_start as *const _ as usize;
-
-            // TODO add support to multiple CPUs (replace the numeric constant)
-            let start = KERNEL_PERCPU_OFFSET + KERNEL_PERCPU_SIZE * cpu_id;
-            let end = start + size;
-
-            let start_page = Page::containing_address(start as VirtualAddress);
-            let end_page = Page::containing_address((end - 1) as VirtualAddress);
-            for page in Page::range_inclusive(start_page, end_page) {
-                mapper.map(page, PRESENT | GLOBAL | NO_EXECUTE | WRITABLE, allocator);
-            }
-        }
+        map_percpu(mapper, cpu_id, allocator);
 
         // Function to remap the kernel sections individually. We can no longer use the multiboot
         // sections because of the thread base sections.
@@ -341,8 +352,8 @@ pub unsafe fn remap_the_kernel<A>(cpu_id: usize, allocator: &mut A, boot_info: &
 
     // turn the old p4 page into a guard page
     let old_p4_page = Page::containing_address(old_table.p4_frame.start_address());
-    active_table.unmap(old_p4_page, allocator);
-    println!("guard page at {:#x}", old_p4_page.start_address());
+    active_table.unmap(old_p4_page, allocator).flush();
+    kdebug!("guard page at {:#x}", old_p4_page.start_address());
 
     // initialize tcb and return the tcb address and the active page table reference
     (active_table, init_tcb(cpu_id))
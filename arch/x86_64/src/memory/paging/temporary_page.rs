@@ -32,7 +32,7 @@ impl TemporaryPage {
 
     /// Unmaps the temporary page in the active table.
     pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
-        active_table.unmap(self.page, &mut self.allocator)
+        active_table.unmap(self.page, &mut self.allocator).flush();
     }
 
     /// Maps the temporary page to the given page table frame in the active table.
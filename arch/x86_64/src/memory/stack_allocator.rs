@@ -1,13 +1,43 @@
-use memory::paging::{self, Page, PageIter, ActivePageTable};
+use collections::Vec;
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use memory::paging::{self, Page, PageIter, ActivePageTable, VirtualAddress};
 use memory::{PAGE_SIZE, FrameAllocator};
 
+/// First address of the area managed by the stack allocator.
+static STACK_AREA_START: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Last address (inclusive) of the area managed by the stack allocator.
+static STACK_AREA_END: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Check if an address is part of the area reserved for stacks.
+///
+/// This doesn't take any lock, so it can be used from exception handlers.
+pub fn in_stack_area(address: VirtualAddress) -> bool {
+    let start = STACK_AREA_START.load(Ordering::Relaxed);
+    let end = STACK_AREA_END.load(Ordering::Relaxed);
+
+    start != end && address >= start && address <= end
+}
+
 pub struct StackAllocator {
     range: PageIter,
+    /// Stacks given back with `free_stack`. They stay mapped, with their guard page, and are
+    /// reused by the next allocations of the same size.
+    free: Vec<Stack>,
 }
 
 impl StackAllocator {
     pub fn new(page_range: PageIter) -> StackAllocator {
-        StackAllocator { range: page_range }
+        // remember the area bounds, this allow us to recognize guard page hits later
+        if let (Some(first), Some(last)) = (page_range.clone().next(), page_range.clone().last()) {
+            STACK_AREA_START.store(first.start_address(), Ordering::SeqCst);
+            STACK_AREA_END.store(last.start_address() + PAGE_SIZE - 1, Ordering::SeqCst);
+        }
+
+        StackAllocator {
+            range: page_range,
+            free: Vec::new()
+        }
     }
 
     pub fn alloc_stack<FA: FrameAllocator>(&mut self,
@@ -19,6 +49,12 @@ impl StackAllocator {
             return None;
         }
 
+        // reuse a freed stack of the same size first
+        let size = size_in_pages * PAGE_SIZE;
+        if let Some(index) = self.free.iter().position(|stack| stack.size() == size) {
+            return Some(self.free.swap_remove(index));
+        }
+
         // close the range, since we only want to change it on success
         let mut range = self.range.clone();
 
@@ -51,6 +87,11 @@ impl StackAllocator {
             _ => None,
         }
     }
+
+    /// Give back a stack allocated by `alloc_stack`, nothing must be running on it anymore.
+    pub fn free_stack(&mut self, stack: Stack) {
+        self.free.push(stack);
+    }
 }
 
 #[derive(Debug)]
@@ -75,4 +116,9 @@ impl Stack {
     pub fn bottom(&self) -> usize {
         self.bottom
     }
+
+    /// Get the size of the stack in bytes.
+    pub fn size(&self) -> usize {
+        self.top - self.bottom
+    }
 }
@@ -0,0 +1,214 @@
+//! # Symmetric Multiprocessing (SMP)
+//!
+//! The Application Processors (APs) are started one at a time, by sending the INIT-SIPI-SIPI
+//! sequence to the Local APIC of each processor listed on the MADT. Without a MADT, the sequence is
+//! broadcast to all the CPUs excluding the Bootstrap Processor (BSP). The APs start in real mode on
+//! the trampoline (`assembly/trampoline.asm`) and end up calling `start::kstart_ap` in long mode.
+//!
+//! ## References
+//! - [OSDev SMP](http://wiki.osdev.org/SMP)
+//! - Intel® 64 and IA-32 Architectures Software Developer’s Manual, Volume 3A, 8.4
+
+use collections::Vec;
+use core::intrinsics::{atomic_load, atomic_store};
+use core::sync::atomic::Ordering;
+use x86_64::instructions::port::outb;
+
+use acpi::ACPI_TABLE;
+use device::local_apic::LOCAL_APIC;
+use interrupts;
+use memory::{self, MemoryController, Frame};
+use memory::paging::Page;
+use memory::paging::entry;
+use start::{self, CPU_COUNT};
+use MAX_CPU_COUNT;
+
+/// Address where the trampoline is copied to, it's identity mapped while the APs are starting.
+/// The SIPI vector is its page number, so it must be page aligned and below 1 MiB.
+const TRAMPOLINE: usize = 0x8000;
+
+/// The trampoline code
+static TRAMPOLINE_DATA: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/trampoline"));
+
+/// Set to 1 when an AP gets the trampoline lock and to 2 when it doesn't need the variables anymore
+const TRAMPOLINE_READY: usize = TRAMPOLINE + 8;
+/// Lock taken by the AP that is using the trampoline
+const TRAMPOLINE_LOCK: usize = TRAMPOLINE + 16;
+/// Set when the variables for the AP holding the lock are filled
+const TRAMPOLINE_GO: usize = TRAMPOLINE + 24;
+/// CPU id of the AP
+const TRAMPOLINE_CPU_ID: usize = TRAMPOLINE + 32;
+/// Physical address of the kernel page table
+const TRAMPOLINE_PAGE_TABLE: usize = TRAMPOLINE + 40;
+/// Top of the AP kernel stack
+const TRAMPOLINE_STACK_END: usize = TRAMPOLINE + 48;
+/// Top of the AP double fault stack
+const TRAMPOLINE_IST_STACK_END: usize = TRAMPOLINE + 56;
+/// Address of the Rust entry point
+const TRAMPOLINE_CODE: usize = TRAMPOLINE + 64;
+
+/// Number of pages for the kernel stack of each AP
+const AP_STACK_PAGES: usize = 16;
+
+/// Time to wait for an AP to reach the trampoline, in microseconds.
+const AP_STARTUP_TIMEOUT: usize = 100_000;
+
+/// ICR delivery mode: INIT
+const ICR_INIT: u64 = 0b101 << 8;
+/// ICR delivery mode: Start-Up
+const ICR_STARTUP: u64 = 0b110 << 8;
+/// ICR level: assert
+const ICR_ASSERT: u64 = 1 << 14;
+/// ICR destination shorthand: all excluding self
+const ICR_ALL_EXCLUDING_SELF: u64 = 0b11 << 18;
+
+/// Wait for approximately the given number of microseconds.
+///
+/// Each write to the POST port takes about one microsecond, which is good enough here, since the
+/// timers are not calibrated at this point.
+fn delay(microseconds: usize) {
+    for _ in 0..microseconds {
+        unsafe { outb(0x80, 0); }
+    }
+}
+
+/// Wait until the trampoline ready variable gets the given value.
+///
+/// ## Returns
+/// `false` if the value wasn't set before the timeout.
+fn wait_ready(value: u64) -> bool {
+    for _ in 0..AP_STARTUP_TIMEOUT {
+        if unsafe { atomic_load(TRAMPOLINE_READY as *const u64) } == value {
+            return true;
+        }
+        delay(1);
+    }
+
+    false
+}
+
+/// Send the INIT-SIPI-SIPI sequence to the destination of the ICR, as recommended by the
+/// specification. The second SIPI is ignored by an AP that already started.
+fn send_startup(destination: u64) {
+    unsafe {
+        let sipi = destination | ICR_ASSERT | ICR_STARTUP | (TRAMPOLINE >> 12) as u64;
+        LOCAL_APIC.set_icr(destination | ICR_ASSERT | ICR_INIT);
+        delay(10_000);
+        LOCAL_APIC.set_icr(sipi);
+        delay(200);
+        LOCAL_APIC.set_icr(sipi);
+    }
+}
+
+/// Allocate the resources of an AP and fill the trampoline variables with them.
+fn prepare_ap(cpu_id: usize, memory_controller: &mut MemoryController) {
+    // map the per-CPU area, the AP sets up its own TCB there
+    memory::init_ap(cpu_id, memory_controller);
+
+    let stack = memory_controller.alloc_stack(AP_STACK_PAGES).expect("could not allocate AP stack");
+    let ist_stack = memory_controller.alloc_stack(interrupts::DOUBLE_FAULT_STACK_PAGES)
+        .expect("could not allocate AP double fault stack");
+    let page_table = unsafe { memory_controller.active_table.address() };
+
+    unsafe {
+        atomic_store(TRAMPOLINE_CPU_ID as *mut u64, cpu_id as u64);
+        atomic_store(TRAMPOLINE_PAGE_TABLE as *mut u64, page_table as u64);
+        atomic_store(TRAMPOLINE_STACK_END as *mut u64, stack.top() as u64);
+        atomic_store(TRAMPOLINE_IST_STACK_END as *mut u64, ist_stack.top() as u64);
+        atomic_store(TRAMPOLINE_CODE as *mut u64, start::kstart_ap as usize as u64);
+    }
+}
+
+/// Hand the trampoline to the next AP that arrives at it, and wait until it's done with it.
+///
+/// ## Returns
+/// `false` if no AP arrived at the trampoline before the timeout.
+fn start_ap(memory_controller: &mut MemoryController) -> bool {
+    if !wait_ready(1) {
+        return false;
+    }
+
+    let cpu_id = CPU_COUNT.load(Ordering::SeqCst);
+    prepare_ap(cpu_id, memory_controller);
+
+    unsafe { atomic_store(TRAMPOLINE_GO as *mut u64, 1); }
+    if !wait_ready(2) {
+        panic!("SMP: CPU {} stopped on the trampoline", cpu_id);
+    }
+
+    CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+
+    // release the trampoline to the next AP
+    unsafe {
+        atomic_store(TRAMPOLINE_READY as *mut u64, 0);
+        atomic_store(TRAMPOLINE_GO as *mut u64, 0);
+        atomic_store(TRAMPOLINE_LOCK as *mut u64, 0);
+    }
+
+    true
+}
+
+/// Start all the APs.
+///
+/// This returns when all the processors listed on the MADT were started, or, without a MADT, when
+/// no more APs arrive at the trampoline. `CPU_COUNT` is updated with each AP that is started.
+pub fn init(memory_controller: &mut MemoryController) {
+    CPU_COUNT.store(1, Ordering::SeqCst);
+
+    // Local APIC ids of the usable APs
+    let bsp_apic_id = unsafe { LOCAL_APIC.id() };
+    let apic_ids = ACPI_TABLE.lock().madt.as_ref().map(|madt| {
+        madt.enabled_local_apics().iter()
+            .map(|local_apic| local_apic.apic_id)
+            .filter(|&apic_id| apic_id as u32 != bsp_apic_id)
+            .collect::<Vec<u8>>()
+    });
+    if apic_ids.as_ref().map_or(false, |apic_ids| apic_ids.is_empty()) {
+        kinfo!("1 CPU");
+        return;
+    }
+
+    // identity map the trampoline, it keeps running from there after enabling paging
+    let trampoline_page = Page::containing_address(TRAMPOLINE);
+    memory_controller.map_to(trampoline_page, Frame::containing_address(TRAMPOLINE),
+                             entry::PRESENT | entry::WRITABLE);
+    memory_controller.flush_all();
+
+    // copy the trampoline code
+    for (index, byte) in TRAMPOLINE_DATA.iter().enumerate() {
+        unsafe { atomic_store((TRAMPOLINE + index) as *mut u8, *byte); }
+    }
+
+    match apic_ids {
+        // start the APs one by one, the ones above MAX_CPU_COUNT are left alone
+        Some(ref apic_ids) => {
+            for &apic_id in apic_ids.iter().take(MAX_CPU_COUNT - 1) {
+                send_startup(unsafe { LOCAL_APIC.icr_destination(apic_id as usize) });
+                if !start_ap(memory_controller) {
+                    kwarn!("SMP: CPU with Local APIC id {} didn't start", apic_id);
+                }
+            }
+        },
+        // the APs take the trampoline lock one by one, until none is left
+        None => {
+            send_startup(ICR_ALL_EXCLUDING_SELF);
+            while CPU_COUNT.load(Ordering::SeqCst) < MAX_CPU_COUNT && start_ap(memory_controller) {}
+        }
+    }
+
+    // The trampoline isn't needed anymore. The APs spin with interrupts disabled until the BSP is
+    // ready, so they can't handle a TLB shootdown; they flush their whole TLB after that instead.
+    {
+        let &mut MemoryController {
+            ref mut active_table,
+            ref mut frame_allocator,
+            .. } = memory_controller;
+        active_table.unmap(trampoline_page, frame_allocator).flush_local();
+    }
+
+    let cpu_count = CPU_COUNT.load(Ordering::SeqCst);
+    match apic_ids {
+        Some(ref apic_ids) if apic_ids.len() + 1 != cpu_count => kwarn!("{} of {} CPUs started", cpu_count, apic_ids.len() + 1),
+        _ => kinfo!("{} CPUs", cpu_count)
+    }
+}
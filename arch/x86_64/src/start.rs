@@ -1,4 +1,4 @@
-use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT, Ordering};
 
 use acpi;
 use device;
@@ -6,6 +6,7 @@ use interrupts;
 use memory;
 use memory::MemoryController;
 use multiboot2;
+use smp;
 use vga_buffer;
 use kernel_messaging;
 use spin::Mutex;
@@ -13,11 +14,23 @@ use spin::Mutex;
 /// This is used to count the number of CPUs on the system
 pub static CPU_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
 
+/// Id of the current CPU, the BSP is always 0
+#[thread_local]
+static CPU_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set when the BSP finished the initialization, the APs wait for it before calling `kmain_ap`
+static BSP_READY: AtomicBool = ATOMIC_BOOL_INIT;
+
 ///  Get the number of CPUs currently active
 pub fn cpu_count() -> usize {
     CPU_COUNT.load(Ordering::Relaxed)
 }
 
+/// Get the id of the current CPU
+pub fn cpu_id() -> usize {
+    CPU_ID.load(Ordering::Relaxed)
+}
+
 /// Enable the NXE bit to allow NO_EXECUTE pages.
 fn enable_nxe_bit() {
     use x86_64::registers::msr::{IA32_EFER, rdmsr, wrmsr};
@@ -39,6 +52,8 @@ fn enable_write_protect_bit() {
 extern {
     /// Kernel main function
     fn kmain(memory_controller: &mut MemoryController) -> !;
+    /// Kernel main function for the APs
+    fn kmain_ap(cpu_id: usize) -> !;
 }
 
 /// Entry point for the Rust code.
@@ -46,14 +61,20 @@ extern {
 pub extern "C" fn rust_main(multiboot_information_address: usize) -> ! {
     // Initialize all the arch components in a different scope than the kernel's main function call.
 
+    // configure the serial ports first, so the whole boot log is mirrored on COM1
+    device::serial::init();
+
     // clear the console screen
     vga_buffer::clear_screen();
 
     // print out a welcome message
-    println!("kernel: botting");
+    kinfo!("booting");
 
     let boot_info = unsafe { multiboot2::load(multiboot_information_address) };
 
+    // look for the framebuffer now, the boot information is still identity mapped
+    let framebuffer = device::framebuffer::find(boot_info);
+
     // enable NXE bit, to allow define none executable pages.
     enable_nxe_bit();
 
@@ -63,21 +84,75 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) -> ! {
     // set up guard page and map the heap pages
     let (mut memory_controller, tcb_offset) = memory::init(0, boot_info);
 
+    // draw the console on the framebuffer, when the bootloader set one
+    if let Some(info) = framebuffer {
+        device::framebuffer::init(&mut memory_controller, info);
+    }
+
     // Initialize IDT and GDT
     interrupts::init(&mut memory_controller, tcb_offset);
 
+    // the kernel TLS is available from here
+    CPU_ID.store(0, Ordering::SeqCst);
+    memory::paging::flush::init();
+
     // Initialize devices
     device::init(&mut memory_controller);
 
-    // Read ACPI tables, starts APs
+    // the BSP can receive IPIs from here
+    interrupts::ipi::init();
+
+    // Read ACPI tables
     acpi::init(&mut memory_controller);
 
     // Initialize all the non-core devices
-    device::init_non_core();
+    device::init_non_core(&mut memory_controller);
 
-    // reset CPU count
-    CPU_COUNT.store(1, Ordering::SeqCst);
+    // Start the APs
+    smp::init(&mut memory_controller);
+
+    // let the APs enter the kernel
+    BSP_READY.store(true, Ordering::SeqCst);
 
     // Call the kernel main function
     unsafe { kmain(&mut memory_controller); }
 }
+
+/// Entry point for the Rust code on the APs, called by the trampoline.
+///
+/// ## Parameters
+/// - `cpu_id`: id given by the BSP to this CPU.
+/// - `ist_stack_end`: top of the double fault stack.
+pub unsafe extern "C" fn kstart_ap(cpu_id: usize, ist_stack_end: usize) -> ! {
+    use x86_64::instructions::tlb;
+
+    // the trampoline already set them, this keeps the BSP and APs in the same state
+    enable_nxe_bit();
+    enable_write_protect_bit();
+
+    // set up the TCB on the per-CPU area mapped by the BSP
+    let tcb_offset = memory::init_tcb(cpu_id);
+
+    // Initialize the GDT and TSS of this CPU, and load the IDT
+    interrupts::init_ap(ist_stack_end, tcb_offset);
+
+    // the kernel TLS is available from here
+    CPU_ID.store(cpu_id, Ordering::SeqCst);
+    memory::paging::flush::init();
+
+    // Initialize the Local APIC and its timer
+    device::init_ap();
+
+    // this CPU can receive IPIs from here
+    interrupts::ipi::init();
+
+    // wait for the BSP to finish the initialization
+    while !BSP_READY.load(Ordering::SeqCst) {
+        interrupts::pause();
+    }
+
+    // the BSP unmapped the trampoline after all the APs started
+    tlb::flush_all();
+
+    kmain_ap(cpu_id);
+}
@@ -1,10 +1,129 @@
+//! # Time keeping
+//!
+//! The monotonic clock is read from a counter with a known frequency, with nanosecond resolution:
+//! the TSC, when it's invariant, or the HPET main counter. Until one of them is calibrated, or when
+//! none can be used, it's advanced by the timer ticks of the BSP.
+
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 use spin::Mutex;
+use x86_64::registers::flags::{self, IF};
+
+use device::hpet;
+use interrupts;
+
+/// Frequency of the timer interrupts, in Hz
+pub const TICK_FREQUENCY: u64 = 100;
+
+/// Nanoseconds per second
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Source of the monotonic clock
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Clock {
+    /// Timer ticks
+    Ticks = 0,
+    /// Time-stamp counter
+    Tsc = 1,
+    /// HPET main counter
+    Hpet = 2
+}
 
 pub static START: Mutex<(u64, u64)> = Mutex::new((0, 0));
 pub static OFFSET: Mutex<(u64, u64)> = Mutex::new((0, 0));
 
+/// Current clock source, as a `Clock`
+static CLOCK: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Frequency of the clock counter, in Hz
+static CLOCK_FREQUENCY: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Value of the clock counter when it became the clock source, at the time stored on `OFFSET`
+static CLOCK_BASE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Run `f` with `OFFSET` locked.
+///
+/// Interrupts are disabled while the lock is held, since the timer interrupt of this CPU may
+/// advance the clock.
+fn with_offset<F, T>(f: F) -> T
+    where F: FnOnce(&mut (u64, u64)) -> T
+{
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { interrupts::disable(); }
+
+    let result = f(&mut OFFSET.lock());
+
+    if interrupts_enabled {
+        unsafe { interrupts::enable(); }
+    }
+
+    result
+}
+
+/// Read the counter of a clock.
+fn counter(clock: Clock) -> u64 {
+    match clock {
+        Clock::Ticks => 0,
+        Clock::Tsc => tsc(),
+        Clock::Hpet => hpet::counter()
+    }
+}
+
+/// Get the current clock source.
+pub fn clock() -> Clock {
+    match CLOCK.load(Ordering::SeqCst) {
+        1 => Clock::Tsc,
+        2 => Clock::Hpet,
+        _ => Clock::Ticks
+    }
+}
+
+/// Read the monotonic clock from a counter, from now on.
+///
+/// ## Parameters
+/// - `clock`: the new clock source.
+/// - `frequency`: frequency of its counter, in Hz.
+pub fn set_clock(clock: Clock, frequency: u64) {
+    with_offset(|offset| {
+        // the new clock continues from the current time
+        *offset = read_monotonic(*offset);
+
+        CLOCK.store(Clock::Ticks as usize, Ordering::SeqCst);
+        CLOCK_FREQUENCY.store(frequency as usize, Ordering::SeqCst);
+        CLOCK_BASE.store(counter(clock) as usize, Ordering::SeqCst);
+        CLOCK.store(clock as usize, Ordering::SeqCst);
+    });
+}
+
+/// Advance the clock by one timer tick, when it isn't read from a counter.
+pub fn tick() {
+    if clock() != Clock::Ticks {
+        return;
+    }
+
+    with_offset(|offset| {
+        let sum = offset.1 + NANOS_PER_SEC / TICK_FREQUENCY;
+        offset.1 = sum % NANOS_PER_SEC;
+        offset.0 += sum / NANOS_PER_SEC;
+    });
+}
+
+/// Add the time elapsed on the clock counter to `offset`.
+fn read_monotonic(offset: (u64, u64)) -> (u64, u64) {
+    let clock = clock();
+    let frequency = CLOCK_FREQUENCY.load(Ordering::SeqCst) as u64;
+    if clock == Clock::Ticks || frequency == 0 {
+        return offset;
+    }
+
+    // the remainder is below the frequency, so the multiplication can't overflow for any
+    // frequency below 18 GHz
+    let cycles = counter(clock).wrapping_sub(CLOCK_BASE.load(Ordering::SeqCst) as u64);
+    let nanos = offset.1 + (cycles % frequency) * NANOS_PER_SEC / frequency;
+    (offset.0 + cycles / frequency + nanos / NANOS_PER_SEC, nanos % NANOS_PER_SEC)
+}
+
+/// Get the time since the boot, as seconds and nanoseconds
 pub fn monotonic() -> (u64, u64) {
-    *OFFSET.lock()
+    let offset = with_offset(|offset| *offset);
+    read_monotonic(offset)
 }
 
 /// Get real time
@@ -12,5 +131,27 @@ pub fn realtime() -> (u64, u64) {
     let offset = monotonic();
     let start = *START.lock();
     let sum = start.1 + offset.1;
-    (start.0 + offset.0 + sum / 1000000000, sum & 1000000000)
+    (start.0 + offset.0 + sum / NANOS_PER_SEC, sum % NANOS_PER_SEC)
+}
+
+/// Set the real time, `START` is the real time when the monotonic clock was zero.
+///
+/// ## Parameters
+/// - `secs`: seconds since the Unix epoch.
+pub fn set_realtime(secs: u64) {
+    let offset = monotonic();
+
+    // borrow a second when the nanoseconds can't be subtracted
+    let start = if offset.1 == 0 {
+        (secs.saturating_sub(offset.0), 0)
+    } else {
+        (secs.saturating_sub(offset.0 + 1), NANOS_PER_SEC - offset.1)
+    };
+
+    *START.lock() = start;
+}
+
+/// Read the CPU time-stamp counter
+pub fn tsc() -> u64 {
+    ::x86_64::instructions::rdtsc()
 }
@@ -1,11 +1,21 @@
 //! # VGA Console Implementation
+//!
+//! Text console on the VGA buffer, with scrollback, a hardware cursor and the ANSI escape
+//! sequences `ansi` understands. When the bootloader gives a linear framebuffer, the same console
+//! is drawn on it instead, with a grid as large as the screen allows.
 
 use core::ptr::Unique;
-use core::fmt;
+use core::{cmp, fmt};
 use spin::Mutex;
 use volatile::Volatile;
 
-use device::serial::COM1;
+use x86_64::instructions::port::outb;
+use x86_64::registers::flags::{self, IF};
+
+use ansi::{self, Action, Erase, Parser};
+use device::framebuffer::Framebuffer;
+use device::serial;
+use interrupts;
 
 /// Print with new line to console
 #[macro_export]
@@ -26,6 +36,26 @@ macro_rules! print {
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 
+/// Largest console on a framebuffer, 1280x1024 with 8x16 cells
+const MAX_WIDTH: usize = 160;
+const MAX_HEIGHT: usize = 64;
+
+/// Number of lines kept after they scroll off the top of the screen
+const SCROLLBACK_LINES: usize = 200;
+/// Number of lines kept, the largest screen included
+const TOTAL_LINES: usize = SCROLLBACK_LINES + MAX_HEIGHT;
+
+/// Distance between tab stops
+const TAB_WIDTH: usize = 8;
+
+/// CRTC index register
+const CRTC_INDEX: u16 = 0x3d4;
+/// CRTC data register
+const CRTC_DATA: u16 = 0x3d5;
+/// CRTC cursor location registers, high and low bytes
+const CRTC_CURSOR_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOW: u8 = 0x0f;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -50,99 +80,440 @@ pub enum Color {
 
 pub const DEFAULT_COLOR_CODE: ColorCode = ColorCode::new(Color::Cyan, Color::White);
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ColorCode(u8);
 
 impl ColorCode {
     pub const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    /// Get the foreground color, from 0 to 15
+    pub fn foreground(&self) -> u8 {
+        self.0 & 0x0f
+    }
+
+    /// Get the background color, from 0 to 15
+    pub fn background(&self) -> u8 {
+        self.0 >> 4
+    }
+
+    /// Get the same color code with another foreground color, from 0 to 15
+    pub fn with_foreground(&self, color: u8) -> ColorCode {
+        ColorCode(self.0 & 0xf0 | color & 0x0f)
+    }
+
+    /// Get the same color code with another background color, from 0 to 15
+    pub fn with_background(&self, color: u8) -> ColorCode {
+        ColorCode(self.0 & 0x0f | (color & 0x0f) << 4)
+    }
 }
 
+/// A text console on the VGA buffer, or on a framebuffer.
+///
+/// The screen and the scrollback are kept on `lines`, a ring of lines where the screen is the last
+/// `height` ones, and copied to the display when they change. The view can be scrolled back
+/// through the history, and any new output scrolls it back to the screen.
 pub struct Writer {
-    row_positon: usize,
+    /// Number of columns of the screen
+    width: usize,
+    /// Number of rows of the screen
+    height: usize,
+    row_position: usize,
     column_position: usize,
     color_code: ColorCode,
+    /// Position saved by the `SaveCursor` escape sequence
+    saved_position: (usize, usize),
+    /// Escape sequences parser
+    parser: Parser,
+    /// Scrollback and screen lines
+    lines: [[ScreenChar; MAX_WIDTH]; TOTAL_LINES],
+    /// Index on `lines` of the first row of the screen
+    top: usize,
+    /// Number of lines above the screen that can be viewed
+    history: usize,
+    /// Number of lines the view is scrolled back
+    view: usize,
     buffer: Unique<Buffer>,
+    /// Framebuffer the console is drawn on, instead of `buffer`
+    framebuffer: Option<Framebuffer>,
+    /// Cell drawn as the cursor on the framebuffer
+    drawn_cursor: Option<(usize, usize)>,
+    /// The console is drawn on the display, it isn't while a program uses the framebuffer
+    visible: bool,
 }
 
+/// Blank character of the default color
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: DEFAULT_COLOR_CODE,
+};
+
 pub static WRITER: Mutex<Writer> = Mutex::new(Writer {
-    row_positon: 0,
+    width: BUFFER_WIDTH,
+    height: BUFFER_HEIGHT,
+    row_position: 0,
     column_position: 0,
     color_code: DEFAULT_COLOR_CODE,
+    saved_position: (0, 0),
+    parser: Parser::new(),
+    lines: [[BLANK; MAX_WIDTH]; TOTAL_LINES],
+    top: SCROLLBACK_LINES,
+    history: 0,
+    view: 0,
     buffer: unsafe { Unique::new(0xb8000 as *mut _) },
+    framebuffer: None,
+    drawn_cursor: None,
+    visible: true,
 });
 
 /// Implement the Writer Struct
 impl Writer {
-    /// Write a byte to the console.
+    /// Write a byte to the console, interpreting the escape sequences.
     ///
     /// # Arguments
     ///
     /// * `byte` - Byte to be writen
     pub fn write_byte(&mut self, byte: u8) {
-        match byte {
-            b'\n' => self.new_line(),
-            b'\t' => for _ in 0..2 { self.write_byte(b' ') },
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
+        if let Some(action) = self.parser.advance(byte) {
+            self.apply(action);
+        }
+    }
+
+    /// Write bytes to the console, and move the hardware cursor after them.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        // new output brings the view back to the screen
+        if self.view != 0 {
+            self.view = 0;
+            self.redraw();
+        }
+
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+
+        self.update_cursor();
+    }
+
+    /// Do what an escape sequence, or a character, asks for.
+    fn apply(&mut self, action: Action) {
+        match action {
+            Action::Print(byte) => {
+                if self.column_position >= self.width {
                     self.new_line();
                 }
-                let row = self.row_positon;
+                let row = self.row_position;
                 let col = self.column_position;
 
                 let color_code = self.color_code;
-
-                self.buffer().chars[row][col].write(ScreenChar {
+                self.put(row, col, ScreenChar {
                     ascii_character: byte,
                     color_code: color_code,
                 });
                 self.column_position += 1;
+            },
+            Action::NewLine => self.new_line(),
+            Action::CarriageReturn => self.column_position = 0,
+            Action::Backspace => {
+                self.column_position = cmp::min(self.column_position, self.width - 1).saturating_sub(1);
+            },
+            Action::Tab => {
+                self.column_position = cmp::min((self.column_position / TAB_WIDTH + 1) * TAB_WIDTH, self.width);
+            },
+            Action::CursorUp(amount) => self.row_position = self.row_position.saturating_sub(amount),
+            Action::CursorDown(amount) => {
+                self.row_position = cmp::min(self.row_position.saturating_add(amount), self.height - 1);
+            },
+            Action::CursorForward(amount) => {
+                self.column_position = cmp::min(self.column_position.saturating_add(amount), self.width - 1);
+            },
+            Action::CursorBack(amount) => {
+                self.column_position = cmp::min(self.column_position, self.width - 1).saturating_sub(amount);
+            },
+            Action::CursorPosition { row, column } => {
+                self.row_position = cmp::min(row, self.height - 1);
+                self.column_position = cmp::min(column, self.width - 1);
+            },
+            Action::EraseDisplay(erase) => {
+                let row = self.row_position;
+                let (first, last) = match erase {
+                    Erase::ToEnd => (row + 1, self.height),
+                    Erase::ToStart => (0, row),
+                    Erase::All => (0, self.height)
+                };
+                for row in first..last {
+                    self.clear_row(row);
+                }
+                if erase != Erase::All {
+                    self.apply(Action::EraseLine(erase));
+                }
+            },
+            Action::EraseLine(erase) => {
+                let row = self.row_position;
+                let col = cmp::min(self.column_position, self.width - 1);
+                let (first, last) = match erase {
+                    Erase::ToEnd => (col, self.width),
+                    Erase::ToStart => (0, col + 1),
+                    Erase::All => (0, self.width)
+                };
+                self.clear_columns(row, first, last);
+            },
+            Action::SelectGraphic { params, count } => {
+                for &param in params[..count].iter() {
+                    self.select_graphic(param);
+                }
+            },
+            Action::SaveCursor => self.saved_position = (self.row_position, self.column_position),
+            Action::RestoreCursor => {
+                let (row, col) = self.saved_position;
+                self.row_position = row;
+                self.column_position = col;
             }
         }
     }
 
+    /// Change the colors with a Select Graphic Rendition parameter.
+    fn select_graphic(&mut self, param: u16) {
+        let color_code = self.color_code;
+        self.color_code = match param {
+            0 => DEFAULT_COLOR_CODE,
+            // bold is shown as a bright foreground
+            1 => color_code.with_foreground(color_code.foreground() | 8),
+            22 => color_code.with_foreground(color_code.foreground() & 7),
+            30...37 => color_code.with_foreground(ansi::vga_color(param - 30, false)),
+            39 => color_code.with_foreground(DEFAULT_COLOR_CODE.foreground()),
+            40...47 => color_code.with_background(ansi::vga_color(param - 40, false)),
+            49 => color_code.with_background(DEFAULT_COLOR_CODE.background()),
+            90...97 => color_code.with_foreground(ansi::vga_color(param - 90, true)),
+            100...107 => color_code.with_background(ansi::vga_color(param - 100, true)),
+            _ => color_code
+        };
+    }
+
     /// Gets a mutable reference to the console Buffer.
     fn buffer(&mut self) -> &mut Buffer {
         unsafe { self.buffer.get_mut() }
     }
 
+    /// Get the index on `lines` of a row of the screen.
+    fn line_index(&self, row: usize) -> usize {
+        (self.top + row) % TOTAL_LINES
+    }
+
+    /// Put a character on the screen.
+    fn put(&mut self, row: usize, col: usize, character: ScreenChar) {
+        let index = self.line_index(row);
+        self.lines[index][col] = character;
+
+        if self.view == 0 {
+            self.draw(row, col, character);
+        }
+    }
+
+    /// Show a character on the display.
+    fn draw(&mut self, row: usize, col: usize, character: ScreenChar) {
+        if !self.visible {
+            return;
+        }
+
+        if let Some(ref framebuffer) = self.framebuffer {
+            let color_code = character.color_code;
+            framebuffer.draw_char(row, col, character.ascii_character, color_code.foreground(),
+                                  color_code.background());
+            return;
+        }
+
+        self.buffer().chars[row][col].write(character);
+    }
+
+    /// Copy the lines on the view to the display.
+    fn redraw(&mut self) {
+        for row in 0..self.height {
+            let index = (self.top + TOTAL_LINES - self.view + row) % TOTAL_LINES;
+            for col in 0..self.width {
+                let character = self.lines[index][col];
+                self.draw(row, col, character);
+            }
+        }
+
+        self.drawn_cursor = None;
+    }
+
+    /// Move the cursor to the cursor position, or hide it when the view is scrolled back.
+    ///
+    /// On the VGA buffer this is the hardware cursor, on a framebuffer the cell under the cursor is
+    /// drawn with its colors swapped.
+    fn update_cursor(&mut self) {
+        if self.framebuffer.is_some() {
+            self.hide_cursor();
+
+            if self.view == 0 {
+                let row = self.row_position;
+                let col = cmp::min(self.column_position, self.width - 1);
+                let character = self.lines[self.line_index(row)][col];
+                let color_code = character.color_code;
+                self.draw(row, col, ScreenChar {
+                    ascii_character: character.ascii_character,
+                    color_code: color_code.with_foreground(color_code.background())
+                        .with_background(color_code.foreground()),
+                });
+                self.drawn_cursor = Some((row, col));
+            }
+            return;
+        }
+
+        let position = if self.view == 0 {
+            self.row_position * BUFFER_WIDTH + cmp::min(self.column_position, BUFFER_WIDTH - 1)
+        } else {
+            // out of the screen
+            BUFFER_WIDTH * BUFFER_HEIGHT
+        };
+
+        unsafe {
+            outb(CRTC_INDEX, CRTC_CURSOR_LOW);
+            outb(CRTC_DATA, position as u8);
+            outb(CRTC_INDEX, CRTC_CURSOR_HIGH);
+            outb(CRTC_DATA, (position >> 8) as u8);
+        }
+    }
+
+    /// Draw the cell under the framebuffer cursor back with its own colors.
+    fn hide_cursor(&mut self) {
+        if let Some((row, col)) = self.drawn_cursor.take() {
+            let character = self.lines[self.line_index(row)][col];
+            self.draw(row, col, character);
+        }
+    }
+
     /// Adds a new file
     fn new_line(&mut self) {
-        // increment the current row
-        self.row_positon += 1;
-
         // reset the column positon
         self.column_position = 0;
 
-        // scroll the view if there is no more free rows
-        if self.row_positon == BUFFER_HEIGHT {
-            for row in 1..BUFFER_HEIGHT {
-                for col in 0..BUFFER_WIDTH {
-                    let buffer = self.buffer();
-                    let character = buffer.chars[row][col].read();
-                    buffer.chars[row - 1][col].write(character);
-                }
-            }
+        // increment the current row, or scroll the view if there is no more free rows
+        if self.row_position + 1 < self.height {
+            self.row_position += 1;
+            return;
+        }
 
-            // clear the last row
-            self.clear_row(BUFFER_HEIGHT - 1);
+        // the first row goes to the scrollback
+        self.top = (self.top + 1) % TOTAL_LINES;
+        self.history = cmp::min(self.history + 1, SCROLLBACK_LINES);
 
-            // positon the cursor on the last row
-            self.row_positon -= 1;
+        // clear the last row, it still holds the oldest line of the scrollback
+        let index = self.line_index(self.height - 1);
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        self.lines[index] = [blank; MAX_WIDTH];
+
+        if self.view != 0 {
+            return;
+        }
+
+        // moving the pixels is much cheaper than drawing every glyph again
+        let framebuffer = self.framebuffer;
+        match framebuffer {
+            Some(ref framebuffer) if self.visible => {
+                self.hide_cursor();
+                framebuffer.scroll(self.height);
+
+                let row = self.height - 1;
+                for col in 0..self.width {
+                    self.draw(row, col, blank);
+                }
+            },
+            _ => self.redraw()
         }
     }
 
-    /// Clear a full row
-    fn clear_row(&mut self, row: usize) {
+    /// Clear the columns `first..last` of a row
+    fn clear_columns(&mut self, row: usize, first: usize, last: usize) {
         let blank = ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer().chars[row][col].write(blank);
+        for col in first..last {
+            self.put(row, col, blank);
+        }
+    }
+
+    /// Clear a full row
+    fn clear_row(&mut self, row: usize) {
+        self.clear_columns(row, 0, self.width);
+    }
+
+    /// Clear the screen and move the cursor to its start, the scrollback is kept.
+    pub fn clear(&mut self) {
+        for row in 0..self.height {
+            self.clear_row(row);
+        }
+
+        self.row_position = 0;
+        self.column_position = 0;
+        self.update_cursor();
+    }
+
+    /// Scroll the view back through the scrollback, or forward when `lines` is negative.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let view = if lines < 0 {
+            self.view.saturating_sub(lines.wrapping_neg() as usize)
+        } else {
+            cmp::min(self.view.saturating_add(lines as usize), self.history)
+        };
+
+        if view != self.view {
+            self.view = view;
+            self.redraw();
+            self.update_cursor();
+        }
+    }
+
+    /// Move the console to a framebuffer, with as many cells as fit on it.
+    pub fn use_framebuffer(&mut self, framebuffer: Framebuffer) {
+        let width = cmp::min(framebuffer.columns(), MAX_WIDTH);
+        let height = cmp::min(framebuffer.rows(), MAX_HEIGHT);
+
+        // keep the cursor on the screen, the rows above it go to the scrollback
+        if self.row_position >= height {
+            let shift = self.row_position + 1 - height;
+            self.top = (self.top + shift) % TOTAL_LINES;
+            self.history = cmp::min(self.history + shift, SCROLLBACK_LINES);
+            self.row_position = height - 1;
+        }
+
+        // the new rows may still hold lines that already left the scrollback
+        for row in self.height..height {
+            let index = self.line_index(row);
+            self.lines[index] = [BLANK; MAX_WIDTH];
         }
+
+        self.width = width;
+        self.height = height;
+        self.column_position = cmp::min(self.column_position, width);
+        self.framebuffer = Some(framebuffer);
+        self.view = 0;
+        self.redraw();
+        self.update_cursor();
     }
+
+    /// Show or hide the console, a hidden console keeps its text without drawing it so a program
+    /// can use the framebuffer. It's drawn again when shown.
+    pub fn set_visible(&mut self, visible: bool) {
+        if visible == self.visible {
+            return;
+        }
+
+        self.visible = visible;
+        if visible {
+            self.redraw();
+            self.update_cursor();
+        } else {
+            self.drawn_cursor = None;
+        }
+    }
+
     /// Change the colors
     pub fn set_colors(&mut self, foreground: Color, background: Color) {
         self.color_code = ColorCode::new(foreground, background)
@@ -165,33 +536,47 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// Run `f` on the console, with interrupts disabled so an interrupt handler that prints can't
+/// deadlock on its lock.
+pub fn with_writer<F, T>(f: F) -> T where F: FnOnce(&mut Writer) -> T {
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { interrupts::disable(); }
+
+    let result = f(&mut WRITER.lock());
+
+    if interrupts_enabled {
+        unsafe { interrupts::enable(); }
+    }
+
+    result
+}
+
+/// Print to the screen, and to COM1 once it's configured.
+///
+/// Interrupts are disabled while the locks are held, so an interrupt handler that prints can't
+/// deadlock on them.
 pub fn print(args: fmt::Arguments) {
     use core::fmt::Write;
+
+    let interrupts_enabled = flags::flags().contains(IF);
+    unsafe { interrupts::disable(); }
+
     WRITER.lock().write_fmt(args).unwrap();
-}
+    serial::print(args);
 
-/// Clear screen-
-pub fn clear_screen() {
-    // iterate all rows and clear them all
-    for i in 0..BUFFER_HEIGHT {
-        WRITER.lock().clear_row(i);
+    if interrupts_enabled {
+        unsafe { interrupts::enable(); }
     }
+}
 
-    // reset the row position
-    WRITER.lock().row_positon = 0;
+/// Clear screen
+pub fn clear_screen() {
+    with_writer(|writer| writer.clear());
 }
 
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
-        for byte in s.bytes() {
-            self.write_byte(byte)
-        }
-
-        // If the console is enable, also print out to the console
-        if COM1.lock().is_enable() {
-            COM1.lock().write_str(s);
-        }
-
+        self.write_bytes(s.as_bytes());
         Ok(())
     }
 }
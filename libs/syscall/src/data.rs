@@ -67,3 +67,96 @@ impl DerefMut for Stat {
         }
     }
 }
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(packed)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i32
+}
+
+impl Deref for TimeSpec {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(self as *const TimeSpec as *const u8, mem::size_of::<TimeSpec>()) as &[u8]
+        }
+    }
+}
+
+impl DerefMut for TimeSpec {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self as *mut TimeSpec as *mut u8, mem::size_of::<TimeSpec>()) as &mut [u8]
+        }
+    }
+}
+
+/// A key pressed or released, read from the `keyboard:` scheme
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(packed)]
+pub struct KeyEvent {
+    /// Scancode set 1 make code, plus `0x80` for the keys prefixed by `0xe0`
+    pub scancode: u8,
+    /// Unicode character of the key with the modifiers, zero when it has none
+    pub character: u32,
+    /// One when the key was pressed, zero when it was released
+    pub pressed: u8,
+    /// Combination of the `KEY_MOD_*` flags
+    pub modifiers: u8
+}
+
+impl Deref for KeyEvent {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(self as *const KeyEvent as *const u8, mem::size_of::<KeyEvent>()) as &[u8]
+        }
+    }
+}
+
+impl DerefMut for KeyEvent {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self as *mut KeyEvent as *mut u8, mem::size_of::<KeyEvent>()) as &mut [u8]
+        }
+    }
+}
+
+/// Mode of the framebuffer, read from the `fb:` scheme
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(packed)]
+pub struct FramebufferInfo {
+    /// Width, in pixels
+    pub width: u32,
+    /// Height, in pixels
+    pub height: u32,
+    /// Number of bytes between the start of two lines
+    pub pitch: u32,
+    /// Bits per pixel, 24 or 32
+    pub bpp: u8,
+    /// Position of the least significant bit and number of bits of each color component
+    pub red_position: u8,
+    pub red_size: u8,
+    pub green_position: u8,
+    pub green_size: u8,
+    pub blue_position: u8,
+    pub blue_size: u8
+}
+
+impl Deref for FramebufferInfo {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(self as *const FramebufferInfo as *const u8, mem::size_of::<FramebufferInfo>()) as &[u8]
+        }
+    }
+}
+
+impl DerefMut for FramebufferInfo {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self as *mut FramebufferInfo as *mut u8, mem::size_of::<FramebufferInfo>()) as &mut [u8]
+        }
+    }
+}
@@ -59,52 +59,70 @@ impl fmt::Display for Error {
     }
 }
 
+/// Operation not permitted
+pub const EPERM: i32 = 1;
 /// No such file or directory
 pub const ENOENT: i32 = 2;
 /// No such process
 pub const ESRCH: i32 = 3;
+/// Argument list too long
+pub const E2BIG: i32 = 7;
 /// Exec format error
 pub const ENOEXEC: i32 = 8;
 /// Bad file number
 pub const EBADF: i32 = 9;
+/// Try again
+pub const EAGAIN: i32 = 11;
+/// Out of memory
+pub const ENOMEM: i32 = 12;
+/// Permission denied
+pub const EACCES: i32 = 13;
 /// Bad address
 pub const EFAULT: i32 = 14;
+/// Device or resource busy
+pub const EBUSY: i32 = 16;
 /// File exists
 pub const EEXIST: i32 = 17;
 /// No such device
 pub const ENODEV: i32 = 19;
 /// Not a directory
 pub const ENOTDIR: i32 = 20;
+/// Invalid argument
+pub const EINVAL: i32 = 22;
 /// Too many open files
 pub const EMFILE: i32 = 24;
+/// Read-only file system
+pub const EROFS: i32 = 30;
 /// Function not implemented
 pub const ENOSYS: i32 = 38;
+/// Too many symbolic links encountered
+pub const ELOOP: i32 = 40;
 
 /// A string representation of each available state.
-pub static STR_STATE: [&'static str; 39] = [
+pub static STR_STATE: [&'static str; 41] = [
     "Success",
-    "",
+    "Operation not permitted",
     "No such file or directory",
     "No such process",
     "",
     "",
     "",
-    "",
+    "Argument list too long",
     "Exec format error",
     "Bad file number",
     "",
-    "",
-    "",
-    "",
+    "Try again",
+    "Out of memory",
+    "Permission denied",
     "Bad address",
     "",
-    "",
+    "Device or resource busy",
     "File exists",
     "",
     "No such device",
     "Not a directory",
     "",
-    "",
+    "Invalid argument",
     "",
     "Too many open files",
     "",
@@ -112,6 +130,7 @@ pub static STR_STATE: [&'static str; 39] = [
     "",
     "",
     "",
+    "Read-only file system",
     "",
     "",
     "",
@@ -119,6 +138,7 @@ pub static STR_STATE: [&'static str; 39] = [
     "",
     "",
     "",
+    "Function not implemented",
     "",
-    "Function not implemented"
+    "Too many symbolic links encountered"
 ];
@@ -3,5 +3,37 @@ pub const MODE_TYPE: u16 = 0xF000;
 pub const MODE_DIR: u16 = 0x4000;
 pub const MODE_FILE: u16 = 0x8000;
 
+// Mode permissions
+pub const MODE_PERM: u16 = 0x0FFF;
+pub const MODE_SETUID: u16 = 0o4000;
+pub const MODE_SETGID: u16 = 0o2000;
+pub const MODE_READ: u16 = 0o4;
+pub const MODE_WRITE: u16 = 0o2;
+pub const MODE_EXEC: u16 = 0o1;
+
+pub const CLOCK_REALTIME: usize = 1;
+pub const CLOCK_MONOTONIC: usize = 4;
+
+// Modifiers of a key event
+pub const KEY_MOD_SHIFT: u8 = 1;
+pub const KEY_MOD_CTRL: u8 = 1 << 1;
+pub const KEY_MOD_ALT: u8 = 1 << 2;
+pub const KEY_MOD_CAPS_LOCK: u8 = 1 << 3;
+pub const KEY_MOD_NUM_LOCK: u8 = 1 << 4;
+
 pub const O_RDONLY: usize    = 0x0001_0000;
+pub const O_WRONLY: usize    = 0x0002_0000;
+pub const O_RDWR: usize      = 0x0003_0000;
+pub const O_ACCMODE: usize   = O_RDONLY | O_WRONLY | O_RDWR;
+pub const O_NONBLOCK: usize  = 0x0004_0000;
+pub const O_CLOEXEC: usize   = 0x0100_0000;
 pub const O_DIRECTORY: usize = 0x1000_0000;
+
+// Auxiliary vector entry types (System V ABI)
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_ENTRY: usize = 9;
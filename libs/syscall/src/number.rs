@@ -2,6 +2,7 @@ pub const SYS_CLASS_FILE: usize = 0x2000_0000;
 pub const SYS_CLASS_PATH: usize=0x1000_0000;
 
 // Types of arguments
+pub const SYS_ARG_SLICE: usize = 0x0100_0000;
 pub const SYS_ARG_MSLICE: usize = 0x0200_0000;
 
 // Return types
@@ -9,5 +10,20 @@ pub const SYS_RET_FILE: usize = 0x0010_0000;
 
 pub const SYS_OPEN: usize =     SYS_CLASS_PATH | SYS_RET_FILE | 5;
 
+pub const SYS_CLOSE: usize  = SYS_CLASS_FILE | 6;
 pub const SYS_READ: usize   = SYS_CLASS_FILE | SYS_ARG_MSLICE | 3;
+pub const SYS_WRITE: usize  = SYS_CLASS_FILE | SYS_ARG_SLICE | 4;
 pub const SYS_FSTAT: usize  = SYS_CLASS_FILE | SYS_ARG_MSLICE | 28;
+pub const SYS_FMAP: usize   = SYS_CLASS_FILE | 90;
+
+pub const SYS_CLOCK_SETTIME: usize = 264;
+pub const SYS_CLOCK_GETTIME: usize = 265;
+pub const SYS_NANOSLEEP: usize      = 162;
+
+pub const SYS_GETUID: usize   = 24;
+pub const SYS_GETGID: usize   = 47;
+pub const SYS_GETEUID: usize  = 49;
+pub const SYS_GETEGID: usize  = 50;
+pub const SYS_SETREUID: usize = 70;
+pub const SYS_SETUID: usize   = 213;
+pub const SYS_SETGID: usize   = 214;
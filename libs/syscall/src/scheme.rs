@@ -9,8 +9,11 @@ pub trait Scheme {
         packet.a = Error::mux(match packet.a {
             SYS_OPEN => self.open(unsafe { slice::from_raw_parts(packet.b as *const u8, packet.c) }, packet.d, packet.uid, packet.gid),
 
+            SYS_CLOSE => self.close(packet.b),
             SYS_READ => self.read(packet.b, unsafe { slice::from_raw_parts_mut(packet.c as *mut u8, packet.d) }),
+            SYS_WRITE => self.write(packet.b, unsafe { slice::from_raw_parts(packet.c as *const u8, packet.d) }),
             SYS_FSTAT => if packet.d >= mem::size_of::<Stat>() { self.fstat(packet.b, unsafe { &mut *(packet.c as *mut Stat) } ) } else { Err(Error::new(EFAULT)) },
+            SYS_FMAP => self.fmap(packet.b, packet.c, packet.d),
            _ => Err(Error::new(ENOSYS))
         });
     }
@@ -36,4 +39,17 @@ pub trait Scheme {
     fn read(&self, id: usize, buffer: &mut [u8]) -> Result<usize> {
         Err(Error::new(EBADF))
     }
+
+    /// This function write to a file descriptor.
+    #[allow(unused_variables)]
+    fn write(&self, id: usize, buffer: &[u8]) -> Result<usize> {
+        Err(Error::new(EBADF))
+    }
+
+    /// This function maps `size` bytes of a file descriptor, from `offset`, on the caller address
+    /// space, and returns the address of the mapping.
+    #[allow(unused_variables)]
+    fn fmap(&self, id: usize, offset: usize, size: usize) -> Result<usize> {
+        Err(Error::new(EBADF))
+    }
 }
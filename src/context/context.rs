@@ -1,5 +1,6 @@
 //! This file contains the implementation of the context concept.
 
+use ::core::mem;
 use ::core::sync::atomic::AtomicUsize;
 use alloc::boxed::Box;
 use alloc::arc::Arc;
@@ -8,8 +9,8 @@ use super::File;
 use scheme::{SchemeNamespace, FileHandle};
 use spin::Mutex;
 
-use arch::memory::MemoryController;
-use super::memory::{Memory, SharedMemory};
+use arch::memory::{MemoryController, Stack};
+use super::memory::{self, Grant, Memory, SharedMemory};
 
 /// Unique identifier for a context
 int_like!(ContextId, AtomicContextId, usize, AtomicUsize);
@@ -39,10 +40,18 @@ pub struct Context {
     pub euid: u32,
     /// The effective group id
     pub egid: u32,
+    /// The saved user id
+    pub suid: u32,
+    /// The saved group id
+    pub sgid: u32,
     /// The effective namespace id
     pub ens: SchemeNamespace,
     /// This status is used to store the current structure state.
     pub status: Status,
+    /// IRQ that the context is blocked on, and its count when it blocked.
+    pub wait_irq: Option<(u8, usize)>,
+    /// Monotonic time, as seconds and nanoseconds, when a blocked context is made runnable.
+    pub wake: Option<(u64, u64)>,
     /// Is just a fast way to check if the context is currently running.
     pub running: bool,
     /// CPU ID, if locked
@@ -51,14 +60,18 @@ pub struct Context {
     pub arch: ::arch::context::Context,
     /// Used to hold the Box to store the FX registers
     pub kfx: Option<Box<[u8]>>,
-    /// Stores the kernel stack.
-    pub kstack: Option<Box<[u8]>>,
+    /// Stores the kernel stack. It's followed by a guard page, so overflows are caught.
+    pub kstack: Option<Stack>,
     /// Executable image
     pub image: Vec<SharedMemory>,
     /// User heap.
     pub heap: Option<SharedMemory>,
     /// User stack.
     pub stack: Option<Memory>,
+    /// User thread local storage (the TLS block followed by the TCB).
+    pub tls: Option<Memory>,
+    /// Physical memory mapped by schemes
+    pub grants: Arc<Mutex<Vec<Grant>>>,
     /// A string identifier for the current context.
     pub name: Arc<Mutex<Vec<u8>>>,
     /// The current working directory
@@ -81,8 +94,12 @@ impl Context {
             rns: SchemeNamespace::from(0),
             euid: 0,
             egid: 0,
+            suid: 0,
+            sgid: 0,
             ens: SchemeNamespace::from(0),
             status: Status::Blocked,
+            wait_irq: None,
+            wake: None,
             running: false,
             cpu_id: None,
             arch: ::arch::context::Context::new(),
@@ -91,6 +108,8 @@ impl Context {
             image: Vec::new(),
             heap: None,
             stack: None,
+            tls: None,
+            grants: Arc::new(Mutex::new(Vec::new())),
             name: Arc::new(Mutex::new(Vec::new())),
             cwd: Arc::new(Mutex::new(Vec::new())),
             files: Arc::new(Mutex::new(Vec::new()))
@@ -203,4 +222,34 @@ impl Context {
             None
         }
     }
+
+    /// Remove a file, releasing its slot.
+    ///
+    /// ## Parameters
+    /// - `fd`: File descriptor of the file to remove.
+    ///
+    /// ## Returns
+    /// The removed file structure if found. Otherwise a `None`.
+    pub fn remove_file(&self, fd: FileHandle) -> Option<File> {
+        let mut files = self.files.lock();
+        if fd.into() < files.len() {
+            files[fd.into()].take()
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Context {
+    /// Give the kernel stack back to the stack allocator and unmap the grants, once the context is
+    /// reaped.
+    fn drop(&mut self) {
+        if let Some(stack) = self.kstack.take() {
+            if let Some(ref mut memory_controller) = *::MEMORY_CONTROLLER.lock() {
+                memory_controller.free_stack(stack);
+            }
+        }
+
+        memory::unmap_grants(mem::replace(&mut self.grants, Arc::new(Mutex::new(Vec::new()))));
+    }
 }
@@ -1,6 +1,7 @@
 //! File structure
 
-use scheme::SchemeId;
+use scheme::{self, SchemeId};
+use syscall::error::{Error, Result, EBADF};
 
 /// A file
 #[derive(Copy, Clone, Debug)]
@@ -9,6 +10,21 @@ pub struct File {
     pub scheme: SchemeId,
     /// The number the scheme uses to refer to this file
     pub number: usize,
+    /// The flags used to open the file
+    pub flags: usize,
     /// If events are on, this is the event ID
     pub event: Option<usize>
 }
+
+impl File {
+    /// Close the file on its scheme.
+    pub fn close(self) -> Result<usize> {
+        let scheme = {
+            let schemes = scheme::schemes();
+            let scheme = schemes.get(self.scheme).ok_or(Error::new(EBADF))?;
+            scheme.clone()
+        };
+
+        scheme.close(self.number)
+    }
+}
@@ -78,20 +78,25 @@ impl ContextList {
                 *b = 0;
             }
 
-            // allocate a vector of 32 KB
-            let mut stack = vec![0; 32_768].into_boxed_slice();
+            // allocate the kernel stack, with a guard page below it
+            let stack = {
+                let mut memory_controller = ::MEMORY_CONTROLLER.lock();
+                memory_controller.as_mut()
+                    .ok_or("Memory controller required")?
+                    .alloc_stack(super::CONTEXT_KERNEL_STACK_PAGES)
+                    .ok_or("No space left for the kernel stack")?
+            };
 
             // Put the function address on the first stack entry
-            let offset = stack.len() - mem::size_of::<usize>();
+            let func_ptr = stack.top() - mem::size_of::<usize>();
             unsafe {
-                let func_ptr = stack.as_mut_ptr().offset(offset as isize);
                 *(func_ptr as *mut usize) = func as usize;
             }
 
             // set the required field of the context structure
             context.arch.set_page_table(unsafe { ::arch::memory::paging::ActivePageTable::new().address() });
             context.arch.set_fx(fx.as_ptr() as usize);
-            context.arch.set_stack(stack.as_ptr() as usize + offset);
+            context.arch.set_stack(func_ptr);
             context.kstack = Some(stack);
             context.kfx = Some(fx);
         }
@@ -101,6 +106,9 @@ impl ContextList {
 
     /// Remove a context from the list.
     ///
+    /// The context must not be running anymore: its kernel stack is freed, to be reused by another
+    /// context, when the last reference to it is dropped.
+    ///
     /// ## Parameters
     /// - `id`: Id from the context to be removed.
     ///
@@ -3,9 +3,12 @@
 //! Some parts of this code are based on the Redox OS.
 
 use alloc::arc::{Arc, Weak};
+use collections::Vec;
+use core::intrinsics;
 use spin::Mutex;
 
-use arch::memory::paging::{ActivePageTable, Page, PageIter, VirtualAddress};
+use arch::memory::Frame;
+use arch::memory::paging::{ActivePageTable, FlushRange, Page, PageIter, PhysicalAddress, VirtualAddress};
 use arch::memory::paging::entry::EntryFlags;
 use arch::start;
 
@@ -78,6 +81,8 @@ impl Memory {
     }
 
     /// Map a new space on the virtual memory for this memory zone.
+    ///
+    /// When `clean` is set the zone is filled with zeros, so it must be mapped as writable.
     fn map(&mut self, clean: bool) {
         // create a new active page table
         let mut active_table = unsafe { ActivePageTable::new() };
@@ -90,6 +95,10 @@ impl Memory {
         } else {
             panic!("Memory controller required");
         }
+
+        if clean {
+            unsafe { intrinsics::write_bytes(self.start as *mut u8, 0, self.size); }
+        }
     }
 
     /// Remap a memory area to another region
@@ -99,13 +108,12 @@ impl Memory {
 
         // get memory controller
         if let Some(ref mut memory_controller) = *::MEMORY_CONTROLLER.lock() {
-            // remap all pages
+            // remap all pages, then flush them from the TLBs of all the CPUs at once
+            let mut flush_range = FlushRange::new();
             for page in self.pages() {
-                memory_controller.remap(&mut active_table, page, new_flags);
+                flush_range.add(memory_controller.remap(&mut active_table, page, new_flags));
             }
-
-            // flush TLB
-            memory_controller.flush_all();
+            flush_range.flush();
 
             self.flags = new_flags;
         } else {
@@ -113,3 +121,82 @@ impl Memory {
         }
     }
 }
+
+/// Physical memory mapped on the address space of a context, like a device memory given by a
+/// scheme. The frames aren't owned, they are never freed.
+#[derive(Debug)]
+pub struct Grant {
+    /// Start address for the mapping.
+    start: VirtualAddress,
+    /// Size of the mapping.
+    size: usize,
+    /// Flags for the mapping.
+    flags: EntryFlags
+}
+
+impl Grant {
+    /// Map `size` bytes of physical memory, from `from`, at the virtual address `to`.
+    pub fn physmap(from: PhysicalAddress, to: VirtualAddress, size: usize, flags: EntryFlags) -> Grant {
+        let mut active_table = unsafe { ActivePageTable::new() };
+
+        if let Some(ref mut memory_controller) = *::MEMORY_CONTROLLER.lock() {
+            let start_page = Page::containing_address(to);
+            let end_page = Page::containing_address((to as usize + size - 1) as VirtualAddress);
+            for page in Page::range_inclusive(start_page, end_page) {
+                let frame = Frame::containing_address(page.start_address() - to as usize + from);
+                active_table.map_to(page, frame, flags, &mut memory_controller.frame_allocator);
+            }
+        } else {
+            panic!("Memory controller required");
+        }
+
+        Grant {
+            start: to,
+            size: size,
+            flags: flags
+        }
+    }
+
+    /// Get the start address for this mapping.
+    pub fn start_address(&self) -> VirtualAddress {
+        self.start
+    }
+
+    /// Get the size of the mapping.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Get the flags associated to this mapping.
+    pub fn flags(&self) -> EntryFlags {
+        self.flags
+    }
+
+    /// Unmap the pages, the physical memory is left as it is.
+    pub fn unmap(self) {
+        let mut active_table = unsafe { ActivePageTable::new() };
+
+        if let Some(ref mut memory_controller) = *::MEMORY_CONTROLLER.lock() {
+            let start_page = Page::containing_address(self.start);
+            let end_page = Page::containing_address((self.start as usize + self.size - 1) as VirtualAddress);
+
+            let mut flush_range = FlushRange::new();
+            for page in Page::range_inclusive(start_page, end_page) {
+                flush_range.add(active_table.unmap(page, &mut memory_controller.frame_allocator));
+            }
+            flush_range.flush();
+        } else {
+            panic!("Memory controller required");
+        }
+    }
+}
+
+/// Unmap the grants of a context, unless another context still shares them.
+pub fn unmap_grants(grants: Arc<Mutex<Vec<Grant>>>) {
+    if let Ok(grants) = Arc::try_unwrap(grants) {
+        let mut grants = grants.lock();
+        for grant in grants.drain(..) {
+            grant.unmap();
+        }
+    }
+}
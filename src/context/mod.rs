@@ -30,6 +30,9 @@ pub const CONTEXT_MAX_CONTEXT: usize = usize::max_value() - 1;
 /// Maximum context files
 pub const CONTEXT_MAX_FILES: usize = 65536;
 
+/// Number of pages for the kernel stack of each context (32 KiB)
+pub const CONTEXT_KERNEL_STACK_PAGES: usize = 8;
+
 /// Current context in this thread
 #[thread_local]
 static CONTEXT_ID: context::AtomicContextId = context::AtomicContextId::default();
@@ -52,7 +55,9 @@ pub fn contexts_mut() -> RwLockWriteGuard<'static, ContextList> {
     CONTEXTS.call_once(init_contexts).write()
 }
 
-/// Initialize the context sub-system
+/// Initialize the context sub-system on the current CPU
+///
+/// This creates the context for the code that is already running, so each CPU calls it once.
 pub fn init() {
     // get the contexts as mutable
     let mut contexts = contexts_mut();
@@ -29,11 +29,27 @@ pub unsafe fn switch() -> bool {
         }
 
         let check_context = |context: &mut Context| -> bool {
-            // Set the CPU id on the context if none specified
-            if context.cpu_id == None && cpu_id == 0 {
+            // Take the context to this CPU if none specified
+            if context.cpu_id == None {
                 context.cpu_id = Some(cpu_id);
             }
 
+            // Unblock a context waiting for an IRQ that fired
+            if let Some((irq, seen)) = context.wait_irq {
+                if context.status == Status::Blocked && arch::interrupts::irq::count(irq) != seen {
+                    context.wait_irq = None;
+                    context.status = Status::Runnable;
+                }
+            }
+
+            // Unblock a sleeping context whose deadline passed
+            if let Some(wake) = context.wake {
+                if context.status == Status::Blocked && arch::time::monotonic() >= wake {
+                    context.wake = None;
+                    context.status = Status::Runnable;
+                }
+            }
+
             // TODO unlock a context if there is new signals to be processed
 
             // the process is on the current CPU, can be run but isn't running.
@@ -46,12 +62,14 @@ pub unsafe fn switch() -> bool {
             false
         };
 
-        // find the next context to be executed
+        // find the next context to be executed. A context locked by another CPU is skipped: waiting
+        // for it here, with interrupts disabled, would deadlock with a TLB shootdown from that CPU.
         for (pid, context_lock) in contexts.iter() {
             if *pid > (*from_ptr).id {
-                let mut context = context_lock.write();
-                if check_context(&mut context) {
-                    to_ptr = context.deref_mut() as *mut Context;
+                if let Some(mut context) = context_lock.try_write() {
+                    if check_context(&mut context) {
+                        to_ptr = context.deref_mut() as *mut Context;
+                    }
                 }
             }
         }
@@ -75,6 +93,9 @@ pub unsafe fn switch() -> bool {
     // HACK: this is a temporary workaround, as arch is only used the the current CPU
     arch::context::CONTEXT_SWITCH_LOCK.store(false, Ordering::SeqCst);
 
+    // the CPU gets the TLB shootdowns of the page table of the new context from here
+    arch::memory::paging::flush::set_loaded_table((&*to_ptr).arch.page_table());
+
     // Switch to this new context
     (&mut *from_ptr).arch.switch_to(&mut (&mut *to_ptr).arch);
 
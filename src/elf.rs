@@ -1,33 +1,256 @@
 //! ELF executables
 
-use collections::String;
-use core::str;
+use collections::Vec;
+use core::{fmt, mem, ptr};
 
 pub use goblin::elf64::{header, program_header};
 
+/// Expected value for the ELF version fields
+const EV_CURRENT: u8 = 1;
+
+/// Index of the version byte on the identification field
+const EI_VERSION: usize = 6;
+
+/// Granularity used to map the segments into memory
+const PAGE_SIZE: u64 = 4096;
+
+/// Maximum size of the TLS block, far below `USER_TCB_OFFSET` where it ends
+pub const TLS_SIZE_MAX: u64 = 0x10_0000;
+
+/// Areas where executables can't place their segments, as `start..end`: the kernel image and the
+/// low memory, the kernel heap and stacks, the TLS block and the TCB, the kernel percpu areas, the
+/// position independent images and the device registers
+const RESERVED_AREAS: [(u64, u64); 7] = [
+    (0, ::arch::KERNEL_IMAGE_END as u64),
+    (::arch::KERNEL_HEAP_OFFSET as u64, (::arch::KERNEL_HEAP_OFFSET + ::arch::KERNEL_HEAP_SIZE) as u64),
+    (::arch::USER_TCB_OFFSET as u64 - TLS_SIZE_MAX, ::arch::USER_TCB_OFFSET as u64 + PAGE_SIZE),
+    (::arch::KERNEL_PERCPU_OFFSET as u64,
+     (::arch::KERNEL_PERCPU_OFFSET + ::arch::KERNEL_PERCPU_SIZE * ::arch::MAX_CPU_COUNT) as u64),
+    (::USER_PIE_OFFSET as u64, (::USER_PIE_OFFSET + ::USER_DYN_SIZE) as u64),
+    (::USER_INTERP_OFFSET as u64, (::USER_INTERP_OFFSET + ::USER_DYN_SIZE) as u64),
+    (::arch::DEVICE_IDENTITY_OFFSET as u64, (::arch::DEVICE_IDENTITY_OFFSET + ::arch::DEVICE_IDENTITY_SIZE) as u64)
+];
+
+/// Marks the end of the dynamic section
+const DT_NULL: u64 = 0;
+/// Address of the relocation table, with addends
+const DT_RELA: u64 = 7;
+/// Total size of the relocation table
+const DT_RELASZ: u64 = 8;
+/// Size of each relocation entry
+const DT_RELAENT: u64 = 9;
+
+/// Size of a dynamic section entry
+const SIZEOF_DYN: usize = 16;
+/// Size of a relocation entry, with addend
+const SIZEOF_RELA: usize = 24;
+
+/// Relocation type that only depends on the load base: `base + addend`
+pub const R_X86_64_RELATIVE: u32 = 8;
+
+/// Reasons for an ELF executable to be rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// There isn't enough data for the header
+    NotEnoughData(usize),
+    /// The file doesn't start with the ELF magic
+    InvalidMagic,
+    /// The file isn't a 64-bit ELF
+    InvalidClass(u8),
+    /// The file isn't little endian
+    InvalidEndianness(u8),
+    /// Unknown ELF version
+    InvalidVersion(u32),
+    /// The file isn't for x86_64
+    InvalidMachine(u16),
+    /// The file isn't an executable
+    InvalidType(u16),
+    /// The program header entries don't have the expected size
+    InvalidProgramHeaderSize(u16),
+    /// The program header table goes beyond the end of the file
+    ProgramHeadersOutOfBounds,
+    /// The segment data goes beyond the end of the file
+    SegmentOutOfBounds(usize),
+    /// The segment has more data on the file than in memory
+    SegmentFileSizeTooBig(usize),
+    /// The segment isn't inside of the user image area
+    SegmentNotInUserSpace(usize),
+    /// The two segments share memory pages
+    SegmentsOverlap(usize, usize),
+    /// The segment is on an area used by the kernel or by another image
+    SegmentInReservedArea(usize),
+    /// The TLS segment is too big, or its alignment isn't a power of two up to a page
+    InvalidTls(usize),
+    /// The entry point isn't inside of an executable loadable segment
+    InvalidEntry(u64),
+    /// The dynamic section points to a relocation table that isn't in the file
+    InvalidDynamic,
+    /// The relocation writes outside of the loadable segments
+    RelocationOutOfBounds(usize)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotEnoughData(len) => write!(f, "Elf: Not enough data: {} < {}", len, header::SIZEOF_EHDR),
+            Error::InvalidMagic => write!(f, "Elf: Invalid magic"),
+            Error::InvalidClass(class) => write!(f, "Elf: Invalid architecture: {} != {}", class, header::ELFCLASS),
+            Error::InvalidEndianness(data) => write!(f, "Elf: Invalid endianness: {} != {}", data, header::ELFDATA2LSB),
+            Error::InvalidVersion(version) => write!(f, "Elf: Invalid version: {}", version),
+            Error::InvalidMachine(machine) => write!(f, "Elf: Invalid machine: {} != {}", machine, header::EM_X86_64),
+            Error::InvalidType(kind) => write!(f, "Elf: Not an executable: type {}", kind),
+            Error::InvalidProgramHeaderSize(size) => write!(f, "Elf: Invalid program header size: {} != {}", size, program_header::SIZEOF_PHDR),
+            Error::ProgramHeadersOutOfBounds => write!(f, "Elf: Program headers out of bounds"),
+            Error::SegmentOutOfBounds(i) => write!(f, "Elf: Segment {} out of bounds", i),
+            Error::SegmentFileSizeTooBig(i) => write!(f, "Elf: Segment {} file size is bigger than the memory size", i),
+            Error::SegmentNotInUserSpace(i) => write!(f, "Elf: Segment {} outside of the user image area", i),
+            Error::SegmentsOverlap(a, b) => write!(f, "Elf: Segments {} and {} overlap", a, b),
+            Error::SegmentInReservedArea(i) => write!(f, "Elf: Segment {} is on a reserved area", i),
+            Error::InvalidTls(i) => write!(f, "Elf: Invalid TLS segment {}", i),
+            Error::InvalidEntry(entry) => write!(f, "Elf: Entry point {:#x} isn't in an executable segment", entry),
+            Error::InvalidDynamic => write!(f, "Elf: Invalid dynamic section"),
+            Error::RelocationOutOfBounds(i) => write!(f, "Elf: Relocation {} out of bounds", i)
+        }
+    }
+}
+
+/// A relocation entry, from the `DT_RELA` table
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Relocation {
+    /// Image relative address to be patched
+    pub offset: u64,
+    /// Relocation type
+    pub kind: u32,
+    /// Constant used to compute the value
+    pub addend: u64
+}
+
 /// ELF executable
 pub struct Elf<'a> {
     pub data: &'a [u8],
-    header: &'a header::Header
+    header: header::Header
 }
 
 impl<'a> Elf<'a> {
-    pub fn from(data: &'a [u8]) -> Result<Elf<'a>, String> {
+    /// Parse and validate an ELF executable.
+    ///
+    /// After this returns successfully, all the program headers and the file data of every segment
+    /// are inside of `data`, and all the loadable segments fit in the user image area without
+    /// sharing pages. For position independent executables the segment addresses are relative to
+    /// the load base, so they must fit in `USER_DYN_SIZE` instead. The TLS segment is at most
+    /// `TLS_SIZE_MAX` bytes, with an alignment that is a power of two up to a page. The entry point
+    /// is inside of an executable loadable segment.
+    pub fn from(data: &'a [u8]) -> Result<Elf<'a>, Error> {
         if data.len() < header::SIZEOF_EHDR {
-            Err(format!("Elf: Not enough data: {} < {}", data.len(), header::SIZEOF_EHDR))
+            return Err(Error::NotEnoughData(data.len()));
         } else if &data[..header::SELFMAG] != header::ELFMAG {
-            Err(format!("Elf: Invalid magic: {:?} != {:?}", &data[..4], header::ELFMAG))
-        } else if data.get(header::EI_CLASS) != Some(&header::ELFCLASS) {
-            Err(format!("Elf: Invalid architecture: {:?} != {:?}", data.get(header::EI_CLASS), header::ELFCLASS))
+            return Err(Error::InvalidMagic);
+        } else if data[header::EI_CLASS] != header::ELFCLASS {
+            return Err(Error::InvalidClass(data[header::EI_CLASS]));
+        } else if data[header::EI_DATA] != header::ELFDATA2LSB {
+            return Err(Error::InvalidEndianness(data[header::EI_DATA]));
+        } else if data[EI_VERSION] != EV_CURRENT {
+            return Err(Error::InvalidVersion(data[EI_VERSION] as u32));
+        }
+
+        // the data buffer doesn't need to be aligned, so the header is copied out
+        let header = unsafe { ptr::read_unaligned(data.as_ptr() as *const header::Header) };
+
+        if header.e_version != EV_CURRENT as u32 {
+            return Err(Error::InvalidVersion(header.e_version));
+        } else if header.e_machine != header::EM_X86_64 {
+            return Err(Error::InvalidMachine(header.e_machine));
+        } else if header.e_type != header::ET_EXEC && header.e_type != header::ET_DYN {
+            return Err(Error::InvalidType(header.e_type));
+        } else if header.e_phentsize as usize != program_header::SIZEOF_PHDR {
+            return Err(Error::InvalidProgramHeaderSize(header.e_phentsize));
+        }
+
+        // the program header table must be inside of the file
+        let table_size = header.e_phnum as u64 * header.e_phentsize as u64;
+        match header.e_phoff.checked_add(table_size) {
+            Some(end) if end <= data.len() as u64 => (),
+            _ => return Err(Error::ProgramHeadersOutOfBounds)
+        }
+
+        let elf = Elf {
+            data: data,
+            header: header
+        };
+        elf.validate_segments()?;
+
+        let entry = elf.header.e_entry;
+        let executable = elf.segments().any(|segment| {
+            segment.p_type == program_header::PT_LOAD
+                && segment.p_flags & program_header::PF_X == program_header::PF_X
+                && segment.p_vaddr <= entry
+                && entry < segment.p_vaddr + segment.p_memsz
+        });
+        if !executable {
+            return Err(Error::InvalidEntry(entry));
+        }
+
+        Ok(elf)
+    }
+
+    /// Check the bounds of every segment, executables must also stay out of `RESERVED_AREAS`.
+    fn validate_segments(&self) -> Result<(), Error> {
+        let (user_start, user_end) = if self.is_dynamic() {
+            (0, ::USER_DYN_SIZE as u64)
         } else {
-            Ok(Elf {
-                data: data,
-                header: unsafe { &*(data.as_ptr() as usize as *const header::Header) }
-            })
+            (::USER_OFFSET as u64, ::USER_HEAP_OFFSET as u64)
+        };
+
+        for (i, segment) in self.segments().enumerate() {
+            // the file data must be inside of the file
+            match segment.p_offset.checked_add(segment.p_filesz) {
+                Some(end) if end <= self.data.len() as u64 => (),
+                _ => return Err(Error::SegmentOutOfBounds(i))
+            }
+
+            if segment.p_filesz > segment.p_memsz {
+                return Err(Error::SegmentFileSizeTooBig(i));
+            }
+
+            // an alignment of 0 means no alignment, like 1
+            if segment.p_type == program_header::PT_TLS &&
+               (segment.p_memsz > TLS_SIZE_MAX || segment.p_align > PAGE_SIZE ||
+                segment.p_align & segment.p_align.wrapping_sub(1) != 0) {
+                return Err(Error::InvalidTls(i));
+            }
+
+            if segment.p_type != program_header::PT_LOAD {
+                continue;
+            }
+
+            // loadable segments must be inside of the user image area
+            match segment.p_vaddr.checked_add(segment.p_memsz) {
+                Some(end) if segment.p_vaddr >= user_start && end <= user_end => (),
+                _ => return Err(Error::SegmentNotInUserSpace(i))
+            }
+
+            // the areas are page aligned, so the pages of the segment intersect them only if the
+            // segment itself does
+            if !self.is_dynamic() {
+                for &(start, end) in RESERVED_AREAS.iter() {
+                    if segment.p_vaddr < end && segment.p_vaddr + segment.p_memsz > start {
+                        return Err(Error::SegmentInReservedArea(i));
+                    }
+                }
+            }
+
+            // each page can only be mapped by one segment
+            for (j, other) in self.segments().enumerate().take(i) {
+                if other.p_type == program_header::PT_LOAD && pages_overlap(&segment, &other) {
+                    return Err(Error::SegmentsOverlap(j, i));
+                }
+            }
         }
+
+        Ok(())
     }
 
-    pub fn segments(&'a self) -> ElfSegments<'a> {
+    pub fn segments(&self) -> ElfSegments<'a> {
         ElfSegments {
             data: self.data,
             header: self.header,
@@ -39,24 +262,179 @@ impl<'a> Elf<'a> {
     pub fn entry(&self) -> usize {
         self.header.e_entry as usize
     }
+
+    /// Check if this is a position independent executable (`ET_DYN`), that can be loaded anywhere.
+    pub fn is_dynamic(&self) -> bool {
+        self.header.e_type == header::ET_DYN
+    }
+
+    /// Get the path of the program interpreter (the dynamic linker), from the `PT_INTERP` segment.
+    pub fn interpreter(&self) -> Option<&'a [u8]> {
+        self.segments()
+            .find(|segment| segment.p_type == program_header::PT_INTERP)
+            .map(|segment| {
+                let start = segment.p_offset as usize;
+                let interp = &self.data[start..start + segment.p_filesz as usize];
+
+                // remove the NUL terminator
+                match interp.iter().position(|&b| b == 0) {
+                    Some(end) => &interp[..end],
+                    None => interp
+                }
+            })
+    }
+
+    /// Get the relocations from the `DT_RELA` table.
+    ///
+    /// All relocation offsets are checked to be inside of a loadable segment.
+    pub fn relocations(&self) -> Result<Vec<Relocation>, Error> {
+        let mut relocations = Vec::new();
+
+        let dynamic = match self.segments().find(|segment| segment.p_type == program_header::PT_DYNAMIC) {
+            Some(dynamic) => dynamic,
+            None => return Ok(relocations)
+        };
+
+        // look for the relocation table on the dynamic section
+        let (mut rela, mut rela_size, mut rela_entry) = (None, 0, SIZEOF_RELA as u64);
+        let start = dynamic.p_offset as usize;
+        for entry in self.data[start..start + dynamic.p_filesz as usize].chunks(SIZEOF_DYN) {
+            if entry.len() < SIZEOF_DYN {
+                break;
+            }
+
+            // d_tag, d_val
+            let dyn = unsafe { ptr::read_unaligned(entry.as_ptr() as *const [u64; 2]) };
+            match dyn[0] {
+                DT_NULL => break,
+                DT_RELA => rela = Some(dyn[1]),
+                DT_RELASZ => rela_size = dyn[1],
+                DT_RELAENT => rela_entry = dyn[1],
+                _ => ()
+            }
+        }
+
+        let rela = match rela {
+            Some(rela) => rela,
+            None => return Ok(relocations)
+        };
+        if rela_entry as usize != SIZEOF_RELA {
+            return Err(Error::InvalidDynamic);
+        }
+
+        // the table is addressed by its virtual address, so it must be backed by the file
+        let table_offset = self.file_offset(rela, rela_size).ok_or(Error::InvalidDynamic)?;
+        let table = &self.data[table_offset..table_offset + rela_size as usize];
+
+        for (i, entry) in table.chunks(SIZEOF_RELA).enumerate() {
+            if entry.len() < SIZEOF_RELA {
+                return Err(Error::InvalidDynamic);
+            }
+
+            // r_offset, r_info, r_addend
+            let rela = unsafe { ptr::read_unaligned(entry.as_ptr() as *const [u64; 3]) };
+            if !self.in_memory(rela[0], mem::size_of::<u64>() as u64) {
+                return Err(Error::RelocationOutOfBounds(i));
+            }
+
+            relocations.push(Relocation {
+                offset: rela[0],
+                kind: rela[1] as u32,
+                addend: rela[2]
+            });
+        }
+
+        Ok(relocations)
+    }
+
+    /// Find the file offset of a virtual address range, backed by the file data of a loadable
+    /// segment.
+    fn file_offset(&self, vaddr: u64, size: u64) -> Option<usize> {
+        let end = match vaddr.checked_add(size) {
+            Some(end) => end,
+            None => return None
+        };
+
+        self.segments()
+            .find(|segment| {
+                segment.p_type == program_header::PT_LOAD
+                    && segment.p_vaddr <= vaddr
+                    && end <= segment.p_vaddr + segment.p_filesz
+            })
+            .map(|segment| (segment.p_offset + vaddr - segment.p_vaddr) as usize)
+    }
+
+    /// Check if the virtual address range is inside of the memory of a loadable segment.
+    fn in_memory(&self, vaddr: u64, size: u64) -> bool {
+        match vaddr.checked_add(size) {
+            Some(end) => self.segments().any(|segment| {
+                segment.p_type == program_header::PT_LOAD
+                    && segment.p_vaddr <= vaddr
+                    && end <= segment.p_vaddr + segment.p_memsz
+            }),
+            None => false
+        }
+    }
+
+    /// Get the number of program headers
+    pub fn program_header_count(&self) -> usize {
+        self.header.e_phnum as usize
+    }
+
+    /// Get the size of each program header entry
+    pub fn program_header_size(&self) -> usize {
+        self.header.e_phentsize as usize
+    }
+
+    /// Get the virtual address of the program headers, once the image is loaded.
+    ///
+    /// This uses the `PT_PHDR` segment when present, otherwise looks for the `PT_LOAD` segment that
+    /// contains the program headers.
+    pub fn program_headers_address(&self) -> Option<usize> {
+        let phoff = self.header.e_phoff;
+
+        if let Some(segment) = self.segments().find(|segment| segment.p_type == program_header::PT_PHDR) {
+            return Some(segment.p_vaddr as usize);
+        }
+
+        self.segments()
+            .find(|segment| {
+                segment.p_type == program_header::PT_LOAD
+                    && segment.p_offset <= phoff
+                    && phoff < segment.p_offset + segment.p_filesz
+            })
+            .map(|segment| (segment.p_vaddr + phoff - segment.p_offset) as usize)
+    }
+}
+
+/// Check if two (already validated) segments share any memory page.
+fn pages_overlap(a: &program_header::ProgramHeader, b: &program_header::ProgramHeader) -> bool {
+    if a.p_memsz == 0 || b.p_memsz == 0 {
+        return false;
+    }
+
+    let a_start = a.p_vaddr / PAGE_SIZE;
+    let a_end = (a.p_vaddr + a.p_memsz - 1) / PAGE_SIZE;
+    let b_start = b.p_vaddr / PAGE_SIZE;
+    let b_end = (b.p_vaddr + b.p_memsz - 1) / PAGE_SIZE;
+
+    a_start <= b_end && b_start <= a_end
 }
 
 pub struct ElfSegments<'a> {
     data: &'a [u8],
-    header: &'a header::Header,
+    header: header::Header,
     i: usize
 }
 
 impl<'a> Iterator for ElfSegments<'a> {
-    type Item = &'a program_header::ProgramHeader;
+    type Item = program_header::ProgramHeader;
     fn next(&mut self) -> Option<Self::Item> {
         if self.i < self.header.e_phnum as usize {
+            // the bounds of the table were checked when the header was validated
+            let offset = self.header.e_phoff as usize + self.i * mem::size_of::<program_header::ProgramHeader>();
             let item = unsafe {
-                &* ((
-                    self.data.as_ptr() as usize
-                        + self.header.e_phoff as usize
-                        + self.i * self.header.e_phentsize as usize
-                ) as *const program_header::ProgramHeader)
+                ptr::read_unaligned(self.data[offset..].as_ptr() as *const program_header::ProgramHeader)
             };
             self.i += 1;
             Some(item)
@@ -65,3 +443,413 @@ impl<'a> Iterator for ElfSegments<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use collections::Vec;
+    use core::{mem, slice};
+
+    use super::{Elf, Error, header, program_header};
+    use super::program_header::ProgramHeader;
+
+    /// Get the raw bytes of a structure.
+    fn bytes<T>(value: &T) -> &[u8] {
+        unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+    }
+
+    /// A valid header with `phnum` program headers right after it.
+    fn valid_header(phnum: u16) -> header::Header {
+        let mut header = header::Header::default();
+        header.e_ident[..header::SELFMAG].copy_from_slice(header::ELFMAG);
+        header.e_ident[header::EI_CLASS] = header::ELFCLASS;
+        header.e_ident[header::EI_DATA] = header::ELFDATA2LSB;
+        header.e_ident[super::EI_VERSION] = super::EV_CURRENT;
+        header.e_type = header::ET_EXEC;
+        header.e_machine = header::EM_X86_64;
+        header.e_version = 1;
+        header.e_entry = 0x40_0000;
+        header.e_phoff = header::SIZEOF_EHDR as u64;
+        header.e_ehsize = header::SIZEOF_EHDR as u16;
+        header.e_phentsize = program_header::SIZEOF_PHDR as u16;
+        header.e_phnum = phnum;
+        header
+    }
+
+    /// An executable loadable segment.
+    fn load(offset: u64, vaddr: u64, filesz: u64, memsz: u64) -> ProgramHeader {
+        ProgramHeader {
+            p_type: program_header::PT_LOAD,
+            p_flags: program_header::PF_R | program_header::PF_X,
+            p_offset: offset,
+            p_vaddr: vaddr,
+            p_paddr: vaddr,
+            p_filesz: filesz,
+            p_memsz: memsz,
+            p_align: 0x1000
+        }
+    }
+
+    /// Build an ELF file, padded up to `len` bytes.
+    fn build(header: &header::Header, segments: &[ProgramHeader], len: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(bytes(header));
+        for segment in segments.iter() {
+            data.extend_from_slice(bytes(segment));
+        }
+        data.resize(len, 0);
+        data
+    }
+
+    fn valid_elf() -> Vec<u8> {
+        let segments = [
+            load(0, 0x40_0000, 0x200, 0x200),
+            load(0x200, 0x60_0000, 0x100, 0x2000)
+        ];
+        build(&valid_header(2), &segments, 0x300)
+    }
+
+    /// A position independent executable, with a dynamic section (at 0x100) pointing to a table
+    /// (at 0x140) with a single relocation, and an interpreter (at 0x180).
+    fn valid_pie() -> Vec<u8> {
+        let mut header = valid_header(3);
+        header.e_type = header::ET_DYN;
+        header.e_entry = 0x10;
+
+        let mut dynamic = ProgramHeader::default();
+        dynamic.p_type = program_header::PT_DYNAMIC;
+        dynamic.p_offset = 0x100;
+        dynamic.p_vaddr = 0x100;
+        dynamic.p_filesz = 0x40;
+        dynamic.p_memsz = 0x40;
+
+        let mut interp = ProgramHeader::default();
+        interp.p_type = program_header::PT_INTERP;
+        interp.p_offset = 0x180;
+        interp.p_filesz = 8;
+        interp.p_memsz = 8;
+
+        let mut data = build(&header, &[load(0, 0, 0x200, 0x1000), dynamic, interp], 0x200);
+        let words: [u64; 9] = [super::DT_RELA, 0x140, super::DT_RELASZ, 0x18, super::DT_NULL, 0, 0x800, super::R_X86_64_RELATIVE as u64, 0x10];
+        for (i, word) in words.iter().enumerate() {
+            let start = if i < 6 { 0x100 + i * 8 } else { 0x140 + (i - 6) * 8 };
+            data[start..start + 8].copy_from_slice(bytes(word));
+        }
+        data[0x180..0x188].copy_from_slice(b"/ld.so\0\0");
+        data
+    }
+
+    /// Pseudo random numbers for the fuzz tests (xorshift), the fixed seed keeps failures
+    /// reproducible.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, max: usize) -> usize {
+            (self.next() % max as u64) as usize
+        }
+    }
+
+    /// Parse mutated data: this must never panic, and anything accepted must keep the guarantees of
+    /// `Elf::from`.
+    fn check_mutation(data: &[u8]) {
+        let elf = match Elf::from(data) {
+            Ok(elf) => elf,
+            Err(_) => return
+        };
+
+        for segment in elf.segments() {
+            assert!(segment.p_offset + segment.p_filesz <= data.len() as u64);
+            assert!(segment.p_filesz <= segment.p_memsz);
+        }
+
+        let entry = elf.entry() as u64;
+        assert!(elf.segments().any(|segment| {
+            segment.p_type == program_header::PT_LOAD
+                && segment.p_flags & program_header::PF_X != 0
+                && segment.p_vaddr <= entry
+                && entry < segment.p_vaddr + segment.p_memsz
+        }));
+
+        let _ = elf.interpreter();
+        let _ = elf.program_headers_address();
+        if let Ok(relocations) = elf.relocations() {
+            for relocation in relocations.iter() {
+                assert!(elf.in_memory(relocation.offset, 8));
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_valid_executable() {
+        let data = valid_elf();
+        let elf = Elf::from(&data).expect("valid ELF rejected");
+        assert_eq!(elf.entry(), 0x40_0000);
+        assert_eq!(elf.segments().count(), 2);
+        assert_eq!(elf.program_headers_address(), Some(0x40_0000 + header::SIZEOF_EHDR));
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert_eq!(Elf::from(&valid_elf()[..10]).err(), Some(Error::NotEnoughData(10)));
+
+        let mut data = valid_elf();
+        data[0] = 0;
+        assert_eq!(Elf::from(&data).err(), Some(Error::InvalidMagic));
+
+        let mut data = valid_elf();
+        data[header::EI_CLASS] = 1;
+        assert_eq!(Elf::from(&data).err(), Some(Error::InvalidClass(1)));
+
+        let mut data = valid_elf();
+        data[header::EI_DATA] = 2;
+        assert_eq!(Elf::from(&data).err(), Some(Error::InvalidEndianness(2)));
+
+        let mut header = valid_header(0);
+        header.e_version = 2;
+        assert_eq!(Elf::from(&build(&header, &[], 0x100)).err(), Some(Error::InvalidVersion(2)));
+
+        let mut header = valid_header(0);
+        header.e_machine = 3;
+        assert_eq!(Elf::from(&build(&header, &[], 0x100)).err(), Some(Error::InvalidMachine(3)));
+
+        let mut header = valid_header(0);
+        header.e_type = 1;
+        assert_eq!(Elf::from(&build(&header, &[], 0x100)).err(), Some(Error::InvalidType(1)));
+
+        let mut header = valid_header(0);
+        header.e_phentsize = 32;
+        assert_eq!(Elf::from(&build(&header, &[], 0x100)).err(), Some(Error::InvalidProgramHeaderSize(32)));
+    }
+
+    #[test]
+    fn rejects_program_headers_out_of_bounds() {
+        let header = valid_header(10);
+        assert_eq!(Elf::from(&build(&header, &[], 0x100)).err(), Some(Error::ProgramHeadersOutOfBounds));
+
+        let mut header = valid_header(1);
+        header.e_phoff = u64::max_value() - 8;
+        assert_eq!(Elf::from(&build(&header, &[], 0x100)).err(), Some(Error::ProgramHeadersOutOfBounds));
+    }
+
+    #[test]
+    fn rejects_invalid_segments() {
+        let check = |segments: &[ProgramHeader], error| {
+            let data = build(&valid_header(segments.len() as u16), segments, 0x300);
+            assert_eq!(Elf::from(&data).err(), Some(error));
+        };
+
+        check(&[load(0x200, 0x40_0000, 0x200, 0x200)], Error::SegmentOutOfBounds(0));
+        check(&[load(u64::max_value(), 0x40_0000, 2, 2)], Error::SegmentOutOfBounds(0));
+        check(&[load(0, 0x40_0000, 0x200, 0x100)], Error::SegmentFileSizeTooBig(0));
+        check(&[load(0, 0xffff_8000_0000_0000, 0x200, 0x200)], Error::SegmentNotInUserSpace(0));
+        check(&[load(0, ::USER_HEAP_OFFSET as u64 - 0x100, 0x200, 0x200)], Error::SegmentNotInUserSpace(0));
+        check(&[load(0, u64::max_value() - 0x10, 0x200, 0x200)], Error::SegmentNotInUserSpace(0));
+        check(&[load(0, 0x40_0000, 0x200, 0x200), load(0, 0x40_0800, 0x100, 0x100)], Error::SegmentsOverlap(0, 1));
+    }
+
+    #[test]
+    fn rejects_segments_in_kernel_areas() {
+        let check = |vaddr: usize| {
+            let mut header = valid_header(1);
+            header.e_entry = vaddr as u64;
+            Elf::from(&build(&header, &[load(0, vaddr as u64, 0x200, 0x200)], 0x300)).err()
+        };
+
+        // the kernel image is linked at 1 MiB, the VGA text buffer is at 0xb8000
+        assert_eq!(check(0), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(0xb_8000), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(0x10_0000), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::arch::KERNEL_IMAGE_END - 0x100), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::arch::KERNEL_IMAGE_END), None);
+
+        assert_eq!(check(::arch::KERNEL_HEAP_OFFSET), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::arch::KERNEL_HEAP_OFFSET + ::arch::KERNEL_HEAP_SIZE - 0x100), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::arch::KERNEL_HEAP_OFFSET + ::arch::KERNEL_HEAP_SIZE), None);
+    }
+
+    #[test]
+    fn rejects_segments_in_tls_area() {
+        let check = |vaddr: usize| {
+            let mut header = valid_header(1);
+            header.e_entry = vaddr as u64;
+            Elf::from(&build(&header, &[load(0, vaddr as u64, 0x200, 0x200)], 0x300)).err()
+        };
+
+        // the TLS block ends at the TCB, the TCB takes a page
+        assert_eq!(check(::arch::USER_TCB_OFFSET - 0x100), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::arch::USER_TCB_OFFSET - super::TLS_SIZE_MAX as usize), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::arch::USER_TCB_OFFSET + 0x800), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::arch::USER_TCB_OFFSET + 0x1000), None);
+    }
+
+    #[test]
+    fn rejects_segments_in_percpu_areas() {
+        let check = |vaddr: usize| {
+            let mut header = valid_header(1);
+            header.e_entry = vaddr as u64;
+            Elf::from(&build(&header, &[load(0, vaddr as u64, 0x200, 0x200)], 0x300)).err()
+        };
+
+        let end = ::arch::KERNEL_PERCPU_OFFSET + ::arch::KERNEL_PERCPU_SIZE * ::arch::MAX_CPU_COUNT;
+        assert_eq!(check(::arch::KERNEL_PERCPU_OFFSET - 0x100), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(end - 0x100), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(end), None);
+    }
+
+    #[test]
+    fn rejects_segments_in_dynamic_areas() {
+        let check = |vaddr: usize| {
+            let mut header = valid_header(1);
+            header.e_entry = vaddr as u64;
+            Elf::from(&build(&header, &[load(0, vaddr as u64, 0x200, 0x200)], 0x300)).err()
+        };
+
+        assert_eq!(check(::USER_PIE_OFFSET - 0x100), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::USER_PIE_OFFSET + ::USER_DYN_SIZE - 0x100), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::USER_INTERP_OFFSET), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::USER_INTERP_OFFSET + ::USER_DYN_SIZE - 0x100), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(::USER_INTERP_OFFSET + ::USER_DYN_SIZE), None);
+    }
+
+    #[test]
+    fn rejects_segments_in_device_areas() {
+        let check = |vaddr: usize| {
+            let mut header = valid_header(1);
+            header.e_entry = vaddr as u64;
+            Elf::from(&build(&header, &[load(0, vaddr as u64, 0x200, 0x200)], 0x300)).err()
+        };
+
+        // I/O APIC, HPET and Local APIC
+        assert_eq!(check(0xfec0_0000), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(0xfed0_0000), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(0xfee0_0000), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(0xffff_ff00), Some(Error::SegmentInReservedArea(0)));
+        assert_eq!(check(0x1_0000_0000), None);
+    }
+
+    #[test]
+    fn rejects_invalid_tls() {
+        let tls = |memsz: u64, align: u64| {
+            let mut tls = ProgramHeader::default();
+            tls.p_type = program_header::PT_TLS;
+            tls.p_offset = 0x200;
+            tls.p_filesz = 0x10;
+            tls.p_memsz = memsz;
+            tls.p_align = align;
+            tls
+        };
+        let check = |segment: ProgramHeader| {
+            let data = build(&valid_header(2), &[load(0, 0x40_0000, 0x200, 0x200), segment], 0x300);
+            Elf::from(&data).err()
+        };
+
+        assert_eq!(check(tls(0x40, 0)), None);
+        assert_eq!(check(tls(0x40, 64)), None);
+        assert_eq!(check(tls(0x40, 48)), Some(Error::InvalidTls(1)));
+        assert_eq!(check(tls(0x40, 0x2000)), Some(Error::InvalidTls(1)));
+        assert_eq!(check(tls(super::TLS_SIZE_MAX + 1, 8)), Some(Error::InvalidTls(1)));
+        assert_eq!(check(tls(u64::max_value(), 8)), Some(Error::InvalidTls(1)));
+        assert_eq!(check(tls(0x8, 8)), Some(Error::SegmentFileSizeTooBig(1)));
+    }
+
+    #[test]
+    fn rejects_invalid_entry() {
+        let check = |entry: u64, flags: u32| {
+            let mut header = valid_header(2);
+            header.e_entry = entry;
+            let mut data_segment = load(0x200, 0x60_0000, 0x100, 0x2000);
+            data_segment.p_flags = flags;
+            Elf::from(&build(&header, &[load(0, 0x40_0000, 0x200, 0x200), data_segment], 0x300)).err()
+        };
+
+        assert_eq!(check(0x40_01ff, program_header::PF_R), None);
+        assert_eq!(check(0x60_1fff, program_header::PF_R | program_header::PF_X), None);
+        assert_eq!(check(0x40_0200, program_header::PF_R), Some(Error::InvalidEntry(0x40_0200)));
+        assert_eq!(check(0x60_0000, program_header::PF_R | program_header::PF_W), Some(Error::InvalidEntry(0x60_0000)));
+        assert_eq!(check(0x60_2000, program_header::PF_R | program_header::PF_X), Some(Error::InvalidEntry(0x60_2000)));
+        assert_eq!(check(0, program_header::PF_R), Some(Error::InvalidEntry(0)));
+        assert_eq!(check(u64::max_value(), program_header::PF_R), Some(Error::InvalidEntry(u64::max_value())));
+    }
+
+    #[test]
+    fn parses_position_independent_executable() {
+        let mut data = valid_pie();
+
+        let elf = Elf::from(&data).expect("valid PIE rejected");
+        assert!(elf.is_dynamic());
+        assert_eq!(elf.interpreter(), Some(&b"/ld.so"[..]));
+        assert_eq!(elf.relocations(), Ok(vec![super::Relocation {
+            offset: 0x800,
+            kind: super::R_X86_64_RELATIVE,
+            addend: 0x10
+        }]));
+
+        // the relocation can't patch memory outside of the image
+        data[0x140..0x148].copy_from_slice(bytes(&0x1000u64));
+        assert_eq!(Elf::from(&data).unwrap().relocations(), Err(Error::RelocationOutOfBounds(0)));
+    }
+
+    #[test]
+    fn fuzz_single_byte_mutations() {
+        for original in [valid_elf(), valid_pie()].iter() {
+            for i in 0..original.len() {
+                for &value in [0x00u8, 0x01, 0x7f, 0x80, 0xfe, 0xff].iter() {
+                    let mut data = original.clone();
+                    data[i] = value;
+                    check_mutation(&data);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_random_mutations() {
+        let originals = [valid_elf(), valid_pie()];
+        let values = [0, 1, 0x1000, 0x40_0000, 0x7fff_ffff_ffff, 0x8000_0000_0000, u64::max_value()];
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..20000 {
+            let mut data = originals[random.below(originals.len())].clone();
+
+            // several mutations at once, so fields that are checked together change together
+            for _ in 0..1 + random.below(8) {
+                match random.below(4) {
+                    // a random byte
+                    0 => {
+                        let i = random.below(data.len());
+                        data[i] = random.next() as u8;
+                    },
+                    // a 64-bit field, set to a boundary value or close to it
+                    1 => {
+                        let i = random.below(data.len() / 8) * 8;
+                        let value = values[random.below(values.len())].wrapping_add(random.below(3) as u64).wrapping_sub(1);
+                        data[i..i + 8].copy_from_slice(bytes(&value));
+                    },
+                    // a random 64-bit field
+                    2 => {
+                        let i = random.below(data.len() / 8) * 8;
+                        let value = random.next();
+                        data[i..i + 8].copy_from_slice(bytes(&value));
+                    },
+                    // the file is truncated
+                    _ => {
+                        let len = random.below(data.len() + 1);
+                        data.truncate(len);
+                    }
+                }
+
+                if data.len() < 8 {
+                    break;
+                }
+            }
+
+            check_mutation(&data);
+        }
+    }
+}
@@ -14,37 +14,46 @@
 #[macro_use]
 extern crate arch_x86_64 as arch;
 
+/// Memory layout of the architecture, for the tests. The rest of the arch crate can't be linked on
+/// the host, so only the parts of the kernel that don't depend on it are built for the tests.
+#[cfg(test)]
+#[path = "../arch/x86_64/src/consts.rs"]
+mod arch;
+
 extern crate alloc;
 #[macro_use]
 extern crate collections;
 extern crate goblin;
 extern crate spin;
 
+#[cfg(not(test))]
 use arch::memory::MemoryController;
+#[cfg(not(test))]
 use arch::interrupts;
-use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+#[cfg(not(test))]
 use spin::Mutex;
 
 #[macro_use]
 pub mod common;
 
+#[cfg(not(test))]
 pub mod context;
 
 /// ELF module
 pub mod elf;
 
 /// Scheme module
+#[cfg(not(test))]
 pub mod scheme;
 
 /// System calls module
+#[cfg(not(test))]
 pub mod syscall;
 
 /// Architecture memory controller.
+#[cfg(not(test))]
 static MEMORY_CONTROLLER: Mutex<Option<&'static mut MemoryController>> = Mutex::new(None);
 
-/// A unique number that identifies the current CPU - used for scheduling.
-static CPU_ID: AtomicUsize = ATOMIC_USIZE_INIT;
-
 /// The size of a single PML4
 pub const PML4_SIZE: usize = 0x0000_0080_0000_0000;
 
@@ -63,32 +72,42 @@ pub const USER_GRANT_OFFSET: usize = USER_HEAP_OFFSET + PML4_SIZE;
 /// Size of user stack
 pub const USER_STACK_SIZE: usize = 1024 * 1024; // 1 MB
 
+/// Offset where position independent executables are loaded
+pub const USER_PIE_OFFSET: usize = USER_OFFSET + PML4_SIZE / 4;
+
+/// Offset where the program interpreter (dynamic linker) is loaded
+pub const USER_INTERP_OFFSET: usize = USER_OFFSET + PML4_SIZE / 2;
+
+/// Maximum size of a position independent image. This is also the range of the random offset
+/// added to the load base when ASLR is enabled.
+pub const USER_DYN_SIZE: usize = PML4_SIZE / 8;
+
 /// Get the current CPU's scheduling ID.
+#[cfg(not(test))]
 pub fn cpu_id() -> usize {
-    CPU_ID.load(Ordering::Relaxed)
+    arch::start::cpu_id()
 }
 
 /// Initialize userspace by running the initfs:bin/init process
+#[cfg(not(test))]
 pub extern fn userspace_init() {
     // change dir for the init FS
     assert_eq!(syscall::chdir(b"initfs:"), Ok(0));
 
     // start the first program
-    syscall::exec(b"/bin/init", &[]).expect("failed to execute init");
+    syscall::exec(b"/bin/init", &[], &[]).expect("failed to execute init");
 
     panic!("init returned");
 }
 
 /// This is the kernel entry point for the primary CPU. The arch crate is responsible for calling
 /// this.
+#[cfg(not(test))]
 #[no_mangle]
 pub extern fn kmain(memory_controller: &'static mut MemoryController) -> ! {
     // save the memory controller
     *MEMORY_CONTROLLER.lock() = Some(memory_controller);
 
-    // set the current CPU id
-    CPU_ID.store(0, Ordering::SeqCst);
-
     // initialize the context sub-system
     context::init();
 
@@ -103,6 +122,25 @@ pub extern fn kmain(memory_controller: &'static mut MemoryController) -> ! {
         }
     }
 
+    run_scheduler();
+}
+
+/// This is the kernel entry point for the application processors. The arch crate is responsible
+/// for calling this, after `kmain` was called on the primary CPU.
+#[cfg(not(test))]
+#[no_mangle]
+pub extern fn kmain_ap(cpu_id: usize) -> ! {
+    // create the context that is running on this CPU
+    context::init();
+
+    kinfo!("CPU {}: running", cpu_id);
+
+    run_scheduler();
+}
+
+/// Run the contexts available for the current CPU, halting it when there is nothing to do.
+#[cfg(not(test))]
+fn run_scheduler() -> ! {
     loop {
         unsafe {
             // disable interrupts in order to perform the switch without interruptions.
@@ -0,0 +1,87 @@
+//! # Console scheme
+//!
+//! Gives access to the text console, on the VGA buffer or on the framebuffer.
+//!
+//! - `write` on `console:` prints the text, interpreting the ANSI escape sequences: colors, cursor
+//!   movement, and clearing the line or the screen.
+//! - `write` on `console:scroll`, with a number of lines as text, scrolls the view back through the
+//!   scrollback, or forward when it's negative.
+//!
+//! Anyone can write on `console:`, only root, the console owner, can use `console:scroll`.
+
+use arch::vga_buffer;
+use collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::str;
+use spin::RwLock;
+
+use syscall::error::*;
+use syscall::flag::MODE_FILE;
+use syscall::scheme::Scheme;
+
+use super::{check_permission, open_access};
+
+/// Number of bytes printed with interrupts disabled, before letting the pending IRQs run
+const WRITE_CHUNK_SIZE: usize = 256;
+
+/// What a handle gives access to
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Text,
+    Scroll
+}
+
+pub struct ConsoleScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Kind>>
+}
+
+impl ConsoleScheme {
+    /// Create a new instance of `ConsoleScheme`
+    pub fn new() -> Self {
+        ConsoleScheme {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new())
+        }
+    }
+}
+
+impl Scheme for ConsoleScheme {
+    fn open(&self, path: &[u8], flags: usize, uid: u32, gid: u32) -> Result<usize> {
+        let (kind, mode) = match str::from_utf8(path).or(Err(Error::new(ENOENT)))?.trim_matches('/') {
+            "" => (Kind::Text, MODE_FILE | 0o622),
+            "scroll" => (Kind::Scroll, MODE_FILE | 0o600),
+            _ => return Err(Error::new(ENOENT))
+        };
+        check_permission(mode, 0, 0, uid, gid, open_access(flags)?)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, kind);
+
+        Ok(id)
+    }
+
+    fn write(&self, id: usize, buffer: &[u8]) -> Result<usize> {
+        let kind = *self.handles.read().get(&id).ok_or(Error::new(EBADF))?;
+
+        match kind {
+            Kind::Text => {
+                for chunk in buffer.chunks(WRITE_CHUNK_SIZE) {
+                    vga_buffer::with_writer(|writer| writer.write_bytes(chunk));
+                }
+            },
+            Kind::Scroll => {
+                let text = str::from_utf8(buffer).or(Err(Error::new(EINVAL)))?;
+                let lines = text.trim().parse::<isize>().or(Err(Error::new(EINVAL)))?;
+                vga_buffer::with_writer(|writer| writer.scroll_view(lines));
+            }
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.handles.write().remove(&id).ok_or(Error::new(EBADF))?;
+        Ok(0)
+    }
+}
@@ -0,0 +1,154 @@
+//! # Framebuffer scheme
+//!
+//! Gives the framebuffer to a userspace compositor. It's only available when the bootloader set a
+//! linear framebuffer, and only to root.
+//!
+//! - `read` on `fb:` returns a `FramebufferInfo` with the mode.
+//! - `fmap` on `fb:` maps the framebuffer pixels on the caller address space. The kernel console
+//!   stops drawing from the first mapping, and draws again once all the handles that mapped it are
+//!   closed. Closing a handle unmaps the pixels it mapped.
+
+use alloc::arc::{Arc, Weak};
+use arch::device::framebuffer::{self, Info};
+use arch::memory::PAGE_SIZE;
+use arch::memory::paging::{entry, VirtualAddress};
+use arch::vga_buffer;
+use collections::{BTreeMap, Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::mem;
+use spin::{Mutex, RwLock};
+
+use context;
+use context::memory::Grant;
+use syscall::data::FramebufferInfo;
+use syscall::error::*;
+use syscall::scheme::Scheme;
+
+/// Open `fb:` handle
+struct Handle {
+    /// Mappings made with the handle, as the grants of the context and the address of the grant
+    grants: Vec<(Weak<Mutex<Vec<Grant>>>, VirtualAddress)>
+}
+
+pub struct FbScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Handle>>
+}
+
+impl FbScheme {
+    /// Create a new instance of `FbScheme`
+    pub fn new() -> Self {
+        FbScheme {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new())
+        }
+    }
+}
+
+/// Get the framebuffer, or `ENODEV` when the console is on the VGA text buffer.
+fn info() -> Result<Info> {
+    framebuffer::info().ok_or(Error::new(ENODEV))
+}
+
+impl Scheme for FbScheme {
+    fn open(&self, path: &[u8], _flags: usize, uid: u32, _gid: u32) -> Result<usize> {
+        if path.iter().any(|&byte| byte != b'/') {
+            return Err(Error::new(ENOENT));
+        }
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+        info()?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, Handle { grants: Vec::new() });
+
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buffer: &mut [u8]) -> Result<usize> {
+        self.handles.read().get(&id).ok_or(Error::new(EBADF))?;
+
+        let size = mem::size_of::<FramebufferInfo>();
+        if buffer.len() < size {
+            return Err(Error::new(EINVAL));
+        }
+
+        let info = info()?;
+        let mode = FramebufferInfo {
+            width: info.width as u32,
+            height: info.height as u32,
+            pitch: info.pitch as u32,
+            bpp: info.bpp,
+            red_position: info.red.position,
+            red_size: info.red.size,
+            green_position: info.green.position,
+            green_size: info.green.size,
+            blue_position: info.blue.position,
+            blue_size: info.blue.size
+        };
+        buffer[..size].copy_from_slice(&mode);
+
+        Ok(size)
+    }
+
+    fn fmap(&self, id: usize, offset: usize, size: usize) -> Result<usize> {
+        self.handles.read().get(&id).ok_or(Error::new(EBADF))?;
+
+        let info = info()?;
+        let end = offset.checked_add(size).ok_or(Error::new(EINVAL))?;
+        if offset % PAGE_SIZE != 0 || size == 0 || end > info.size() {
+            return Err(Error::new(EINVAL));
+        }
+        let pages_size = (size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+
+        let (context_grants, address) = {
+            let contexts = context::contexts();
+            let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+            let context = context_lock.read();
+            let mut grants = context.grants.lock();
+
+            // the grants are placed one after the other
+            let address = match grants.last() {
+                Some(grant) => grant.start_address() + (grant.size() + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE,
+                None => ::USER_GRANT_OFFSET
+            };
+            if address + pages_size > ::USER_GRANT_OFFSET + ::PML4_SIZE {
+                return Err(Error::new(ENOMEM));
+            }
+
+            grants.push(Grant::physmap(info.address + offset, address, pages_size,
+                                       entry::WRITABLE | entry::USER_ACCESSIBLE | entry::NO_EXECUTE));
+            (Arc::downgrade(&context.grants), address)
+        };
+
+        match self.handles.write().get_mut(&id) {
+            Some(handle) => handle.grants.push((context_grants, address)),
+            None => return Err(Error::new(EBADF))
+        }
+        vga_buffer::with_writer(|writer| writer.set_visible(false));
+
+        Ok(address)
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        let mut handles = self.handles.write();
+        let handle = handles.remove(&id).ok_or(Error::new(EBADF))?;
+
+        // the grants are gone if their context exec'd or was reaped
+        for (context_grants, address) in handle.grants.into_iter() {
+            if let Some(context_grants) = context_grants.upgrade() {
+                let mut grants = context_grants.lock();
+                if let Some(index) = grants.iter().position(|grant| grant.start_address() == address) {
+                    grants.remove(index).unmap();
+                }
+            }
+        }
+
+        if !handles.values().any(|handle| !handle.grants.is_empty()) {
+            vga_buffer::with_writer(|writer| writer.set_visible(true));
+        }
+
+        Ok(0)
+    }
+}
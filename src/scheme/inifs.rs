@@ -8,7 +8,9 @@ use spin::RwLock;
 use syscall::data::Stat;
 use syscall::error::*;
 use syscall::scheme::Scheme;
-use syscall::flag::{MODE_DIR, MODE_FILE};
+use syscall::flag::{MODE_DIR, MODE_FILE, MODE_WRITE};
+
+use super::{check_permission, open_access};
 
 // Include the auto-generated file with list of files that are part of Initfs.
 include!(concat!(env!("OUT_DIR"), "/gen.rs"));
@@ -39,7 +41,7 @@ impl InitFsScheme {
 }
 
 impl Scheme for InitFsScheme {
-    fn open(&self, path: &[u8], flags: usize, _uid: u32, _gid: u32) -> Result<usize> {
+    fn open(&self, path: &[u8], flags: usize, uid: u32, gid: u32) -> Result<usize> {
         // get a str from the path argument
         let path_utf8 = str::from_utf8(path).or(Err(Error::new(ENOENT)))?;
 
@@ -48,12 +50,21 @@ impl Scheme for InitFsScheme {
 
         for entry in self.files.iter() {
             if entry.0 == &path_trimmed.as_bytes() {
+                let mode = if (entry.1).1 { MODE_DIR | 0o755 } else { MODE_FILE | 0o744 };
+
+                // all the files are owned by root, and nothing can be written, even by root
+                let access = open_access(flags)?;
+                if access & MODE_WRITE == MODE_WRITE {
+                    return Err(Error::new(EROFS));
+                }
+                check_permission(mode, 0, 0, uid, gid, access)?;
+
                 let id = self.next_id.fetch_add(1, Ordering::SeqCst);
                 self.handles.write().insert(id, Handle {
                     path: entry.0,
                     flags: flags,
                     data: (entry.1).0,
-                    mode: if (entry.1).1 { MODE_DIR | 0o755 } else { MODE_FILE | 0o744 },
+                    mode: mode,
                     seek: 0
                 });
 
@@ -0,0 +1,320 @@
+//! # IRQ scheme
+//!
+//! Lets userspace drivers wait for hardware interrupts. Opening `irq:N` (root only) installs a
+//! kernel handler for the IRQ `N`, that masks the IRQ each time it fires.
+//!
+//! PCI functions can signal their interrupts as messages instead. Opening `irq:msi/00:02.0`
+//! allocates a vector and enables the MSI of that function on it, `irq:msix/00:02.0/N` does the
+//! same with the entry `N` of its MSI-X table. The messages are sent to the BSP.
+//!
+//! - `read` blocks until the IRQ fires, then returns the number of times it fired, as an `usize`.
+//! - `write`, with the value returned by `read`, acknowledges the IRQ. The IRQ is unmasked once
+//!   every handle of it acknowledged the same count. Messages are edge triggered, so they're never
+//!   masked.
+//!
+//! The IRQ handler is removed when the last handle for it is closed. The vector of a message is
+//! freed, and the message disabled, when its handle is closed.
+
+use arch::device::io_apic;
+use arch::device::pci::{self, Device, PciAddress};
+use arch::device::pci::msi;
+use arch::interrupts::{self, IRQ_COUNT};
+use arch::interrupts::ipi::{self, IpiTarget};
+use arch::interrupts::irq::{self, count};
+use collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{mem, ptr, str};
+use spin::{Mutex, RwLock};
+
+use context;
+use syscall::error::*;
+use syscall::scheme::Scheme;
+
+/// Kernel handler for the IRQs opened through the scheme.
+///
+/// The IRQ is masked until userspace acknowledges it, since a level triggered one would keep
+/// firing until the driver handles the device.
+fn irq_handler(irq: u8) {
+    io_apic::mask(irq);
+
+    // the context waiting for the IRQ may belong to a halted CPU
+    ipi::reschedule(IpiTarget::Other);
+}
+
+/// Kernel handler for the messages opened through the scheme.
+fn msi_handler(_irq: u8) {
+    ipi::reschedule(IpiTarget::Other);
+}
+
+/// Where the interrupts of a handle come from
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Source {
+    /// An I/O APIC input, shared by the handles of the IRQ
+    Legacy,
+    /// The MSI of a function
+    Msi(PciAddress),
+    /// An entry of the MSI-X table of a function
+    MsiX(PciAddress, u16)
+}
+
+/// An open IRQ
+struct Handle {
+    irq: u8,
+    source: Source,
+    /// IRQ count last acknowledged through this handle
+    acknowledged: usize
+}
+
+pub struct IrqScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Handle>>,
+    /// Number of open handles of each IRQ
+    users: Mutex<[usize; IRQ_COUNT]>
+}
+
+impl IrqScheme {
+    /// Create a new instance of `IrqScheme`
+    pub fn new() -> Self {
+        IrqScheme {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new()),
+            users: Mutex::new([0; IRQ_COUNT])
+        }
+    }
+
+    /// Get the IRQ of a handle and the last count it acknowledged
+    fn state(&self, id: usize) -> Result<(u8, usize)> {
+        let handles = self.handles.read();
+        let handle = handles.get(&id).ok_or(Error::new(EBADF))?;
+        Ok((handle.irq, handle.acknowledged))
+    }
+
+    /// Install the kernel handler of an I/O APIC input, for a new handle.
+    fn open_legacy(&self, irq: u8) -> Result<()> {
+        let mut users = self.users.lock();
+
+        // the first handle installs the kernel handler, this fails if a kernel driver owns it
+        if users[irq as usize] == 0 && !irq::register(irq, irq_handler) {
+            return Err(Error::new(EBUSY));
+        }
+        users[irq as usize] += 1;
+        Ok(())
+    }
+}
+
+/// Unmask a legacy IRQ, if every handle of it acknowledged the last time it fired.
+fn unmask_acknowledged(handles: &BTreeMap<usize, Handle>, irq: u8) {
+    let current = count(irq);
+    let acknowledged = handles.values()
+        .filter(|handle| handle.source == Source::Legacy && handle.irq == irq)
+        .all(|handle| handle.acknowledged == current);
+
+    if acknowledged {
+        io_apic::unmask(irq);
+    }
+}
+
+/// Parse the path of a message, `msi/bb:dd.f` or `msix/bb:dd.f/N`.
+fn parse_message(path: &str) -> Option<Source> {
+    let mut parts = path.split('/');
+    let kind = parts.next();
+    let address = parts.next().and_then(PciAddress::parse);
+    let index = parts.next();
+
+    match (kind, address, index, parts.next()) {
+        (Some("msi"), Some(address), None, None) => Some(Source::Msi(address)),
+        (Some("msix"), Some(address), Some(index), None) => {
+            index.parse::<u16>().ok().map(|index| Source::MsiX(address, index))
+        },
+        _ => None
+    }
+}
+
+/// Allocate a vector for a message and program the function to send it to the BSP.
+///
+/// ## Returns
+/// The IRQ of the vector.
+fn open_message(source: Source) -> Result<u8> {
+    let device: &Device = match source {
+        Source::Msi(address) | Source::MsiX(address, _) => pci::find(address).ok_or(Error::new(ENOENT))?,
+        Source::Legacy => return Err(Error::new(EINVAL))
+    };
+
+    // check the capability first, so a missing one isn't reported as busy
+    let supported = match source {
+        Source::Msi(_) => device.capability(pci::CAPABILITY_MSI).is_some(),
+        Source::MsiX(_, index) => msi::msix_count(device).map_or(false, |count| index < count),
+        Source::Legacy => false
+    };
+    if !supported {
+        return Err(Error::new(ENODEV));
+    }
+
+    let apic_id = ipi::apic_id(0).ok_or(Error::new(ENODEV))?;
+    let irq = irq::allocate_msi(msi_handler).ok_or(Error::new(EAGAIN))?;
+
+    let enabled = match (source, irq::vector(irq)) {
+        (Source::Msi(_), Some(vector)) => msi::enable_msi(device, apic_id, vector),
+        (Source::MsiX(_, index), Some(vector)) => {
+            match *::MEMORY_CONTROLLER.lock() {
+                Some(ref mut memory_controller) => msi::enable_msix(memory_controller, device, index, apic_id, vector),
+                None => false
+            }
+        },
+        _ => false
+    };
+    if !enabled {
+        irq::free_msi(irq);
+        return Err(Error::new(EBUSY));
+    }
+
+    Ok(irq)
+}
+
+/// Disable a message and free its vector.
+fn close_message(source: Source, irq: u8) {
+    match source {
+        Source::Msi(address) => {
+            if let Some(device) = pci::find(address) {
+                msi::disable_msi(device);
+            }
+        },
+        Source::MsiX(address, index) => {
+            if let Some(device) = pci::find(address) {
+                msi::disable_msix(device, index);
+            }
+        },
+        Source::Legacy => return
+    }
+
+    irq::free_msi(irq);
+}
+
+/// Block the current context until the IRQ count is different from `seen`.
+///
+/// Kernel drivers use it too, to wait for their device.
+///
+/// ## Returns
+/// The new IRQ count.
+pub fn wait(irq: u8, seen: usize) -> Result<usize> {
+    loop {
+        let current = count(irq);
+
+        {
+            let contexts = context::contexts();
+            let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+            let mut context = context_lock.write();
+
+            if current != seen {
+                context.wait_irq = None;
+                context.status = context::Status::Runnable;
+                return Ok(current);
+            }
+
+            // the scheduler makes the context runnable again when the count changes
+            context.wait_irq = Some((irq, seen));
+            context.status = context::Status::Blocked;
+        }
+
+        unsafe {
+            interrupts::disable();
+            if context::switch() {
+                interrupts::enable_and_nop();
+            } else {
+                // there is nothing else to run, wait for the next interrupt here
+                interrupts::enable_and_halt();
+            }
+        }
+    }
+}
+
+impl Scheme for IrqScheme {
+    fn open(&self, path: &[u8], _flags: usize, uid: u32, _gid: u32) -> Result<usize> {
+        // only root can handle hardware interrupts
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
+        let path_str = str::from_utf8(path).or(Err(Error::new(ENOENT)))?.trim_matches('/');
+        let (irq, source) = match parse_message(path_str) {
+            Some(source) => (open_message(source)?, source),
+            None => {
+                let irq = path_str.parse::<u8>().or(Err(Error::new(ENOENT)))?;
+                if irq as usize >= IRQ_COUNT {
+                    return Err(Error::new(ENOENT));
+                }
+                self.open_legacy(irq)?;
+                (irq, Source::Legacy)
+            }
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, Handle {
+            irq: irq,
+            source: source,
+            acknowledged: count(irq)
+        });
+
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buffer: &mut [u8]) -> Result<usize> {
+        let (irq, acknowledged) = self.state(id)?;
+
+        if buffer.len() < mem::size_of::<usize>() {
+            return Err(Error::new(EINVAL));
+        }
+
+        // wait for an IRQ that wasn't acknowledged yet
+        let current = wait(irq, acknowledged)?;
+
+        unsafe { ptr::write_unaligned(buffer.as_mut_ptr() as *mut usize, current); }
+        Ok(mem::size_of::<usize>())
+    }
+
+    fn write(&self, id: usize, buffer: &[u8]) -> Result<usize> {
+        if buffer.len() < mem::size_of::<usize>() {
+            return Err(Error::new(EINVAL));
+        }
+        let acknowledged = unsafe { ptr::read_unaligned(buffer.as_ptr() as *const usize) };
+
+        let mut handles = self.handles.write();
+        let irq = {
+            let handle = handles.get_mut(&id).ok_or(Error::new(EBADF))?;
+            handle.acknowledged = acknowledged;
+            handle.irq
+        };
+
+        // only unmask if no IRQ fired after the one being acknowledged, on any handle
+        if !irq::is_msi(irq) {
+            unmask_acknowledged(&handles, irq);
+        }
+
+        Ok(mem::size_of::<usize>())
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        let handle = self.handles.write().remove(&id).ok_or(Error::new(EBADF))?;
+        if handle.source != Source::Legacy {
+            close_message(handle.source, handle.irq);
+            return Ok(0);
+        }
+
+        // the last handle removes the kernel handler
+        let remaining = {
+            let mut users = self.users.lock();
+            users[handle.irq as usize] -= 1;
+            if users[handle.irq as usize] == 0 {
+                irq::unregister(handle.irq);
+            }
+            users[handle.irq as usize]
+        };
+
+        // the IRQ may only be waiting for this handle to be acknowledged
+        if remaining > 0 {
+            unmask_acknowledged(&self.handles.read(), handle.irq);
+        }
+
+        Ok(0)
+    }
+}
@@ -0,0 +1,109 @@
+//! # Keyboard scheme
+//!
+//! Delivers the key events of the PS/2 keyboard.
+//!
+//! - `read` on `keyboard:` fills the buffer with as many `KeyEvent` as are available. It blocks
+//!   until there is at least one, unless the handle was opened with `O_NONBLOCK`, then it fails
+//!   with `EAGAIN`.
+//!
+//! All the handles share the same events, each event is only read once, so only root, the console
+//! owner, can open the keyboard.
+
+use arch::device::keyboard::{self, Modifiers};
+use arch::device::ps2::KEYBOARD_IRQ;
+use arch::interrupts::irq;
+use collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{cmp, mem};
+use spin::RwLock;
+
+use syscall::data::KeyEvent;
+use syscall::error::*;
+use syscall::flag::{MODE_FILE, O_NONBLOCK};
+use syscall::scheme::Scheme;
+
+use super::irq::wait;
+use super::{check_permission, open_access};
+
+/// Number of events moved from the keyboard queue at once
+const READ_BATCH: usize = 16;
+
+pub struct KeyboardScheme {
+    next_id: AtomicUsize,
+    /// Flags of each handle
+    handles: RwLock<BTreeMap<usize, usize>>
+}
+
+impl KeyboardScheme {
+    /// Create a new instance of `KeyboardScheme`
+    pub fn new() -> Self {
+        KeyboardScheme {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new())
+        }
+    }
+}
+
+impl Scheme for KeyboardScheme {
+    fn open(&self, path: &[u8], flags: usize, uid: u32, gid: u32) -> Result<usize> {
+        if path.iter().any(|&byte| byte != b'/') {
+            return Err(Error::new(ENOENT));
+        }
+        check_permission(MODE_FILE | 0o600, 0, 0, uid, gid, open_access(flags)?)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, flags);
+
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buffer: &mut [u8]) -> Result<usize> {
+        let flags = *self.handles.read().get(&id).ok_or(Error::new(EBADF))?;
+
+        let size = mem::size_of::<KeyEvent>();
+        let capacity = cmp::min(buffer.len() / size, READ_BATCH);
+        if capacity == 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        let mut events = [keyboard::KeyEvent {
+            scancode: 0,
+            character: '\0',
+            pressed: false,
+            modifiers: Modifiers::empty()
+        }; READ_BATCH];
+
+        let mut count;
+        loop {
+            // take the count before looking at the queue, so a key pressed in between wakes us
+            let seen = irq::count(KEYBOARD_IRQ);
+
+            count = keyboard::read(&mut events[..capacity]);
+            if count > 0 {
+                break;
+            } else if flags & O_NONBLOCK == O_NONBLOCK {
+                return Err(Error::new(EAGAIN));
+            }
+
+            wait(KEYBOARD_IRQ, seen)?;
+        }
+
+        for (event, chunk) in events[..count].iter().zip(buffer.chunks_mut(size)) {
+            let key_event = KeyEvent {
+                scancode: event.scancode,
+                character: event.character as u32,
+                pressed: event.pressed as u8,
+                // the modifiers have the same bits as the `KEY_MOD_*` flags
+                modifiers: event.modifiers.bits()
+            };
+            chunk.copy_from_slice(&key_event);
+        }
+
+        Ok(count * size)
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.handles.write().remove(&id).ok_or(Error::new(EBADF))?;
+        Ok(0)
+    }
+}
@@ -0,0 +1,143 @@
+//! # Log scheme
+//!
+//! Gives access to the kernel log, so a userspace logger can drain it.
+//!
+//! - `read` on `log:` returns the records logged since the last read on the handle, starting at the
+//!   oldest one still on the ring buffer. It returns zero bytes when there is nothing new.
+//! - `write` on `log:` adds the text as an information message, one record per line.
+//!
+//! `log:level` reads the most verbose level kept, like `info`, and writing a level name (root only)
+//! changes it.
+//!
+//! Only root can read `log:`, anyone can write on it.
+
+use arch::klog::{self, Level};
+use collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{cmp, str};
+use spin::RwLock;
+
+use syscall::error::*;
+use syscall::flag::MODE_FILE;
+use syscall::scheme::Scheme;
+
+use super::{check_permission, open_access};
+
+/// What a handle gives access to
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Log,
+    Level
+}
+
+/// An open log
+struct Handle {
+    kind: Kind,
+    uid: u32,
+    /// Position on the log, or on the level name
+    seek: usize
+}
+
+pub struct LogScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Handle>>
+}
+
+impl LogScheme {
+    /// Create a new instance of `LogScheme`
+    pub fn new() -> Self {
+        LogScheme {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new())
+        }
+    }
+
+    /// Get what a handle gives access to, its owner and its position
+    fn state(&self, id: usize) -> Result<(Kind, u32, usize)> {
+        let handles = self.handles.read();
+        let handle = handles.get(&id).ok_or(Error::new(EBADF))?;
+        Ok((handle.kind, handle.uid, handle.seek))
+    }
+
+    /// Change the position of a handle
+    fn set_seek(&self, id: usize, seek: usize) {
+        if let Some(handle) = self.handles.write().get_mut(&id) {
+            handle.seek = seek;
+        }
+    }
+}
+
+impl Scheme for LogScheme {
+    fn open(&self, path: &[u8], flags: usize, uid: u32, gid: u32) -> Result<usize> {
+        let (kind, mode) = match str::from_utf8(path).or(Err(Error::new(ENOENT)))?.trim_matches('/') {
+            "" => (Kind::Log, MODE_FILE | 0o622),
+            "level" => (Kind::Level, MODE_FILE | 0o644),
+            _ => return Err(Error::new(ENOENT))
+        };
+        check_permission(mode, 0, 0, uid, gid, open_access(flags)?)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, Handle {
+            kind: kind,
+            uid: uid,
+            seek: 0
+        });
+
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buffer: &mut [u8]) -> Result<usize> {
+        let (kind, _, seek) = self.state(id)?;
+
+        match kind {
+            Kind::Log => {
+                let (count, seek) = klog::read(seek, buffer);
+                self.set_seek(id, seek);
+                Ok(count)
+            },
+            Kind::Level => {
+                let name = klog::level().name().as_bytes();
+                let start = cmp::min(seek, name.len() + 1);
+
+                // the name followed by a new line
+                let mut count = 0;
+                while count < buffer.len() && start + count <= name.len() {
+                    buffer[count] = name.get(start + count).cloned().unwrap_or(b'\n');
+                    count += 1;
+                }
+
+                self.set_seek(id, start + count);
+                Ok(count)
+            }
+        }
+    }
+
+    fn write(&self, id: usize, buffer: &[u8]) -> Result<usize> {
+        let (kind, uid, _) = self.state(id)?;
+
+        let text = str::from_utf8(buffer).or(Err(Error::new(EINVAL)))?;
+        match kind {
+            Kind::Log => {
+                for line in text.lines().filter(|line| !line.is_empty()) {
+                    klog::log(Level::Info, "user", format_args!("{}", line));
+                }
+            },
+            Kind::Level => {
+                // only root can change what the kernel logs
+                if uid != 0 {
+                    return Err(Error::new(EACCES));
+                }
+
+                let level = Level::from_name(text.trim()).ok_or(Error::new(EINVAL))?;
+                klog::set_level(level);
+            }
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.handles.write().remove(&id).ok_or(Error::new(EBADF))?;
+        Ok(0)
+    }
+}
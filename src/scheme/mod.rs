@@ -9,13 +9,46 @@ use ::core::sync::atomic::AtomicUsize;
 use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use syscall::error::*;
+use syscall::flag::{MODE_EXEC, MODE_PERM, MODE_READ, MODE_WRITE, O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY};
 use syscall::scheme::Scheme;
 
+use self::console::ConsoleScheme;
+use self::fb::FbScheme;
 use self::inifs::InitFsScheme;
+use self::irq::IrqScheme;
+use self::keyboard::KeyboardScheme;
+use self::log::LogScheme;
+use self::pci::PciScheme;
+use self::serial::SerialScheme;
+use self::time::TimeScheme;
+
+/// `console`: the text console
+pub mod console;
+
+/// `fb`: the framebuffer, for a userspace compositor
+pub mod fb;
 
 /// `initfs`: a readonly filesystem used for initializing the system
 pub mod inifs;
 
+/// `irq`: hardware interrupts for userspace drivers
+pub mod irq;
+
+/// `keyboard`: key events of the PS/2 keyboard
+pub mod keyboard;
+
+/// `log`: the kernel log
+pub mod log;
+
+/// `pci`: the PCI functions, for userspace drivers
+pub mod pci;
+
+/// `serial` and `debug`: the serial ports
+pub mod serial;
+
+/// `time`: clocks and timers
+pub mod time;
+
 /// Unique identifier for a file descriptor.
 int_like!(FileHandle, AtomicFileHandle, usize, AtomicUsize);
 
@@ -25,6 +58,53 @@ int_like!(SchemeId, AtomicSchemeId, usize, AtomicUsize);
 /// Unique identifier for a scheme namespace.
 int_like!(SchemeNamespace, AtomicSchemeNamespace, usize, AtomicUsize);
 
+/// Check if the caller can access a file, using POSIX rules.
+///
+/// The owner bits are used when the caller owns the file, otherwise the group bits are used when
+/// the caller is on the file group, otherwise the others bits are used. Root can read and write
+/// anything, but can only execute if some execute bit is set.
+///
+/// ## Parameters
+/// - `mode`: file mode, only the permission bits are used.
+/// - `owner_uid`, `owner_gid`: file owner.
+/// - `uid`, `gid`: effective ids of the caller.
+/// - `access`: combination of `MODE_READ`, `MODE_WRITE` and `MODE_EXEC`.
+///
+/// ## Returns
+/// `EACCES` if the access isn't allowed.
+pub fn check_permission(mode: u16, owner_uid: u32, owner_gid: u32, uid: u32, gid: u32, access: u16) -> Result<()> {
+    let perm = mode & MODE_PERM;
+
+    let allowed = if uid == 0 {
+        access & MODE_EXEC == 0 || perm & (MODE_EXEC << 6 | MODE_EXEC << 3 | MODE_EXEC) != 0
+    } else if uid == owner_uid {
+        (perm >> 6) & access == access
+    } else if gid == owner_gid {
+        (perm >> 3) & access == access
+    } else {
+        perm & access == access
+    };
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(Error::new(EACCES))
+    }
+}
+
+/// Get the access requested by the open flags, as a combination of `MODE_READ` and `MODE_WRITE`.
+///
+/// ## Returns
+/// `EINVAL` if the flags don't have an access mode.
+pub fn open_access(flags: usize) -> Result<u16> {
+    match flags & O_ACCMODE {
+        O_RDONLY => Ok(MODE_READ),
+        O_WRONLY => Ok(MODE_WRITE),
+        O_RDWR => Ok(MODE_READ | MODE_WRITE),
+        _ => Err(Error::new(EINVAL))
+    }
+}
+
 pub struct SchemeList {
     map: BTreeMap<SchemeId, Arc<Box<Scheme + Send + Sync>>>,
     names: BTreeMap<SchemeNamespace, BTreeMap<Box<[u8]>, SchemeId>>,
@@ -59,6 +139,15 @@ impl SchemeList {
 
         // The following namespace must be only available on te root namespace.
         self.insert(ns, Box::new(*b"initfs"), |scheme_id| Arc::new(Box::new(InitFsScheme::new()))).unwrap();
+        self.insert(ns, Box::new(*b"console"), |scheme_id| Arc::new(Box::new(ConsoleScheme::new()))).unwrap();
+        self.insert(ns, Box::new(*b"fb"), |scheme_id| Arc::new(Box::new(FbScheme::new()))).unwrap();
+        self.insert(ns, Box::new(*b"irq"), |scheme_id| Arc::new(Box::new(IrqScheme::new()))).unwrap();
+        self.insert(ns, Box::new(*b"keyboard"), |scheme_id| Arc::new(Box::new(KeyboardScheme::new()))).unwrap();
+        self.insert(ns, Box::new(*b"log"), |scheme_id| Arc::new(Box::new(LogScheme::new()))).unwrap();
+        self.insert(ns, Box::new(*b"pci"), |scheme_id| Arc::new(Box::new(PciScheme::new()))).unwrap();
+        self.insert(ns, Box::new(*b"serial"), |scheme_id| Arc::new(Box::new(SerialScheme::new(false)))).unwrap();
+        self.insert(ns, Box::new(*b"debug"), |scheme_id| Arc::new(Box::new(SerialScheme::new(true)))).unwrap();
+        self.insert(ns, Box::new(*b"time"), |scheme_id| Arc::new(Box::new(TimeScheme::new()))).unwrap();
     }
 
     /// Get an iterator.
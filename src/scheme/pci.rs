@@ -0,0 +1,218 @@
+//! # PCI scheme
+//!
+//! Lets userspace drivers find their hardware on the PCI functions found at boot.
+//!
+//! - `read` on `pci:` lists the functions, one per line: the address as `bus:device.function`, the
+//!   vendor and device ids and the class, subclass and programming interface, like
+//!   `00:1f.2 8086:2922 01.06.01`.
+//! - `read` on `pci:00:1f.2` describes a function: its ids, its legacy IRQ, its decoded BARs and its
+//!   capabilities, one per line.
+//! - `read` on `pci:00:1f.2/config` returns its configuration space, from the position of the
+//!   handle. Only root can read past the standard header, the first 64 bytes. Writing
+//!   `offset value`, two hexadecimal numbers, writes a 32 bits register (root only).
+
+use arch::device::pci::{self, Bar, Device, PciAddress};
+use arch::device::pci::config;
+use collections::{BTreeMap, String};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{cmp, str};
+use spin::RwLock;
+
+use syscall::error::*;
+use syscall::scheme::Scheme;
+
+/// Size of the standard header of the configuration space
+const STANDARD_HEADER_SIZE: usize = 0x40;
+
+/// What a handle gives access to
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    List,
+    Function(PciAddress),
+    Config(PciAddress)
+}
+
+/// An open file of the scheme
+struct Handle {
+    kind: Kind,
+    uid: u32,
+    /// Position on the text, or on the configuration space
+    seek: usize
+}
+
+pub struct PciScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Handle>>
+}
+
+impl PciScheme {
+    /// Create a new instance of `PciScheme`
+    pub fn new() -> Self {
+        PciScheme {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new())
+        }
+    }
+
+    /// Get what a handle gives access to, its owner and its position
+    fn state(&self, id: usize) -> Result<(Kind, u32, usize)> {
+        let handles = self.handles.read();
+        let handle = handles.get(&id).ok_or(Error::new(EBADF))?;
+        Ok((handle.kind, handle.uid, handle.seek))
+    }
+
+    /// Change the position of a handle
+    fn set_seek(&self, id: usize, seek: usize) {
+        if let Some(handle) = self.handles.write().get_mut(&id) {
+            handle.seek = seek;
+        }
+    }
+}
+
+/// Get a function that was found at boot.
+fn function(address: PciAddress) -> Result<&'static Device> {
+    pci::find(address).ok_or(Error::new(ENOENT))
+}
+
+/// Get the list of the functions as text.
+fn list_text() -> Result<String> {
+    let mut text = String::new();
+    for device in pci::devices() {
+        write!(text, "{} {:04x}:{:04x} {:02x}.{:02x}.{:02x}\n", device.address, device.vendor_id,
+               device.device_id, device.class, device.subclass, device.interface)
+            .or(Err(Error::new(EINVAL)))?;
+    }
+    Ok(text)
+}
+
+/// Get the description of a function as text.
+fn function_text(device: &Device) -> Result<String> {
+    let mut text = String::new();
+    describe(&mut text, device).or(Err(Error::new(EINVAL)))?;
+    Ok(text)
+}
+
+/// Write the description of a function, one property per line.
+fn describe(text: &mut String, device: &Device) -> fmt::Result {
+    write!(text, "vendor {:04x}\n", device.vendor_id)?;
+    write!(text, "device {:04x}\n", device.device_id)?;
+    write!(text, "class {:02x}.{:02x}.{:02x}\n", device.class, device.subclass, device.interface)?;
+    write!(text, "revision {:02x}\n", device.revision)?;
+    if device.interrupt_pin != 0 {
+        write!(text, "irq {} pin {}\n", device.interrupt_line, device.interrupt_pin)?;
+    }
+    for (index, bar) in device.bars.iter().enumerate() {
+        if *bar != Bar::None {
+            write!(text, "bar{} {}\n", index, bar)?;
+        }
+    }
+    for capability in device.capabilities.iter() {
+        write!(text, "capability {:02x} {:#x}\n", capability.id, capability.offset)?;
+    }
+    Ok(())
+}
+
+/// Parse a hexadecimal number, with or without the `0x` prefix.
+fn parse_hex(text: &str) -> Option<u32> {
+    let digits = if text.starts_with("0x") { &text[2..] } else { text };
+    u32::from_str_radix(digits, 16).ok()
+}
+
+impl Scheme for PciScheme {
+    fn open(&self, path: &[u8], _flags: usize, uid: u32, _gid: u32) -> Result<usize> {
+        let path = str::from_utf8(path).or(Err(Error::new(ENOENT)))?.trim_matches('/');
+
+        let kind = if path.is_empty() {
+            Kind::List
+        } else {
+            let mut parts = path.splitn(2, '/');
+            let address = parts.next().and_then(PciAddress::parse).ok_or(Error::new(ENOENT))?;
+            function(address)?;
+
+            match parts.next() {
+                None => Kind::Function(address),
+                Some("config") => Kind::Config(address),
+                Some(_) => return Err(Error::new(ENOENT))
+            }
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, Handle {
+            kind: kind,
+            uid: uid,
+            seek: 0
+        });
+
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buffer: &mut [u8]) -> Result<usize> {
+        let (kind, uid, seek) = self.state(id)?;
+
+        let count = match kind {
+            Kind::List | Kind::Function(_) => {
+                let text = match kind {
+                    Kind::Function(address) => function_text(function(address)?)?,
+                    _ => list_text()?
+                };
+
+                let start = cmp::min(seek, text.len());
+                let count = cmp::min(buffer.len(), text.len() - start);
+                buffer[..count].copy_from_slice(&text.as_bytes()[start..start + count]);
+                count
+            },
+            Kind::Config(address) => {
+                // the capabilities past the header may hold device specific registers
+                let size = if uid == 0 {
+                    config::size(address) as usize
+                } else {
+                    cmp::min(config::size(address) as usize, STANDARD_HEADER_SIZE)
+                };
+                let start = cmp::min(seek, size);
+                let count = cmp::min(buffer.len(), size - start);
+
+                for (index, byte) in buffer[..count].iter_mut().enumerate() {
+                    *byte = config::read_u8(address, (start + index) as u16);
+                }
+                count
+            }
+        };
+
+        self.set_seek(id, seek + count);
+        Ok(count)
+    }
+
+    fn write(&self, id: usize, buffer: &[u8]) -> Result<usize> {
+        let (kind, uid, _) = self.state(id)?;
+
+        let address = match kind {
+            Kind::Config(address) => address,
+            _ => return Err(Error::new(EBADF))
+        };
+
+        // a wrong register can hang the machine
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
+        let text = str::from_utf8(buffer).or(Err(Error::new(EINVAL)))?;
+        let mut words = text.split_whitespace().map(parse_hex);
+        let (offset, value) = match (words.next(), words.next(), words.next()) {
+            (Some(Some(offset)), Some(Some(value)), None) => (offset, value),
+            _ => return Err(Error::new(EINVAL))
+        };
+        if offset % 4 != 0 || offset >= config::size(address) as u32 {
+            return Err(Error::new(EINVAL));
+        }
+
+        config::write(address, offset as u16, value);
+
+        Ok(buffer.len())
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.handles.write().remove(&id).ok_or(Error::new(EBADF))?;
+        Ok(0)
+    }
+}
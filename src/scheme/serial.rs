@@ -0,0 +1,186 @@
+//! # Serial scheme
+//!
+//! Gives access to the serial ports. `serial:N` (root only) is the port `N`, numbered from 1, and
+//! `debug:` is COM1, where the kernel also prints its messages. Anyone can write on `debug:`, only
+//! root can read from it.
+//!
+//! - `read` returns the received bytes. It blocks until at least one is available, unless the
+//!   handle was opened with `O_NONBLOCK`, then it fails with `EAGAIN`.
+//! - `write` sends the bytes as they are.
+//!
+//! `serial:N/settings` reads the baud rate and the character format, like `115200 8N1`, and
+//! writing the same format changes them.
+
+use arch::device::serial::{self, LineSettings};
+use arch::interrupts::irq;
+use collections::{BTreeMap, String};
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{cmp, str};
+use spin::RwLock;
+
+use syscall::error::*;
+use syscall::flag::{MODE_FILE, MODE_READ, MODE_WRITE, O_NONBLOCK};
+use syscall::scheme::Scheme;
+
+use super::irq::wait;
+use super::{check_permission, open_access};
+
+/// Number of bytes sent with interrupts disabled, before letting the pending IRQs run
+const WRITE_CHUNK_SIZE: usize = 64;
+
+/// What a handle gives access to
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Data,
+    Settings
+}
+
+/// An open serial port
+struct Handle {
+    /// Port number
+    port: usize,
+    kind: Kind,
+    flags: usize,
+    /// Position on the settings text
+    seek: usize
+}
+
+pub struct SerialScheme {
+    /// `debug:` instead of `serial:`
+    debug: bool,
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Handle>>
+}
+
+impl SerialScheme {
+    /// Create a new instance of `SerialScheme`, for `debug:` when `debug` is set
+    pub fn new(debug: bool) -> Self {
+        SerialScheme {
+            debug: debug,
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new())
+        }
+    }
+
+    /// Get the port of a handle, what it gives access to, its flags and its position
+    fn state(&self, id: usize) -> Result<(usize, Kind, usize, usize)> {
+        let handles = self.handles.read();
+        let handle = handles.get(&id).ok_or(Error::new(EBADF))?;
+        Ok((handle.port, handle.kind, handle.flags, handle.seek))
+    }
+
+    /// Parse the path of a `serial:` handle.
+    fn parse_path(path: &[u8]) -> Result<(usize, Kind)> {
+        let path_str = str::from_utf8(path).or(Err(Error::new(ENOENT)))?.trim_matches('/');
+
+        let mut parts = path_str.splitn(2, '/');
+        let port = parts.next().unwrap_or("").parse::<usize>().or(Err(Error::new(ENOENT)))?;
+        let kind = match parts.next() {
+            None => Kind::Data,
+            Some("settings") => Kind::Settings,
+            Some(_) => return Err(Error::new(ENOENT))
+        };
+
+        if port == 0 || port > serial::PORT_COUNT {
+            return Err(Error::new(ENOENT));
+        }
+
+        Ok((port, kind))
+    }
+}
+
+/// Get the settings of a port as text.
+fn settings_text(port: usize) -> Result<String> {
+    let settings = serial::with_port(port, |port| port.settings()).ok_or(Error::new(ENODEV))?;
+
+    let mut text = String::new();
+    write!(text, "{}\n", settings).or(Err(Error::new(EINVAL)))?;
+    Ok(text)
+}
+
+impl Scheme for SerialScheme {
+    fn open(&self, path: &[u8], flags: usize, uid: u32, gid: u32) -> Result<usize> {
+        let (port, kind, mode) = if self.debug {
+            (1, Kind::Data, MODE_FILE | 0o622)
+        } else {
+            let (port, kind) = SerialScheme::parse_path(path)?;
+            (port, kind, MODE_FILE | 0o600)
+        };
+        check_permission(mode, 0, 0, uid, gid, open_access(flags)?)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, Handle {
+            port: port,
+            kind: kind,
+            flags: flags,
+            seek: 0
+        });
+
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buffer: &mut [u8]) -> Result<usize> {
+        let (port, kind, flags, seek) = self.state(id)?;
+        if open_access(flags)? & MODE_READ != MODE_READ {
+            return Err(Error::new(EBADF));
+        }
+
+        if kind == Kind::Settings {
+            let text = settings_text(port)?;
+            let start = cmp::min(seek, text.len());
+            let count = cmp::min(buffer.len(), text.len() - start);
+            buffer[..count].copy_from_slice(&text.as_bytes()[start..start + count]);
+
+            if let Some(handle) = self.handles.write().get_mut(&id) {
+                handle.seek = start + count;
+            }
+            return Ok(count);
+        }
+
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let port_irq = serial::with_port(port, |port| port.irq()).ok_or(Error::new(ENODEV))?;
+        loop {
+            // take the count before looking at the buffer, so a byte received in between wakes us
+            let seen = irq::count(port_irq);
+
+            let count = serial::with_port(port, |port| port.read(buffer)).unwrap_or(0);
+            if count > 0 {
+                return Ok(count);
+            } else if flags & O_NONBLOCK == O_NONBLOCK {
+                return Err(Error::new(EAGAIN));
+            }
+
+            wait(port_irq, seen)?;
+        }
+    }
+
+    fn write(&self, id: usize, buffer: &[u8]) -> Result<usize> {
+        let (port, kind, flags, _) = self.state(id)?;
+        if open_access(flags)? & MODE_WRITE != MODE_WRITE {
+            return Err(Error::new(EBADF));
+        }
+
+        if kind == Kind::Settings {
+            let settings = LineSettings::parse(buffer).ok_or(Error::new(EINVAL))?;
+            if !serial::with_port(port, |port| port.configure(settings)).unwrap_or(false) {
+                return Err(Error::new(EINVAL));
+            }
+            return Ok(buffer.len());
+        }
+
+        for chunk in buffer.chunks(WRITE_CHUNK_SIZE) {
+            serial::with_port(port, |port| port.write_bytes(chunk)).ok_or(Error::new(ENODEV))?;
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.handles.write().remove(&id).ok_or(Error::new(EBADF))?;
+        Ok(0)
+    }
+}
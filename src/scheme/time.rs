@@ -0,0 +1,124 @@
+//! # Time scheme
+//!
+//! `time:N` gives access to the clock `N`, `CLOCK_MONOTONIC` when it's omitted. Each handle has
+//! its own timer.
+//!
+//! - `read` returns the current time, as a `TimeSpec`. When the timer is set, it first blocks until
+//!   the timer fires, and then clears it.
+//! - `write`, with a `TimeSpec`, sets the timer to fire when the clock reaches that time.
+//!
+//! Anyone can use the clocks.
+//!
+//! There is no readiness notification: the only way to know that a timer fired is the blocking
+//! `read`, so a thread can only wait on one timer at a time. Waiting on several timers takes one
+//! thread per timer.
+
+use collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{mem, ptr, str};
+use spin::RwLock;
+
+use syscall::data::TimeSpec;
+use syscall::error::*;
+use syscall::flag::{CLOCK_MONOTONIC, CLOCK_REALTIME, MODE_FILE};
+use syscall::scheme::Scheme;
+use syscall::time::{add_time, clock_time, from_time_spec, sleep_until, sub_time, to_time_spec};
+
+use super::{check_permission, open_access};
+
+/// An open clock
+struct Handle {
+    clock: usize,
+    /// Time, on the handle clock, when the timer fires
+    timer: Option<(u64, u64)>
+}
+
+pub struct TimeScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Handle>>
+}
+
+impl TimeScheme {
+    /// Create a new instance of `TimeScheme`
+    pub fn new() -> Self {
+        TimeScheme {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new())
+        }
+    }
+
+    /// Get the clock of a handle and its timer
+    fn state(&self, id: usize) -> Result<(usize, Option<(u64, u64)>)> {
+        let handles = self.handles.read();
+        let handle = handles.get(&id).ok_or(Error::new(EBADF))?;
+        Ok((handle.clock, handle.timer))
+    }
+}
+
+impl Scheme for TimeScheme {
+    fn open(&self, path: &[u8], flags: usize, uid: u32, gid: u32) -> Result<usize> {
+        let path_str = str::from_utf8(path).or(Err(Error::new(ENOENT)))?.trim_matches('/');
+
+        let clock = if path_str.is_empty() {
+            CLOCK_MONOTONIC
+        } else {
+            path_str.parse::<usize>().or(Err(Error::new(ENOENT)))?
+        };
+        if clock != CLOCK_MONOTONIC && clock != CLOCK_REALTIME {
+            return Err(Error::new(ENOENT));
+        }
+        check_permission(MODE_FILE | 0o666, 0, 0, uid, gid, open_access(flags)?)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, Handle {
+            clock: clock,
+            timer: None
+        });
+
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buffer: &mut [u8]) -> Result<usize> {
+        let (clock, timer) = self.state(id)?;
+
+        if buffer.len() < mem::size_of::<TimeSpec>() {
+            return Err(Error::new(EINVAL));
+        }
+
+        if let Some(timer) = timer {
+            // contexts only sleep on the monotonic clock
+            let remaining = sub_time(timer, clock_time(clock)?);
+            sleep_until(add_time(clock_time(CLOCK_MONOTONIC)?, remaining))?;
+
+            if let Some(handle) = self.handles.write().get_mut(&id) {
+                if handle.timer == Some(timer) {
+                    handle.timer = None;
+                }
+            }
+        }
+
+        let time_spec = to_time_spec(clock_time(clock)?);
+        buffer[..mem::size_of::<TimeSpec>()].copy_from_slice(&time_spec);
+        Ok(mem::size_of::<TimeSpec>())
+    }
+
+    fn write(&self, id: usize, buffer: &[u8]) -> Result<usize> {
+        if buffer.len() < mem::size_of::<TimeSpec>() {
+            return Err(Error::new(EINVAL));
+        }
+
+        let time_spec = unsafe { ptr::read_unaligned(buffer.as_ptr() as *const TimeSpec) };
+        let timer = from_time_spec(&time_spec)?;
+
+        let mut handles = self.handles.write();
+        let handle = handles.get_mut(&id).ok_or(Error::new(EBADF))?;
+        handle.timer = Some(timer);
+
+        Ok(mem::size_of::<TimeSpec>())
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.handles.write().remove(&id).ok_or(Error::new(EBADF))?;
+        Ok(0)
+    }
+}
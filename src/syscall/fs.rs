@@ -49,6 +49,34 @@ pub fn file_open_mut_slice(a: usize, fd: FileHandle, slice: &mut [u8]) -> Result
     file_open(a, fd, slice.as_mut_ptr() as usize, slice.len())
 }
 
+pub fn file_open_slice(a: usize, fd: FileHandle, slice: &[u8]) -> Result<usize> {
+    file_open(a, fd, slice.as_ptr() as usize, slice.len())
+}
+
+/// Read from a file.
+///
+/// ## Returns
+/// The number of bytes read.
+pub fn read(fd: FileHandle, buffer: &mut [u8]) -> Result<usize> {
+    file_open_mut_slice(syscall::number::SYS_READ, fd, buffer)
+}
+
+/// Write to a file.
+///
+/// ## Returns
+/// The number of bytes written.
+pub fn write(fd: FileHandle, buffer: &[u8]) -> Result<usize> {
+    file_open_slice(syscall::number::SYS_WRITE, fd, buffer)
+}
+
+/// Map a file on the address space of the current context.
+///
+/// ## Returns
+/// The address of the mapping.
+pub fn fmap(fd: FileHandle, offset: usize, size: usize) -> Result<usize> {
+    file_open(syscall::number::SYS_FMAP, fd, offset, size)
+}
+
 /// Change the current work directory
 ///
 /// ## Parameters
@@ -64,8 +92,8 @@ pub fn chdir(path: &[u8]) -> Result<usize> {
     let mut stat = Stat::default();
     let stat_res = file_open_mut_slice(syscall::number::SYS_FSTAT, fd, &mut stat);
 
-    // TODO close the file descriptor (implement syscall close)
-    // let _ = close(fd);
+    // close the file descriptor
+    let _ = close(fd);
 
     // handle the response status
     stat_res?;
@@ -128,6 +156,22 @@ pub fn open(path: &[u8], flags: usize) -> Result<FileHandle> {
     context.add_file(::context::File {
         scheme: scheme_id,
         number: file_id,
+        flags: flags,
         event: None
     }).ok_or(Error::new(EMFILE))
 }
+
+/// Close system call.
+///
+/// ## Parameters
+/// - `fd`: file descriptor to be closed.
+pub fn close(fd: FileHandle) -> Result<usize> {
+    let file = {
+        let contexts = context::contexts();
+        let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+        let context = context_lock.read();
+        context.remove_file(fd).ok_or(Error::new(EBADF))?
+    };
+
+    file.close()
+}
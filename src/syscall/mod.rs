@@ -8,6 +8,7 @@ pub use self::syscall::{error, scheme, flag, data, number};
 // export everything
 pub use self::fs::*;
 pub use self::process::*;
+pub use self::time::*;
 
 use self::error::{Error, Result};
 
@@ -17,4 +18,10 @@ pub mod fs;
 /// Process syscalls
 pub mod process;
 
+/// Time syscalls
+pub mod time;
+
+/// Validation of the user memory given to the syscalls
+pub mod validate;
+
 
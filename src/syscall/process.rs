@@ -3,7 +3,7 @@
 use alloc::arc::Arc;
 use alloc::boxed::Box;
 use collections::Vec;
-use core::{intrinsics, mem, str};
+use core::{cmp, intrinsics, mem, str};
 use spin::Mutex;
 
 use arch::usermode;
@@ -16,142 +16,665 @@ use scheme::{self, FileHandle};
 use syscall;
 use syscall::data::{Stat, Packet};
 use syscall::error::*;
+use syscall::validate::validate_slice;
+use syscall::flag::{AT_NULL, AT_PHDR, AT_PHENT, AT_PHNUM, AT_PAGESZ, AT_BASE, AT_ENTRY};
+use syscall::flag::{MODE_TYPE, MODE_FILE, MODE_EXEC, MODE_SETUID, MODE_SETGID, O_CLOEXEC};
 
-// TODO implement Drop to close the file descriptor
-/// Represents a executable file
+/// Maximum space that arguments and environment can use on the user stack.
+const EXEC_ARGS_MAX: usize = ::USER_STACK_SIZE / 4;
+
+/// Maximum number of nested script interpreters.
+const EXEC_SHEBANG_MAX: usize = 4;
+
+/// Maximum length of a shebang line.
+const EXEC_SHEBANG_LEN: usize = 128;
+
+/// Represents a executable file, that is closed when dropped
 struct ExecFile(FileHandle);
 
+impl Drop for ExecFile {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.0);
+    }
+}
+
+/// Get the space that strings of the given lengths use on the initial stack: the string, its NUL
+/// and its pointer.
+fn strings_size<I: Iterator<Item = usize>>(lengths: I) -> usize {
+    lengths.fold(0, |size: usize, len| size.saturating_add(len).saturating_add(1 + mem::size_of::<usize>()))
+}
+
+/// Copy a list of `[pointer, length]` pairs, from the caller memory, into kernel owned strings.
+///
+/// Each string must be in user memory.
+fn copy_strings(ptrs: &[[usize; 2]]) -> Result<Vec<Box<[u8]>>> {
+    let mut strings = Vec::with_capacity(ptrs.len());
+    for ptr in ptrs.iter() {
+        let string = validate_slice(ptr[0] as *const u8, ptr[1])?;
+        strings.push(string.to_vec().into_boxed_slice());
+    }
+    Ok(strings)
+}
+
+/// Push a value into a stack.
+unsafe fn push(sp: &mut usize, value: usize) {
+    *sp -= mem::size_of::<usize>();
+    *(*sp as *mut usize) = value;
+}
+
+/// Copy the strings to the top of a stack, each one terminated by a NUL.
+///
+/// ## Returns
+/// The address of each one of the strings.
+unsafe fn push_strings(sp: &mut usize, strings: &[Box<[u8]>]) -> Vec<usize> {
+    let mut addresses = Vec::with_capacity(strings.len());
+
+    for string in strings.iter() {
+        *sp -= string.len() + 1;
+        intrinsics::copy(string.as_ptr(), *sp as *mut u8, string.len());
+        *((*sp + string.len()) as *mut u8) = 0;
+        addresses.push(*sp);
+    }
+
+    addresses
+}
+
+/// Build a System V initial process stack, at `top`, with the following layout (growing down):
+///
+/// ```text
+/// argv and envp strings
+/// padding (16 bytes alignment)
+/// auxv pairs, terminated by AT_NULL
+/// envp pointers, terminated by NULL
+/// argv pointers, terminated by NULL
+/// argc                                <- returned stack pointer
+/// ```
+unsafe fn push_initial_stack(top: usize, args: &[Box<[u8]>], vars: &[Box<[u8]>], auxv: &[(usize, usize)]) -> usize {
+    let mut sp = top;
+
+    // copy the strings
+    let arg_addresses = push_strings(&mut sp, args);
+    let var_addresses = push_strings(&mut sp, vars);
+
+    // the stack pointer must be 16 bytes aligned when pointing to argc
+    sp &= !0xF;
+    let words = (auxv.len() + 1) * 2 + var_addresses.len() + 1 + arg_addresses.len() + 1 + 1;
+    if words % 2 == 1 {
+        sp -= mem::size_of::<usize>();
+    }
+
+    // auxiliary vector
+    push(&mut sp, 0);
+    push(&mut sp, AT_NULL);
+    for &(kind, value) in auxv.iter().rev() {
+        push(&mut sp, value);
+        push(&mut sp, kind);
+    }
+
+    // environment pointers
+    push(&mut sp, 0);
+    for &address in var_addresses.iter().rev() {
+        push(&mut sp, address);
+    }
+
+    // argument pointers
+    push(&mut sp, 0);
+    for &address in arg_addresses.iter().rev() {
+        push(&mut sp, address);
+    }
+
+    // argument count
+    push(&mut sp, args.len());
+
+    sp
+}
+
+/// Read the whole content of an executable file.
+fn read_exec_file(path: &[u8]) -> Result<(Stat, Vec<u8>)> {
+    // open the executable file
+    let file = ExecFile(syscall::open(path, syscall::flag::O_RDONLY)?);
+
+    // get the file stats
+    let mut stat = Stat::default();
+    syscall::file_open_mut_slice(syscall::number::SYS_FSTAT, file.0, &mut stat)?;
+
+    // get the file content
+    let mut data = vec![0; stat.st_size as usize];
+    syscall::file_open_mut_slice(syscall::number::SYS_READ, file.0, &mut data)?;
+
+    Ok((stat, data))
+}
+
+/// Check if the file is a regular file that can be executed with the given effective ids.
+fn check_exec_permission(stat: &Stat, uid: u32, gid: u32) -> Result<()> {
+    if stat.st_mode & MODE_TYPE != MODE_FILE {
+        return Err(Error::new(EACCES));
+    }
+
+    scheme::check_permission(stat.st_mode, stat.st_uid, stat.st_gid, uid, gid, MODE_EXEC)
+}
+
+/// Parse the first line of a script, in the form `#!interpreter [arg]`.
+///
+/// ## Returns
+/// The interpreter path and the optional argument, or `None` if there is no interpreter or the line
+/// (with its newline) is longer than `EXEC_SHEBANG_LEN`.
+fn parse_shebang(data: &[u8]) -> Option<(Box<[u8]>, Option<Box<[u8]>>)> {
+    let is_space = |b: &u8| *b == b' ' || *b == b'\t';
+
+    let line = &data[2..cmp::min(data.len(), EXEC_SHEBANG_LEN)];
+    let line = match line.iter().position(|&b| b == b'\n') {
+        Some(end) => &line[..end],
+        // the line doesn't fit, it would be truncated
+        None if data.len() > EXEC_SHEBANG_LEN => return None,
+        None => line
+    };
+
+    // trim the line
+    let line = match (line.iter().position(|b| !is_space(b)), line.iter().rposition(|b| !is_space(b))) {
+        (Some(start), Some(end)) => &line[start..end + 1],
+        _ => return None
+    };
+
+    // the argument is everything after the interpreter path
+    match line.iter().position(|b| is_space(b)) {
+        Some(i) => {
+            let arg = &line[i..];
+            let arg = &arg[arg.iter().position(|b| !is_space(b)).unwrap_or(0)..];
+            Some((line[..i].to_vec().into_boxed_slice(), Some(arg.to_vec().into_boxed_slice())))
+        },
+        None => Some((line.to_vec().into_boxed_slice(), None))
+    }
+}
+
+/// Parse an ELF executable, reporting why it was rejected.
+fn parse_elf<'a>(path: &[u8], data: &'a [u8]) -> Result<elf::Elf<'a>> {
+    elf::Elf::from(data).map_err(|err| {
+        kwarn!("failed to execute {}: {}", unsafe { str::from_utf8_unchecked(path) }, err);
+        Error::new(ENOEXEC)
+    })
+}
+
+/// Choose the load base for a position independent image, starting at `offset`.
+///
+/// With the `aslr` feature a random offset, aligned to 2 MiB to respect the segments alignment, is
+/// added to the base. The offset only uses the space left after the image, so the image stays
+/// inside of its `USER_DYN_SIZE` area.
+fn load_base(offset: usize, elf: &elf::Elf) -> usize {
+    const ASLR_ALIGN: usize = 0x20_0000;
+
+    if cfg!(feature = "aslr") {
+        // the segments were checked to end inside of `USER_DYN_SIZE`
+        let span = elf.segments()
+            .filter(|segment| segment.p_type == program_header::PT_LOAD)
+            .map(|segment| (segment.p_vaddr + segment.p_memsz) as usize)
+            .max()
+            .unwrap_or(0);
+        let slots = (::USER_DYN_SIZE - span) / ASLR_ALIGN + 1;
+
+        offset + (::arch::time::tsc() as usize % slots) * ASLR_ALIGN
+    } else {
+        offset
+    }
+}
+
+/// Get the `R_X86_64_RELATIVE` relocations of an image, which the kernel applies when loading it.
+///
+/// The remaining relocation types are left to the dynamic linker, unless `strict` is set, in which
+/// case they are rejected.
+fn image_relocations(elf: &elf::Elf, strict: bool) -> Result<Vec<elf::Relocation>> {
+    if !elf.is_dynamic() {
+        return Ok(Vec::new());
+    }
+
+    let mut relocations = elf.relocations().map_err(|err| {
+        kwarn!("failed to relocate image: {}", err);
+        Error::new(ENOEXEC)
+    })?;
+
+    if strict {
+        if let Some(relocation) = relocations.iter().find(|relocation| relocation.kind != elf::R_X86_64_RELATIVE) {
+            kwarn!("failed to relocate image: unsupported relocation type {}", relocation.kind);
+            return Err(Error::new(ENOEXEC));
+        }
+    }
+    relocations.retain(|relocation| relocation.kind == elf::R_X86_64_RELATIVE);
+
+    Ok(relocations)
+}
+
+/// Map the loadable segments of an image at `base`, and apply the relocations given by
+/// `image_relocations`.
+///
+/// This can't fail, so the image must be completely validated before replacing the caller memory.
+fn load_image(context: &mut context::Context, elf: &elf::Elf, base: usize, relocations: &[elf::Relocation]) {
+    let mut segments = Vec::new();
+
+    for segment in elf.segments() {
+        if segment.p_type == program_header::PT_LOAD {
+            let memory = context::memory::Memory::new(
+                (base + segment.p_vaddr as usize) as VirtualAddress,
+                segment.p_memsz as usize,
+                entry::NO_EXECUTE | entry::WRITABLE,
+                true
+            );
+
+            // Copy file data
+            unsafe {
+                intrinsics::copy((elf.data.as_ptr() as usize + segment.p_offset as usize) as *const u8,
+                                 (base + segment.p_vaddr as usize) as *mut u8,
+                                 segment.p_filesz as usize);
+            }
+
+            let mut flags = entry::NO_EXECUTE | entry::USER_ACCESSIBLE;
+
+            if segment.p_flags & program_header::PF_R == program_header::PF_R {
+                flags.insert(entry::PRESENT);
+            }
+
+            // W ^ X. If it is executable, do not allow it to be writable, even if requested
+            if segment.p_flags & program_header::PF_X == program_header::PF_X {
+                flags.remove(entry::NO_EXECUTE);
+            } else if segment.p_flags & program_header::PF_W == program_header::PF_W {
+                flags.insert(entry::WRITABLE);
+            }
+
+            segments.push((memory, flags));
+        }
+    }
+
+    // Relocate while all the segments are still writable
+    for relocation in relocations.iter() {
+        unsafe {
+            *((base + relocation.offset as usize) as *mut usize) = base + relocation.addend as usize;
+        }
+    }
+
+    // Set the final permissions
+    for (mut memory, flags) in segments.into_iter() {
+        memory.remap(flags);
+        context.image.push(memory.to_shared());
+    }
+}
+
 /// Replaces the current process image with a new process image.
 ///
 /// ## Parameters
 /// - `path`: name of a file that is to be executed.
-/// - `arg_ptrs`: list of arguments.
+/// - `arg_ptrs`: list of arguments, as `[pointer, length]` pairs.
+/// - `var_ptrs`: list of environment variables, as `[pointer, length]` pairs.
 ///
 /// ## Returns
 /// Only returns if an error has occurred.
-pub fn exec(path: &[u8], arg_ptrs: &[[usize; 2]]) -> Result<usize> {
+pub fn exec(path: &[u8], arg_ptrs: &[[usize; 2]], var_ptrs: &[[usize; 2]]) -> Result<usize> {
     let entry;
-    let mut sp = ::USER_STACK_OFFSET + ::USER_STACK_SIZE - 256;
+    let fs_base;
+    let mut sp = ::USER_STACK_OFFSET + ::USER_STACK_SIZE;
 
     {
-        // TODO: handle the arguments
+        // check the size given by the caller before copying anything
+        let caller_size = strings_size(Some(path.len()).into_iter()
+            .chain(arg_ptrs.iter().chain(var_ptrs.iter()).map(|ptr| ptr[1])));
+        if caller_size > EXEC_ARGS_MAX {
+            return Err(Error::new(E2BIG));
+        }
+
+        // Copy the arguments and the environment, since the caller memory is going to be replaced.
+        // The first argument is always the path to the executable.
+        let mut args = Vec::with_capacity(arg_ptrs.len() + 1);
+        args.push(path.to_vec().into_boxed_slice());
+        args.extend(copy_strings(arg_ptrs)?);
+        let vars = copy_strings(var_ptrs)?;
 
         // get uid, gid and the canonical path to the exec
-        let (uid, gid, mut canonical) = {
+        let (uid, gid, canonical) = {
             let contexts = context::contexts();
             let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
             let context = context_lock.read();
             (context.euid, context.egid, context.canonicalize(path))
         };
 
-        let mut stat: Stat;
-        let mut data: Vec<u8>;
+        // get the file content
+        let mut exec_path = canonical.clone();
+        let (mut stat, mut data) = read_exec_file(&exec_path)?;
+        check_exec_permission(&stat, uid, gid)?;
 
-        // open the executable file
-        let file = ExecFile(syscall::open(&canonical, syscall::flag::O_RDONLY)?);
+        // Scripts are executed by the interpreter named on the shebang, which receives the script
+        // path (already on the first argument) after its own path and optional argument.
+        let mut shebang_depth = 0;
+        while data.starts_with(b"#!") {
+            if shebang_depth >= EXEC_SHEBANG_MAX {
+                return Err(Error::new(ELOOP));
+            }
+            shebang_depth += 1;
 
-        // get the file stats
-        stat = Stat::default();
-        syscall::file_open_mut_slice(syscall::number::SYS_FSTAT, file.0, &mut stat)?;
+            let (interpreter, arg_option) = parse_shebang(&data).ok_or(Error::new(ENOEXEC))?;
+            if let Some(arg) = arg_option {
+                args.insert(0, arg);
+            }
 
-        // TODO check permissions when we implement it
+            // the interpreter path is resolved like the path given by the caller
+            exec_path = {
+                let contexts = context::contexts();
+                let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+                let context = context_lock.read();
+                context.canonicalize(&interpreter)
+            };
+            args.insert(0, interpreter);
 
-        // get the file content
-        data = vec![0; stat.st_size as usize];
-        syscall::file_open_mut_slice(syscall::number::SYS_READ, file.0, &mut data)?;
-        // TODO drop file when implemented
+            let file = read_exec_file(&exec_path)?;
+            check_exec_permission(&file.0, uid, gid)?;
+            stat = file.0;
+            data = file.1;
+        }
 
-        // TODO add support for a shebang
+        // check again with the arguments added by the shebangs
+        let args_size = strings_size(args.iter().chain(vars.iter()).map(|string| string.len()));
+        if args_size > EXEC_ARGS_MAX {
+            return Err(Error::new(E2BIG));
+        }
 
         // Read ELF sections
-        match elf::Elf::from(&data) {
-            Ok (elf) => {
-                // read ELF sections
-                entry = elf.entry();
+        let elf = parse_elf(&exec_path, &data)?;
 
-                // TODO drop path
+        // Read the program interpreter (dynamic linker), when requested. Paths without a scheme are
+        // looked up on initfs.
+        let interp_data = match elf.interpreter() {
+            Some(interp) => {
+                let mut interp_path = Vec::new();
+                if !interp.contains(&b':') {
+                    interp_path.extend_from_slice(b"initfs:");
+                }
+                interp_path.extend_from_slice(interp);
 
-                // get all contexts
-                let contexts = context::contexts();
-                let ppid = {
-                    // get current context, mutable
-                    let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
-                    let mut context = context_lock.write();
-
-                    // Set the new name
-                    context.name = Arc::new(Mutex::new(canonical));
-
-                    // TODO clear context
-
-                    // TODO set context uid and egid
-
-                    // Map and copy new segments
-                    for segment in elf.segments() {
-                        // TODO add support for TLS sections
-                        if segment.p_type == program_header::PT_LOAD {
-                            let mut memory = context::memory::Memory::new(
-                                segment.p_vaddr as VirtualAddress,
-                                segment.p_memsz as usize,
-                                entry::NO_EXECUTE | entry::WRITABLE,
-                                true
-                            );
-
-                            unsafe {
-                                // Copy file data
-                                intrinsics::copy((elf.data.as_ptr() as usize + segment.p_offset as usize) as *const u8,
-                                                segment.p_vaddr as *mut u8,
-                                                segment.p_filesz as usize);
-
-                                let mut flags = entry::NO_EXECUTE | entry::USER_ACCESSIBLE;
-
-                                if segment.p_flags & program_header::PF_R == program_header::PF_R {
-                                    flags.insert(entry::PRESENT);
-                                }
-
-                                // W ^ X. If it is executable, do not allow it to be writable, even if requested
-                                if segment.p_flags & program_header::PF_X == program_header::PF_X {
-                                    flags.remove(entry::NO_EXECUTE);
-                                } else if segment.p_flags & program_header::PF_W == program_header::PF_W {
-                                    flags.insert(entry::WRITABLE);
-                                }
-
-                                memory.remap(flags);
-
-                                context.image.push(memory.to_shared());
-                            }
-                        }
-                    }
-
-                    // Map heap
-                    context.heap = Some(context::memory::Memory::new(
-                        ::USER_HEAP_OFFSET as VirtualAddress,
-                        0,
-                        entry::NO_EXECUTE | entry::WRITABLE | entry::USER_ACCESSIBLE,
-                        true
-                    ).to_shared());
-
-                    // Map stack
-                    context.stack = Some(context::memory::Memory::new(
-                        ::USER_STACK_OFFSET as VirtualAddress,
-                        ::USER_STACK_SIZE,
-                        entry::NO_EXECUTE | entry::WRITABLE | entry::USER_ACCESSIBLE,
-                        true
-                    ));
-
-                    // TODO map TLS
-
-                    // TODO push arguments to stack
-
-                    context.ppid
-                };
+                let (interp_stat, interp_data) = read_exec_file(&interp_path)?;
+                check_exec_permission(&interp_stat, uid, gid)?;
+                Some(interp_data)
+            },
+            None => None
+        };
+        let interp_option = match interp_data {
+            Some(ref interp_data) => {
+                let interp = parse_elf(elf.interpreter().unwrap_or(b""), interp_data)?;
+
+                // the interpreter must be relocatable and can't request another interpreter
+                if !interp.is_dynamic() || interp.interpreter().is_some() {
+                    return Err(Error::new(ENOEXEC));
+                }
+
+                Some(interp)
+            },
+            None => None
+        };
+
+        // Everything that can fail must be checked before the caller memory is replaced
+        let relocations = image_relocations(&elf, interp_option.is_none())?;
+        let interp_relocations = match interp_option {
+            Some(ref interp) => image_relocations(interp, false)?,
+            None => Vec::new()
+        };
+
+        // Lay out the TLS block. The x86_64 ABI places it right below the TCB, where the FS segment
+        // points to (see `init_tcb` for the kernel equivalent).
+        let (tdata_offset, tdata_size, tls_start, tls_size) = {
+            let tls_option = elf.segments().find(|segment| segment.p_type == program_header::PT_TLS);
+            let (tdata_offset, tdata_size, tls_size, tls_align) = match tls_option {
+                Some(segment) => (segment.p_offset as usize, segment.p_filesz as usize,
+                                  segment.p_memsz as usize, segment.p_align as usize),
+                None => (0, 0, 0, 1)
+            };
+
+            // round the block size to its alignment, so the TCB ends up correctly aligned
+            let align = cmp::max(tls_align, mem::size_of::<usize>());
+            let size = tls_size.checked_add(align - 1).ok_or(Error::new(ENOEXEC))? / align * align;
+            let start = ::arch::USER_TCB_OFFSET.checked_sub(size).ok_or(Error::new(ENOEXEC))?;
+
+            (tdata_offset, tdata_size, start, size)
+        };
+
+        // Honor the setuid and setgid bits of the executable. The credentials are only applied
+        // once the new image is in place.
+        let euid = if stat.st_mode & MODE_SETUID == MODE_SETUID { stat.st_uid } else { uid };
+        let egid = if stat.st_mode & MODE_SETGID == MODE_SETGID { stat.st_gid } else { gid };
+
+        // TODO drop path
+
+        // get all contexts
+        let contexts = context::contexts();
+
+        // get current context, mutable
+        let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+        let mut context = context_lock.write();
+
+        // Set the new name
+        context.name = Arc::new(Mutex::new(canonical));
+
+        // TODO clear context
+
+        // the grants belong to the old image
+        context::memory::unmap_grants(mem::replace(&mut context.grants, Arc::new(Mutex::new(Vec::new()))));
+
+        // Map and copy new segments. Position independent executables are placed on their own area.
+        let base = if elf.is_dynamic() { load_base(::USER_PIE_OFFSET, &elf) } else { 0 };
+        load_image(&mut context, &elf, base, &relocations);
+
+        let mut auxv = vec![
+            (AT_ENTRY, base + elf.entry()),
+            (AT_PAGESZ, ::arch::memory::PAGE_SIZE),
+            (AT_PHENT, elf.program_header_size()),
+            (AT_PHNUM, elf.program_header_count())
+        ];
+        if let Some(phdr) = elf.program_headers_address() {
+            auxv.push((AT_PHDR, base + phdr));
+        }
+
+        // When there is an interpreter, it runs first and is responsible for loading the program
+        entry = match interp_option {
+            Some(ref interp) => {
+                let interp_base = load_base(::USER_INTERP_OFFSET, interp);
+                load_image(&mut context, interp, interp_base, &interp_relocations);
+                auxv.push((AT_BASE, interp_base));
+
+                interp_base + interp.entry()
             },
-            Err(err) => {
-                println!("failed to execute {}: {}", unsafe { str::from_utf8_unchecked(path) }, err);
-                return Err(Error::new(ENOEXEC));
+            None => base + elf.entry()
+        };
+
+        // Map heap
+        context.heap = Some(context::memory::Memory::new(
+            ::USER_HEAP_OFFSET as VirtualAddress,
+            0,
+            entry::NO_EXECUTE | entry::WRITABLE | entry::USER_ACCESSIBLE,
+            true
+        ).to_shared());
+
+        // Map stack
+        context.stack = Some(context::memory::Memory::new(
+            ::USER_STACK_OFFSET as VirtualAddress,
+            ::USER_STACK_SIZE,
+            entry::NO_EXECUTE | entry::WRITABLE | entry::USER_ACCESSIBLE,
+            true
+        ));
+
+        // Map TLS
+        {
+            let tcb = ::arch::USER_TCB_OFFSET;
+
+            // map the TLS block and the TCB, zeroing .tbss
+            let memory = context::memory::Memory::new(
+                tls_start as VirtualAddress,
+                tls_size + mem::size_of::<usize>(),
+                entry::NO_EXECUTE | entry::WRITABLE | entry::USER_ACCESSIBLE,
+                true
+            );
+
+            unsafe {
+                // copy .tdata
+                intrinsics::copy((elf.data.as_ptr() as usize + tdata_offset) as *const u8,
+                                 tls_start as *mut u8,
+                                 tdata_size);
+
+                // set the TCB self pointer
+                *(tcb as *mut usize) = tcb;
             }
+
+            fs_base = tcb;
+            context.tls = Some(memory);
+        }
+
+        // Set the new credentials
+        context.euid = euid;
+        context.egid = egid;
+        context.suid = euid;
+        context.sgid = egid;
+
+        // Push arguments, environment and the auxiliary vector to the stack
+        sp = unsafe { push_initial_stack(sp, &args, &vars, &auxv) };
+
+        // Close the files marked as close-on-exec
+        let cloexec_files = {
+            let mut files = context.files.lock();
+            files.iter_mut()
+                .filter(|file_option| file_option.map_or(false, |file| file.flags & O_CLOEXEC == O_CLOEXEC))
+                .filter_map(|file_option| file_option.take())
+                .collect::<Vec<_>>()
+        };
+        for file in cloexec_files.into_iter() {
+            let _ = file.close();
         }
     }
 
     // TODO go to userland
-    unsafe { usermode(entry, sp); }
+    unsafe { usermode(entry, sp, fs_base); }
+}
+
+/// Get the real user id of the current context.
+pub fn getuid() -> Result<usize> {
+    let contexts = context::contexts();
+    let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+    let context = context_lock.read();
+    Ok(context.ruid as usize)
+}
+
+/// Get the effective user id of the current context.
+pub fn geteuid() -> Result<usize> {
+    let contexts = context::contexts();
+    let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+    let context = context_lock.read();
+    Ok(context.euid as usize)
+}
+
+/// Get the real group id of the current context.
+pub fn getgid() -> Result<usize> {
+    let contexts = context::contexts();
+    let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+    let context = context_lock.read();
+    Ok(context.rgid as usize)
+}
+
+/// Get the effective group id of the current context.
+pub fn getegid() -> Result<usize> {
+    let contexts = context::contexts();
+    let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+    let context = context_lock.read();
+    Ok(context.egid as usize)
+}
+
+/// Convert a syscall argument into an user or group id.
+fn to_id(id: usize) -> Result<u32> {
+    if id > u32::max_value() as usize {
+        Err(Error::new(EINVAL))
+    } else {
+        Ok(id as u32)
+    }
+}
+
+/// Set the user id of the current context.
+///
+/// A privileged context sets the real, effective and saved user ids. Otherwise only the effective
+/// user id can be changed, and only to the real or saved user id.
+pub fn setuid(uid: usize) -> Result<usize> {
+    let uid = to_id(uid)?;
+
+    let contexts = context::contexts();
+    let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+    let mut context = context_lock.write();
+
+    if context.euid == 0 {
+        context.ruid = uid;
+        context.euid = uid;
+        context.suid = uid;
+    } else if uid == context.ruid || uid == context.suid {
+        context.euid = uid;
+    } else {
+        return Err(Error::new(EPERM));
+    }
+
+    Ok(0)
+}
+
+/// Set the group id of the current context.
+///
+/// A privileged context sets the real, effective and saved group ids. Otherwise only the
+/// effective group id can be changed, and only to the real or saved group id.
+pub fn setgid(gid: usize) -> Result<usize> {
+    let gid = to_id(gid)?;
+
+    let contexts = context::contexts();
+    let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+    let mut context = context_lock.write();
+
+    if context.euid == 0 {
+        context.rgid = gid;
+        context.egid = gid;
+        context.sgid = gid;
+    } else if gid == context.rgid || gid == context.sgid {
+        context.egid = gid;
+    } else {
+        return Err(Error::new(EPERM));
+    }
+
+    Ok(0)
+}
+
+/// Set the real and effective user ids of the current context.
+///
+/// Passing `usize::MAX` (`-1`) leaves the respective id unchanged. An unprivileged context can
+/// only swap between its real, effective and saved user ids. When the real user id is set, or the
+/// effective one is set to a value other than the previous real user id, the saved user id becomes
+/// the new effective user id.
+pub fn setreuid(ruid: usize, euid: usize) -> Result<usize> {
+    let new_ruid = if ruid == usize::max_value() { None } else { Some(to_id(ruid)?) };
+    let new_euid = if euid == usize::max_value() { None } else { Some(to_id(euid)?) };
+
+    let contexts = context::contexts();
+    let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+    let mut context = context_lock.write();
+
+    if context.euid != 0 {
+        if let Some(ruid) = new_ruid {
+            if ruid != context.ruid && ruid != context.euid {
+                return Err(Error::new(EPERM));
+            }
+        }
+
+        if let Some(euid) = new_euid {
+            if euid != context.ruid && euid != context.euid && euid != context.suid {
+                return Err(Error::new(EPERM));
+            }
+        }
+    }
+
+    let old_ruid = context.ruid;
+    if let Some(ruid) = new_ruid {
+        context.ruid = ruid;
+    }
+    if let Some(euid) = new_euid {
+        context.euid = euid;
+    }
+    if new_ruid.is_some() || new_euid.map_or(false, |euid| euid != old_ruid) {
+        context.suid = context.euid;
+    }
+
+    Ok(0)
 }
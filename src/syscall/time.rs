@@ -0,0 +1,145 @@
+//! Time related syscalls.
+
+use arch::device::rtc;
+use arch::interrupts;
+use arch::time;
+use context;
+use syscall::data::TimeSpec;
+use syscall::error::*;
+use syscall::flag::{CLOCK_MONOTONIC, CLOCK_REALTIME};
+
+/// Nanoseconds per second
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Read a clock.
+///
+/// ## Returns
+/// The time as seconds and nanoseconds, or `EINVAL` if the clock doesn't exist.
+pub fn clock_time(clock: usize) -> Result<(u64, u64)> {
+    match clock {
+        CLOCK_REALTIME => Ok(time::realtime()),
+        CLOCK_MONOTONIC => Ok(time::monotonic()),
+        _ => Err(Error::new(EINVAL))
+    }
+}
+
+/// Convert a `TimeSpec` to seconds and nanoseconds.
+///
+/// ## Returns
+/// `EINVAL` if the time is negative or the nanoseconds are out of range.
+pub fn from_time_spec(time_spec: &TimeSpec) -> Result<(u64, u64)> {
+    if time_spec.tv_sec < 0 || time_spec.tv_nsec < 0 || time_spec.tv_nsec as u64 >= NANOS_PER_SEC {
+        return Err(Error::new(EINVAL));
+    }
+
+    Ok((time_spec.tv_sec as u64, time_spec.tv_nsec as u64))
+}
+
+/// Convert seconds and nanoseconds to a `TimeSpec`.
+pub fn to_time_spec(time: (u64, u64)) -> TimeSpec {
+    TimeSpec {
+        tv_sec: time.0 as i64,
+        tv_nsec: time.1 as i32
+    }
+}
+
+/// Add two times, as seconds and nanoseconds.
+pub fn add_time(a: (u64, u64), b: (u64, u64)) -> (u64, u64) {
+    let nanos = a.1 + b.1;
+    (a.0 + b.0 + nanos / NANOS_PER_SEC, nanos % NANOS_PER_SEC)
+}
+
+/// Subtract two times, as seconds and nanoseconds.
+///
+/// ## Returns
+/// `a - b`, or zero if `b` is after `a`.
+pub fn sub_time(a: (u64, u64), b: (u64, u64)) -> (u64, u64) {
+    if a <= b {
+        (0, 0)
+    } else if a.1 >= b.1 {
+        (a.0 - b.0, a.1 - b.1)
+    } else {
+        (a.0 - b.0 - 1, a.1 + NANOS_PER_SEC - b.1)
+    }
+}
+
+/// Get the time of a clock.
+///
+/// ## Parameters
+/// - `clock`: `CLOCK_REALTIME` or `CLOCK_MONOTONIC`.
+/// - `time`: where the time is stored.
+pub fn clock_gettime(clock: usize, time: &mut TimeSpec) -> Result<usize> {
+    *time = to_time_spec(clock_time(clock)?);
+    Ok(0)
+}
+
+/// Set the time of a clock, only `CLOCK_REALTIME` can be set, and only by root.
+///
+/// The RTC is updated too, so the time persists across boots. It only keeps seconds.
+pub fn clock_settime(clock: usize, time: &TimeSpec) -> Result<usize> {
+    let euid = {
+        let contexts = context::contexts();
+        let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+        let context = context_lock.read();
+        context.euid
+    };
+
+    if clock != CLOCK_REALTIME {
+        return Err(Error::new(EINVAL));
+    }
+    if euid != 0 {
+        return Err(Error::new(EPERM));
+    }
+
+    let (secs, _) = from_time_spec(time)?;
+    rtc::set_time(secs);
+
+    Ok(0)
+}
+
+/// Block the current context until the monotonic clock reaches `deadline`.
+pub fn sleep_until(deadline: (u64, u64)) -> Result<()> {
+    loop {
+        {
+            let contexts = context::contexts();
+            let context_lock = contexts.current().ok_or(Error::new(ESRCH))?;
+            let mut context = context_lock.write();
+
+            if time::monotonic() >= deadline {
+                context.wake = None;
+                context.status = context::Status::Runnable;
+                return Ok(());
+            }
+
+            // the scheduler makes the context runnable again when the deadline passes
+            context.wake = Some(deadline);
+            context.status = context::Status::Blocked;
+        }
+
+        unsafe {
+            interrupts::disable();
+            if context::switch() {
+                interrupts::enable_and_nop();
+            } else {
+                // there is nothing else to run, wait for the next timer tick here
+                interrupts::enable_and_halt();
+            }
+        }
+    }
+}
+
+/// Suspend the current context for the given time.
+///
+/// ## Parameters
+/// - `req`: time to sleep.
+/// - `rem_opt`: remaining time, always zero since nothing interrupts the sleep.
+pub fn nanosleep(req: &TimeSpec, rem_opt: Option<&mut TimeSpec>) -> Result<usize> {
+    let duration = from_time_spec(req)?;
+    sleep_until(add_time(time::monotonic(), duration))?;
+
+    if let Some(rem) = rem_opt {
+        *rem = TimeSpec::default();
+    }
+
+    Ok(0)
+}
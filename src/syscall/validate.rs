@@ -0,0 +1,40 @@
+//! Validation of the memory that userspace passes to the syscalls.
+
+use core::{mem, slice};
+
+use arch::memory::paging::{ActivePageTable, Page, VirtualAddress};
+use arch::memory::paging::entry::USER_ACCESSIBLE;
+use syscall::error::*;
+
+/// Check that `len` bytes from `address` are mapped on user pages.
+fn validate(address: usize, len: usize) -> Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    // the user address space ends with the stack area
+    let end = address.checked_add(len).ok_or(Error::new(EFAULT))?;
+    if end > ::USER_STACK_OFFSET + ::PML4_SIZE {
+        return Err(Error::new(EFAULT));
+    }
+
+    // the kernel shares the lower half, so each page must be checked
+    let active_table = unsafe { ActivePageTable::new() };
+    let start_page = Page::containing_address(address as VirtualAddress);
+    let end_page = Page::containing_address((end - 1) as VirtualAddress);
+    for page in Page::range_inclusive(start_page, end_page) {
+        match active_table.page_flags(page) {
+            Some(flags) if flags.contains(USER_ACCESSIBLE) => (),
+            _ => return Err(Error::new(EFAULT))
+        }
+    }
+
+    Ok(())
+}
+
+/// Get a slice of user memory, checking that it can be read.
+pub fn validate_slice<T>(ptr: *const T, len: usize) -> Result<&'static [T]> {
+    let size = len.checked_mul(mem::size_of::<T>()).ok_or(Error::new(EFAULT))?;
+    validate(ptr as usize, size)?;
+    Ok(unsafe { slice::from_raw_parts(ptr, len) })
+}